@@ -0,0 +1,108 @@
+//! Threshold rules like `confirmed_per_100k>500`, flagging the nodes that match them
+
+use crate::{Graph, MetricName, MetricValue};
+use anyhow::anyhow;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy)]
+enum Comparison {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+    Equal,
+}
+
+impl Comparison {
+    /// Longest first, so `>=` isn't read as `>`
+    const ALL: &'static [(&'static str, Comparison)] = &[
+        (">=", Comparison::GreaterOrEqual),
+        ("<=", Comparison::LessOrEqual),
+        ("==", Comparison::Equal),
+        (">", Comparison::Greater),
+        ("<", Comparison::Less),
+        ("=", Comparison::Equal),
+    ];
+
+    fn holds(self, value: MetricValue, threshold: MetricValue) -> bool {
+        let ordering = value.total_cmp(&threshold);
+        match self {
+            Comparison::Greater => ordering.is_gt(),
+            Comparison::GreaterOrEqual => ordering.is_ge(),
+            Comparison::Less => ordering.is_lt(),
+            Comparison::LessOrEqual => ordering.is_le(),
+            Comparison::Equal => ordering.is_eq(),
+        }
+    }
+}
+
+/// A metric compared with a threshold, matched by the nodes that have that metric
+#[derive(Debug, Clone)]
+pub struct Alert {
+    /// The rule as given, which is what matching nodes list
+    rule: String,
+    metric: String,
+    comparison: Comparison,
+    threshold: MetricValue,
+}
+
+impl FromStr for Alert {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (at, operator, comparison) = Comparison::ALL
+            .iter()
+            .find_map(|&(operator, comparison)| {
+                s.find(operator).map(|at| (at, operator, comparison))
+            })
+            .ok_or_else(|| anyhow!("Invalid alert {}, expected e.g. confirmed>1000", s))?;
+        let metric = s[..at].trim();
+        let threshold = s[at + operator.len()..].trim();
+        if metric.is_empty() {
+            return Err(anyhow!("Alert {} has no metric", s));
+        }
+        let threshold = match (threshold.parse::<i64>(), threshold.parse::<f64>()) {
+            (Ok(n), _) => MetricValue::Int(n),
+            (_, Ok(x)) => MetricValue::Float(x),
+            _ => {
+                return Err(anyhow!(
+                    "Invalid alert threshold {}, expected a number",
+                    threshold
+                ))
+            }
+        };
+        Ok(Alert {
+            rule: s.chars().filter(|c| !c.is_whitespace()).collect(),
+            metric: metric.to_string(),
+            comparison,
+            threshold,
+        })
+    }
+}
+
+impl Alert {
+    fn matches(&self, metrics: &BTreeMap<MetricName, MetricValue>) -> bool {
+        metrics
+            .get(self.metric.as_str())
+            .is_some_and(|&value| self.comparison.holds(value, self.threshold))
+    }
+}
+
+/// List the rules each node matches in an `alerts` extra field. Returns how many nodes
+/// matched any
+pub fn annotate(graphs: &mut [Graph], alerts: &[Alert]) -> usize {
+    let mut num_alerted = 0;
+    for node in graphs.iter_mut().flat_map(|graph| &mut graph.nodes) {
+        let matched = alerts
+            .iter()
+            .filter(|alert| alert.matches(&node.metrics))
+            .map(|alert| alert.rule.as_str())
+            .collect::<Vec<_>>();
+        if !matched.is_empty() {
+            node.extra_fields.insert("alerts", matched.join(","));
+            num_alerted += 1;
+        }
+    }
+    num_alerted
+}
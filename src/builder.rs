@@ -0,0 +1,217 @@
+//! `GraphBuilder`: the pipeline from entries to graphs, put together step by step instead
+//! of from the command line. `prepare` is the part between grouping the entries and
+//! building the graphs, which the CLI runs too, so both go through the same order
+
+use crate::dedup::Dedup;
+use crate::error::Result;
+use crate::filter::{EntryFilter, Unassigned};
+use crate::granularity::Granularity;
+use crate::graph::{self, Graph, GraphOpt};
+use crate::group::{self, GroupedEntries};
+use crate::join::adjacency::Adjacency;
+use crate::join::cbsa::Crosswalk;
+use crate::join::commuting::Commuting;
+use crate::join::population::{self, Population};
+use crate::lag::Lags;
+use crate::log;
+use crate::source::RawEntry;
+use crate::states::Grouping;
+use crate::timezone::Timezone;
+use crate::transform::{Pipeline, Transform};
+use std::collections::BTreeSet;
+
+/// Merge `opt.population` into the grouped entries, bucket them and `opt.state_metrics` by
+/// `granularity`, then carry the counties forward with `backfill`. Returns the counties
+/// without a population, `None` when there's no population to merge
+pub fn prepare(
+    grouped: &mut GroupedEntries,
+    opt: &mut GraphOpt,
+    granularity: Granularity,
+    backfill: bool,
+) -> Result<Option<BTreeSet<String>>> {
+    let unmatched = opt
+        .population
+        .as_ref()
+        .map(|population| population::merge(grouped, population));
+    if !matches!(granularity, Granularity::Daily) {
+        log::lib_stage("bucket dates", || {
+            *grouped = granularity.bucket(std::mem::take(grouped));
+            opt.state_metrics = granularity.bucket(std::mem::take(&mut opt.state_metrics));
+            tracing::info!(dates = grouped.len());
+            Ok(())
+        })?;
+    }
+    if backfill {
+        log::lib_stage("backfill", || {
+            tracing::info!(entries = group::carry_forward(grouped));
+            Ok(())
+        })?;
+    }
+    Ok(unmatched)
+}
+
+/// Collects entries and the options the CLI would take, `build` turns them into a graph
+/// per date sorted by date
+pub struct GraphBuilder {
+    entries: Vec<RawEntry>,
+    filter: Option<EntryFilter>,
+    strict: bool,
+    dedup: Dedup,
+    timezone: Timezone,
+    lags: Option<Lags>,
+    granularity: Granularity,
+    backfill: bool,
+    opt: GraphOpt,
+    pipeline: Pipeline,
+}
+
+impl Default for GraphBuilder {
+    fn default() -> Self {
+        GraphBuilder {
+            entries: Vec::new(),
+            filter: None,
+            strict: false,
+            dedup: Dedup::Sum,
+            timezone: Timezone::UTC,
+            lags: None,
+            granularity: Granularity::Daily,
+            backfill: false,
+            opt: GraphOpt::default(),
+            pipeline: Pipeline::new(),
+        }
+    }
+}
+
+impl GraphBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_entry(&mut self, entry: RawEntry) -> &mut Self {
+        self.entries.push(entry);
+        self
+    }
+
+    pub fn add_entries(&mut self, entries: impl IntoIterator<Item = RawEntry>) -> &mut Self {
+        self.entries.extend(entries);
+        self
+    }
+
+    /// Dates, states and counties to keep. Everything is kept without one
+    pub fn filter(&mut self, filter: EntryFilter) -> &mut Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Fail with `UnknownEntryType` on entries of types no metric maps to, instead of
+    /// leaving them out
+    pub fn strict(&mut self, strict: bool) -> &mut Self {
+        self.strict = strict;
+        self
+    }
+
+    /// What to do with a county's repeated records of a date, `Sum` them by default
+    pub fn dedup(&mut self, dedup: Dedup) -> &mut Self {
+        self.dedup = dedup;
+        self
+    }
+
+    /// Zone the entries' timestamps are turned into dates in, UTC by default
+    pub fn timezone(&mut self, timezone: Timezone) -> &mut Self {
+        self.timezone = timezone;
+        self
+    }
+
+    pub fn lags(&mut self, lags: Lags) -> &mut Self {
+        self.lags = Some(lags);
+        self
+    }
+
+    pub fn granularity(&mut self, granularity: Granularity) -> &mut Self {
+        self.granularity = granularity;
+        self
+    }
+
+    /// Carry counties' last entries forward onto the dates they didn't report on
+    pub fn backfill(&mut self, backfill: bool) -> &mut Self {
+        self.backfill = backfill;
+        self
+    }
+
+    /// Add `population` extra fields to the counties and states
+    pub fn population(&mut self, population: Population) -> &mut Self {
+        self.opt.population = Some(population);
+        self
+    }
+
+    /// Add `metro` nodes from a CBSA crosswalk
+    pub fn cbsa(&mut self, crosswalk: Crosswalk) -> &mut Self {
+        self.opt.cbsa = Some(crosswalk);
+        self
+    }
+
+    pub fn adjacency(&mut self, adjacency: Adjacency) -> &mut Self {
+        self.opt.adjacency = adjacency;
+        self
+    }
+
+    pub fn commuting(&mut self, commuting: Commuting) -> &mut Self {
+        self.opt.commuting = commuting;
+        self
+    }
+
+    /// Note how the filter handled pseudo-counties on the state nodes
+    pub fn unassigned(&mut self, unassigned: Unassigned) -> &mut Self {
+        self.opt.unassigned = Some(unassigned);
+        self
+    }
+
+    /// Add nodes for a grouping of states. Can be called more than once
+    pub fn hierarchy(&mut self, grouping: Grouping) -> &mut Self {
+        self.opt.hierarchy.push(grouping);
+        self
+    }
+
+    /// Add a `United States` node above the states
+    pub fn national(&mut self, national: bool) -> &mut Self {
+        self.opt.national = national;
+        self
+    }
+
+    /// Run a stage over the built graphs, like a `transform::Stage`. Stages run in the
+    /// order they were added, so a custom metric can use the ones before it
+    pub fn transform(&mut self, transform: impl Transform + 'static) -> &mut Self {
+        self.pipeline.push(transform);
+        self
+    }
+
+    /// Build the graphs from the entries added so far, which are used up. The options
+    /// stay, for building again from more entries
+    pub fn build(&mut self) -> Result<Vec<Graph>> {
+        let default_filter;
+        let filter = match &self.filter {
+            Some(filter) => filter,
+            None => {
+                default_filter = EntryFilter::new(None, None, &[], &[], Unassigned::Keep, false)?;
+                &default_filter
+            }
+        };
+        let entries = std::mem::take(&mut self.entries);
+        let (mut grouped, unmapped) = group::group_by_date(
+            Box::new(entries.into_iter().map(Ok)),
+            filter,
+            self.dedup,
+            self.timezone,
+            self.lags.as_ref(),
+        )?;
+        if self.strict && !unmapped.is_empty() {
+            return Err(unmapped.into_error());
+        }
+        prepare(&mut grouped, &mut self.opt, self.granularity, self.backfill)?;
+
+        let mut graphs = graph::build_graphs(grouped, &self.opt);
+        graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        self.pipeline.run(&mut graphs)?;
+        Ok(graphs)
+    }
+}
@@ -0,0 +1,222 @@
+//! Invariants of the graphs as `build_graphs` makes them, for `--check`: every edge
+//! points at a node of its graph, no node is there twice, and a state's metrics are the
+//! sums of its counties'
+//!
+//! The stages after it break the sums on purpose, like `--top` dropping counties, so the
+//! graphs are checked before them
+
+use crate::{Graph, MetricValue, Node};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+    MissingTarget {
+        timestamp: String,
+        node: String,
+        target: String,
+    },
+    Duplicate {
+        timestamp: String,
+        node: String,
+    },
+    StateSum {
+        timestamp: String,
+        state: String,
+        metric: String,
+        state_value: Option<MetricValue>,
+        county_sum: MetricValue,
+    },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::MissingTarget {
+                timestamp,
+                node,
+                target,
+            } => write!(
+                f,
+                "{}: {} has an edge to {}, which isn't a node",
+                timestamp, node, target
+            ),
+            Violation::Duplicate { timestamp, node } => {
+                write!(f, "{}: {} is there more than once", timestamp, node)
+            }
+            Violation::StateSum {
+                timestamp,
+                state,
+                metric,
+                state_value,
+                county_sum,
+            } => write!(
+                f,
+                "{}: {} has {} {}, its counties {}",
+                timestamp,
+                state,
+                metric,
+                state_value.map_or("none".to_string(), |value| value.to_string()),
+                county_sum
+            ),
+        }
+    }
+}
+
+/// Floats summed in another order can be off in the last places
+fn equal(a: MetricValue, b: MetricValue) -> bool {
+    match (a, b) {
+        (MetricValue::Int(a), MetricValue::Int(b)) => a == b,
+        (a, b) => (a.as_f64() - b.as_f64()).abs() <= 1e-9 * a.as_f64().abs().max(1.0),
+    }
+}
+
+pub fn check(graphs: &[Graph]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    for graph in graphs {
+        let timestamp = &graph.timestamp;
+        let mut nodes = HashMap::new();
+        for node in &graph.nodes {
+            if nodes.insert(node.id.as_str(), node).is_some() {
+                violations.push(Violation::Duplicate {
+                    timestamp: timestamp.clone(),
+                    node: node.id.clone(),
+                });
+            }
+        }
+        for node in &graph.nodes {
+            let mut seen = HashSet::new();
+            for edge in &node.edges {
+                if !nodes.contains_key(edge.target.as_str()) && seen.insert(&edge.target) {
+                    violations.push(Violation::MissingTarget {
+                        timestamp: timestamp.clone(),
+                        node: node.id.clone(),
+                        target: edge.target.clone(),
+                    });
+                }
+            }
+        }
+        for state in graph.nodes.iter().filter(|node| node.level == "state") {
+            // Pseudo-counties counted towards the state only have no county node to sum
+            if state.extra_fields.get("unassigned").map(String::as_str) == Some("state") {
+                continue;
+            }
+            let counties = state
+                .edges
+                .iter()
+                .filter_map(|edge| nodes.get(edge.target.as_str()))
+                .filter(|node| node.level == "county");
+            for (metric, county_sum) in sums(counties) {
+                let state_value = state.metrics.get(metric).copied();
+                if !state_value.is_some_and(|value| equal(value, county_sum)) {
+                    violations.push(Violation::StateSum {
+                        timestamp: timestamp.clone(),
+                        state: state.name.clone(),
+                        metric: metric.to_string(),
+                        state_value,
+                        county_sum,
+                    });
+                }
+            }
+        }
+    }
+    violations
+}
+
+fn sums<'a>(counties: impl Iterator<Item = &'a &'a Node>) -> BTreeMap<&'a str, MetricValue> {
+    let mut sums = BTreeMap::<&str, MetricValue>::new();
+    for county in counties {
+        for (metric, &value) in &county.metrics {
+            let sum = sums.entry(metric.as_ref()).or_default();
+            *sum = *sum + value;
+        }
+    }
+    sums
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::RawEntry;
+    use crate::{Edge, EdgeKind, GraphBuilder};
+
+    fn confirmed(county: &str, fips: &str, values: i64) -> RawEntry {
+        RawEntry {
+            date: 1585699200000,
+            county: county.to_string(),
+            state: "New York".to_string(),
+            values,
+            entry_type: "Confirmed".to_string(),
+            fips: Some(fips.to_string()),
+            zcta: None,
+            date_only: true,
+        }
+    }
+
+    fn graph() -> Graph {
+        let mut graphs = GraphBuilder::new()
+            .add_entry(confirmed("Kings", "36047", 5))
+            .add_entry(confirmed("Queens", "36081", 3))
+            .build()
+            .unwrap();
+        assert_eq!(graphs.len(), 1);
+        graphs.remove(0)
+    }
+
+    fn node<'a>(graph: &'a mut Graph, id: &str) -> &'a mut Node {
+        graph.nodes.iter_mut().find(|node| node.id == id).unwrap()
+    }
+
+    #[test]
+    fn built_graphs_pass() {
+        assert_eq!(check(&[graph()]), Vec::new());
+    }
+
+    #[test]
+    fn dangling_edge_target() {
+        let mut graph = graph();
+        let edge = Edge::new("36061".to_string(), EdgeKind::Adjacent);
+        node(&mut graph, "36047").edges.push(edge);
+        let violations = check(&[graph]);
+        assert_eq!(violations.len(), 1);
+        assert!(matches!(
+            &violations[0],
+            Violation::MissingTarget { node, target, .. } if node == "36047" && target == "36061"
+        ));
+    }
+
+    #[test]
+    fn duplicate_id() {
+        let mut graph = graph();
+        let copy = node(&mut graph, "36081").clone();
+        graph.nodes.push(copy);
+        let violations = check(&[graph]);
+        assert!(violations.iter().any(
+            |violation| matches!(violation, Violation::Duplicate { node, .. } if node == "36081")
+        ));
+    }
+
+    #[test]
+    fn state_sum_mismatch() {
+        let mut graph = graph();
+        node(&mut graph, "36")
+            .metrics
+            .insert("confirmed".into(), 9.into());
+        let violations = check(&[graph]);
+        assert_eq!(violations.len(), 1);
+        match &violations[0] {
+            Violation::StateSum {
+                state,
+                metric,
+                state_value,
+                county_sum,
+                ..
+            } => {
+                assert_eq!((state.as_str(), metric.as_str()), ("New York", "confirmed"));
+                assert_eq!(*state_value, Some(9.into()));
+                assert_eq!(*county_sum, 8.into());
+            }
+            violation => panic!("expected a state sum violation, got {}", violation),
+        }
+    }
+}
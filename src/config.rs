@@ -0,0 +1,547 @@
+//! `--config` files: a `build` invocation's flags kept in a file, as TOML or JSON
+//!
+//! Keys are the long flag names, optionally grouped into tables that are only there to
+//! read better, and `inputs` for the input paths:
+//!
+//! ```toml
+//! inputs = ["dumps/*.json"]
+//! output = "out"
+//!
+//! [filter]
+//! states = ["NY", "NJ"]
+//! from = 2020-03-01
+//!
+//! [metrics]
+//! deltas = true
+//! pct-change = [7, 14]
+//! ```
+//!
+//! `true` gives a flag, `false` leaves it out and an array repeats it. The TOML is the
+//! subset a config needs: tables, strings, numbers, booleans, bare dates and arrays. The
+//! format goes by the extension, `.toml` or `.json`
+
+use anyhow::{anyhow, Context, Result};
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug)]
+pub struct Config {
+    inputs: Vec<String>,
+    flags: Map<String, Value>,
+}
+
+impl Config {
+    /// Whether the config has `flag`, in any table
+    pub fn sets(&self, flag: &str) -> bool {
+        self.flags.contains_key(flag)
+    }
+
+    /// The config as command line arguments, leaving out the flags `given` says the
+    /// command line has, and the inputs when it has some
+    pub fn args(&self, given: impl Fn(&str) -> bool, inputs_given: bool) -> Result<Vec<String>> {
+        let mut args = Vec::new();
+        if !inputs_given {
+            args.extend(self.inputs.iter().cloned());
+        }
+        for (flag, value) in &self.flags {
+            if given(flag) {
+                continue;
+            }
+            match value {
+                Value::Bool(true) => args.push(format!("--{}", flag)),
+                Value::Bool(false) => {}
+                Value::Array(values) => {
+                    for value in values {
+                        args.push(format!("--{}", flag));
+                        args.push(arg(flag, value)?);
+                    }
+                }
+                value => {
+                    args.push(format!("--{}", flag));
+                    args.push(arg(flag, value)?);
+                }
+            }
+        }
+        Ok(args)
+    }
+}
+
+fn arg(flag: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::String(s) => Ok(s.clone()),
+        Value::Number(n) => Ok(n.to_string()),
+        _ => Err(anyhow!(
+            "Invalid value for {}, expected a string or number",
+            flag
+        )),
+    }
+}
+
+pub fn load(path: &Path) -> Result<Config> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config {}", path.display()))?;
+    let root = match path.extension().and_then(|e| e.to_str()) {
+        Some("json") => serde_json::from_str(&text).map_err(anyhow::Error::from),
+        Some("toml") => Parser::new(&text).document(),
+        _ => {
+            return Err(anyhow!(
+                "Config {} isn't a .toml or .json file, the formats configs can be in",
+                path.display()
+            ))
+        }
+    }
+    .with_context(|| format!("Invalid config {}", path.display()))?;
+    let root = match root {
+        Value::Object(root) => root,
+        _ => return Err(anyhow!("Config {} isn't a table", path.display())),
+    };
+
+    let mut config = Config {
+        inputs: Vec::new(),
+        flags: Map::new(),
+    };
+    flatten(root, &mut config)?;
+    Ok(config)
+}
+
+fn flatten(table: Map<String, Value>, config: &mut Config) -> Result<()> {
+    for (key, value) in table {
+        match (key.as_str(), value) {
+            (_, Value::Object(table)) => flatten(table, config)?,
+            ("inputs", Value::String(input)) => config.inputs.push(input),
+            ("inputs", Value::Array(inputs)) => {
+                for input in &inputs {
+                    config.inputs.push(arg("inputs", input)?);
+                }
+            }
+            ("config", _) => return Err(anyhow!("A config can't name another config")),
+            (_, value) => {
+                if config.flags.insert(key.clone(), value).is_some() {
+                    return Err(anyhow!("{} is set more than once", key));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// TOML into the same `Value`s as the JSON configs
+struct Parser<'a> {
+    text: &'a str,
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        Parser {
+            text,
+            pos: 0,
+            line: 1,
+        }
+    }
+
+    fn error(&self, message: &str) -> anyhow::Error {
+        anyhow!("line {}: {}", self.line, message)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.text[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn take_while(&mut self, f: impl Fn(char) -> bool) -> &'a str {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if f(c)) {
+            self.bump();
+        }
+        &self.text[start..self.pos]
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.bump() {
+            Some(c) if c == expected => Ok(()),
+            _ => Err(self.error(&format!("expected `{}`", expected))),
+        }
+    }
+
+    /// Spaces and tabs, and with `newlines` line ends and comments too
+    fn skip(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' => {}
+                '\r' | '\n' if newlines => {}
+                '#' if newlines => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.bump();
+                    }
+                    continue;
+                }
+                _ => break,
+            }
+            self.bump();
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<()> {
+        self.skip(false);
+        match self.peek() {
+            None | Some('\n') | Some('\r') | Some('#') => Ok(()),
+            Some(_) => Err(self.error("expected the end of the line")),
+        }
+    }
+
+    fn document(&mut self) -> Result<Value> {
+        let mut root = Map::new();
+        let mut table: Vec<String> = Vec::new();
+        loop {
+            self.skip(true);
+            match self.peek() {
+                None => return Ok(Value::Object(root)),
+                Some('[') => {
+                    self.bump();
+                    self.skip(false);
+                    table = self.key()?;
+                    self.skip(false);
+                    self.expect(']')?;
+                    self.end_of_line()?;
+                    lookup(&mut root, &table).map_err(|e| self.error(&e))?;
+                }
+                Some(_) => {
+                    let mut key = table.clone();
+                    key.extend(self.key()?);
+                    self.skip(false);
+                    self.expect('=')?;
+                    self.skip(false);
+                    let value = self.value()?;
+                    self.end_of_line()?;
+                    let (last, path) = key.split_last().unwrap();
+                    let parent = lookup(&mut root, path).map_err(|e| self.error(&e))?;
+                    if parent.insert(last.clone(), value).is_some() {
+                        return Err(self.error(&format!("{} is set more than once", last)));
+                    }
+                }
+            }
+        }
+    }
+
+    /// A bare, quoted or dotted key, as its parts
+    fn key(&mut self) -> Result<Vec<String>> {
+        let mut parts = Vec::new();
+        loop {
+            let part = match self.peek() {
+                Some('"') => self.basic_string()?,
+                Some('\'') => self.literal_string()?,
+                _ => {
+                    let part =
+                        self.take_while(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+                    if part.is_empty() {
+                        return Err(self.error("expected a key"));
+                    }
+                    part.to_string()
+                }
+            };
+            parts.push(part);
+            self.skip(false);
+            if self.peek() != Some('.') {
+                return Ok(parts);
+            }
+            self.bump();
+            self.skip(false);
+        }
+    }
+
+    fn value(&mut self) -> Result<Value> {
+        match self.peek() {
+            Some('"') => Ok(Value::String(self.basic_string()?)),
+            Some('\'') => Ok(Value::String(self.literal_string()?)),
+            Some('[') => self.array(),
+            Some('{') => Err(self.error("inline tables aren't supported, use a [table]")),
+            _ => self.scalar(),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut s = String::new();
+        loop {
+            match self.bump() {
+                None | Some('\n') => return Err(self.error("unterminated string")),
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let escaped = match self.bump() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('u') => {
+                            let hex = self.text.get(self.pos..self.pos + 4).unwrap_or_default();
+                            let c = u32::from_str_radix(hex, 16)
+                                .ok()
+                                .and_then(char::from_u32)
+                                .ok_or_else(|| self.error("invalid \\u escape"))?;
+                            self.pos += 4;
+                            c
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    };
+                    s.push(escaped);
+                }
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String> {
+        self.expect('\'')?;
+        let start = self.pos;
+        loop {
+            match self.bump() {
+                None | Some('\n') => return Err(self.error("unterminated string")),
+                Some('\'') => return Ok(self.text[start..self.pos - 1].to_string()),
+                Some(_) => {}
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        loop {
+            self.skip(true);
+            match self.peek() {
+                None => return Err(self.error("unterminated array")),
+                Some(']') => {
+                    self.bump();
+                    return Ok(Value::Array(values));
+                }
+                Some(_) => {}
+            }
+            values.push(self.value()?);
+            self.skip(true);
+            match self.bump() {
+                Some(',') => {}
+                Some(']') => return Ok(Value::Array(values)),
+                None => return Err(self.error("unterminated array")),
+                _ => return Err(self.error("expected `,` or `]` in array")),
+            }
+        }
+    }
+
+    /// Booleans and numbers, and dates and times kept as their text
+    fn scalar(&mut self) -> Result<Value> {
+        let token = self.take_while(|c| !matches!(c, ' ' | '\t' | '\r' | '\n' | ',' | ']' | '#'));
+        let number = token.replace('_', "");
+        if token == "true" || token == "false" {
+            Ok(Value::Bool(token == "true"))
+        } else if let Ok(n) = number.parse::<i64>() {
+            Ok(n.into())
+        } else if let Some(n) = number
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+        {
+            Ok(Value::Number(n))
+        } else if token.starts_with(|c: char| c.is_ascii_digit()) && token.contains(['-', ':']) {
+            Ok(Value::String(token.to_string()))
+        } else {
+            Err(self.error(&format!("invalid value {:?}", token)))
+        }
+    }
+}
+
+/// The table at `path`, made if it isn't there yet
+fn lookup<'m>(
+    root: &'m mut Map<String, Value>,
+    path: &[String],
+) -> std::result::Result<&'m mut Map<String, Value>, String> {
+    let mut table = root;
+    for key in path {
+        table = match table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Object(Map::new()))
+        {
+            Value::Object(t) => t,
+            _ => return Err(format!("{} is a value, not a table", key)),
+        };
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn parse(text: &str) -> Result<Value> {
+        Parser::new(text).document()
+    }
+
+    fn config(text: &str) -> Result<Config> {
+        let root = match parse(text)? {
+            Value::Object(root) => root,
+            _ => unreachable!(),
+        };
+        let mut config = Config {
+            inputs: Vec::new(),
+            flags: Map::new(),
+        };
+        flatten(root, &mut config)?;
+        Ok(config)
+    }
+
+    fn error(text: &str) -> String {
+        parse(text).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn values() {
+        let text = r#"
+            # comment
+            string = "a \"b\"\t\u00e9"  # trailing comment
+            literal = 'C:\dumps'
+            int = 1_000
+            negative = -5
+            float = 0.5
+            yes = true
+            no = false
+            date = 2020-03-01
+            array = [1, "two",
+                3,]
+            empty = []
+        "#;
+        assert_eq!(
+            parse(text).unwrap(),
+            json!({
+                "string": "a \"b\"\té",
+                "literal": "C:\\dumps",
+                "int": 1000,
+                "negative": -5,
+                "float": 0.5,
+                "yes": true,
+                "no": false,
+                "date": "2020-03-01",
+                "array": [1, "two", 3],
+                "empty": [],
+            })
+        );
+    }
+
+    #[test]
+    fn tables_and_dotted_keys() {
+        let text = "
+            top = 1
+            [filter]
+            states = [\"NY\"]
+            [metrics.derived]
+            deltas = true
+            [output]
+            format.name = \"csv\"
+            \"quoted key\" = 2
+        ";
+        assert_eq!(
+            parse(text).unwrap(),
+            json!({
+                "top": 1,
+                "filter": {"states": ["NY"]},
+                "metrics": {"derived": {"deltas": true}},
+                "output": {"format": {"name": "csv"}, "quoted key": 2},
+            })
+        );
+    }
+
+    #[test]
+    fn unsupported_and_invalid() {
+        assert_eq!(
+            error("a = {b = 1}"),
+            "line 1: inline tables aren't supported, use a [table]"
+        );
+        assert_eq!(error("[[inputs]]"), "line 1: expected a key");
+        assert_eq!(
+            error("a = \"\"\"multi\nline\"\"\""),
+            "line 1: expected the end of the line"
+        );
+        assert_eq!(error("a = \"open"), "line 1: unterminated string");
+        assert_eq!(error("a = 'open\n'"), "line 2: unterminated string");
+        assert_eq!(error("a = \"\\q\""), "line 1: invalid escape");
+        assert_eq!(error("a = [1, 2"), "line 1: unterminated array");
+        assert_eq!(error("a = [1 2]"), "line 1: expected `,` or `]` in array");
+        assert_eq!(error("a = yes"), "line 1: invalid value \"yes\"");
+        assert_eq!(error("a 1"), "line 1: expected `=`");
+        assert_eq!(error("a = 1 b"), "line 1: expected the end of the line");
+        assert_eq!(error("a = 1\na = 2"), "line 2: a is set more than once");
+        assert_eq!(error("a = 1\n[a]"), "line 2: a is a value, not a table");
+    }
+
+    #[test]
+    fn flatten_tables_into_flags() {
+        let config = config(
+            "inputs = [\"a.csv\", \"b.csv\"]\n[metrics]\ndeltas = true\npct-change = [7, 14]",
+        )
+        .unwrap();
+        assert_eq!(config.inputs, ["a.csv", "b.csv"]);
+        assert!(config.sets("deltas"));
+        assert!(!config.sets("metrics"));
+    }
+
+    #[test]
+    fn flatten_errors() {
+        assert_eq!(
+            config("states = [\"NY\"]\n[filter]\nstates = [\"NJ\"]")
+                .unwrap_err()
+                .to_string(),
+            "states is set more than once"
+        );
+        assert_eq!(
+            config("config = \"other.toml\"").unwrap_err().to_string(),
+            "A config can't name another config"
+        );
+    }
+
+    #[test]
+    fn args() {
+        let config = config(
+            "inputs = \"a.csv\"\nfrom = 2020-03-01\ndeltas = true\nbackfill = false\n\
+             pct-change = [7, 14]\ntop = 5",
+        )
+        .unwrap();
+        assert_eq!(
+            config.args(|_| false, false).unwrap(),
+            [
+                "a.csv",
+                "--deltas",
+                "--from",
+                "2020-03-01",
+                "--pct-change",
+                "7",
+                "--pct-change",
+                "14",
+                "--top",
+                "5"
+            ]
+        );
+        assert_eq!(
+            config.args(|flag| flag != "top", true).unwrap(),
+            ["--top", "5"]
+        );
+    }
+
+    #[test]
+    fn args_reject_nested_arrays() {
+        let config = config("states = [[\"NY\"]]").unwrap();
+        assert_eq!(
+            config.args(|_| false, false).unwrap_err().to_string(),
+            "Invalid value for states, expected a string or number"
+        );
+    }
+}
@@ -0,0 +1,85 @@
+//! County names the feeds and Census files spell differently, mapped to one spelling so
+//! they group and join into one node
+
+use crate::GroupedEntries;
+use std::collections::BTreeSet;
+
+/// State, variant spelling, the Census spelling and its FIPS code. Counties the feeds
+/// combine, like Dukes and Nantucket, have no code of their own
+const VARIANTS: &[(&str, &str, &str, Option<&str>)] = &[
+    ("Alabama", "De Kalb", "DeKalb", Some("01049")),
+    ("Alaska", "Wade Hampton", "Kusilvak", Some("02158")),
+    (
+        "District of Columbia",
+        "Washington",
+        "District of Columbia",
+        Some("11001"),
+    ),
+    ("Florida", "De Soto", "DeSoto", Some("12027")),
+    ("Georgia", "De Kalb", "DeKalb", Some("13089")),
+    ("Illinois", "De Kalb", "DeKalb", Some("17037")),
+    ("Illinois", "Du Page", "DuPage", Some("17043")),
+    ("Illinois", "La Salle", "LaSalle", Some("17099")),
+    ("Indiana", "De Kalb", "DeKalb", Some("18033")),
+    ("Indiana", "La Grange", "LaGrange", Some("18087")),
+    ("Indiana", "La Porte", "LaPorte", Some("18091")),
+    ("Louisiana", "DeSoto", "De Soto", Some("22031")),
+    ("Louisiana", "LaSalle", "La Salle", Some("22059")),
+    (
+        "Massachusetts",
+        "Dukes & Nantucket",
+        "Dukes and Nantucket",
+        None,
+    ),
+    (
+        "Massachusetts",
+        "Nantucket and Dukes",
+        "Dukes and Nantucket",
+        None,
+    ),
+    ("Minnesota", "Saint Louis", "St. Louis", Some("27137")),
+    ("Mississippi", "De Soto", "DeSoto", Some("28033")),
+    ("Missouri", "De Kalb", "DeKalb", Some("29063")),
+    ("Missouri", "Saint Louis", "St. Louis", Some("29189")),
+    ("New Mexico", "Dona Ana", "Doña Ana", Some("35013")),
+    ("South Dakota", "Shannon", "Oglala Lakota", Some("46102")),
+    ("Tennessee", "De Kalb", "DeKalb", Some("47041")),
+    ("Texas", "LaSalle", "La Salle", Some("48283")),
+    ("Virginia", "Bedford City", "Bedford", Some("51019")),
+];
+
+/// The Census spelling of a county and its FIPS code, when the name is a known variant
+pub fn canonical(state: &str, county: &str) -> Option<(&'static str, Option<&'static str>)> {
+    let county = county.to_lowercase();
+    VARIANTS
+        .iter()
+        .find(|(s, variant, _, _)| *s == state && variant.to_lowercase() == county)
+        .map(|&(_, _, name, fips)| (name, fips))
+}
+
+/// The Census spelling of a county, or the name as given
+pub fn canonical_name<'a>(state: &str, county: &'a str) -> &'a str {
+    match canonical(state, county) {
+        Some((name, _)) => name,
+        None => county,
+    }
+}
+
+/// Counties that are still without a FIPS code after normalizing, which joins by code
+/// can't find, as their keys
+pub fn unmatched(grouped: &GroupedEntries) -> BTreeSet<String> {
+    grouped
+        .values()
+        .flatten()
+        .filter(|(_, county_entry)| {
+            county_entry.fips.is_none()
+                && county_entry.zcta.is_none()
+                && !county_entry.name.is_empty()
+                && !crate::filter::is_pseudo_county(&county_entry.name)
+                && !VARIANTS.iter().any(|&(state, _, name, _)| {
+                    state == county_entry.state && name == county_entry.name
+                })
+        })
+        .map(|(key, _)| key.clone())
+        .collect()
+}
@@ -0,0 +1,197 @@
+//! What `--dedup` does when the same date, county and entry type comes up more than once,
+//! as it does when overlapping inputs are given together
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dedup {
+    /// Add the values up, which is what the feed's split records need
+    Sum,
+    /// Keep the value that came last in the inputs
+    Last,
+    Max,
+    Error,
+}
+
+impl FromStr for Dedup {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sum" => Ok(Dedup::Sum),
+            "last" => Ok(Dedup::Last),
+            "max" => Ok(Dedup::Max),
+            "error" => Ok(Dedup::Error),
+            _ => Err(anyhow!(
+                "Unknown dedup policy {}, expected sum, last, max or error",
+                s
+            )),
+        }
+    }
+}
+
+/// A record's date, the key of the entry it goes into, its county as the input named it
+/// and its metric. Counties merged into one entry, like the NYC boroughs, stay apart
+pub type RecordKey = (DateTime<Utc>, String, String, &'static str);
+
+/// One value per record under the policy, with the position in the input it came from
+#[derive(Default)]
+pub struct Records {
+    values: HashMap<RecordKey, (usize, i64)>,
+}
+
+impl Records {
+    pub fn insert(
+        &mut self,
+        policy: Dedup,
+        key: RecordKey,
+        (position, value): (usize, i64),
+    ) -> Result<()> {
+        let kept = match self.values.get(&key) {
+            None => (position, value),
+            Some(&(kept_position, kept_value)) => match policy {
+                Dedup::Sum => (position.max(kept_position), kept_value + value),
+                Dedup::Last if position > kept_position => (position, value),
+                Dedup::Last => (kept_position, kept_value),
+                Dedup::Max => (position.max(kept_position), value.max(kept_value)),
+                Dedup::Error => {
+                    let (date, entry, county, metric) = key;
+                    return Err(anyhow!(
+                        "Duplicate {} for {} ({}) on {}: {} and {}",
+                        metric,
+                        entry,
+                        county,
+                        date.date_naive(),
+                        kept_value,
+                        value
+                    ));
+                }
+            },
+        };
+        self.values.insert(key, kept);
+        Ok(())
+    }
+
+    pub fn merge(&mut self, policy: Dedup, other: Records) -> Result<()> {
+        for (key, value) in other.values {
+            self.insert(policy, key, value)?;
+        }
+        Ok(())
+    }
+
+    /// Take out the records of one date, leaving the others
+    pub fn take_date(&mut self, date: DateTime<Utc>) -> Records {
+        let (values, kept) = std::mem::take(&mut self.values)
+            .into_iter()
+            .partition(|((record_date, ..), _)| *record_date == date);
+        self.values = kept;
+        Records { values }
+    }
+
+    /// The values to add to each entry, by date and entry key
+    pub fn into_values(self) -> impl Iterator<Item = (DateTime<Utc>, String, &'static str, i64)> {
+        self.values
+            .into_iter()
+            .map(|((date, key, _, metric), (_, value))| (date, key, metric, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn key(day: u32, metric: &'static str) -> RecordKey {
+        let date = Utc.with_ymd_and_hms(2020, 4, day, 0, 0, 0).unwrap();
+        let entry = "New York - Kings".to_string();
+        (date, entry, "Kings".to_string(), metric)
+    }
+
+    /// The values kept of `records`, given as (position, value) of a county's confirmed
+    /// cases on one date, inserted in that order
+    fn kept(policy: Dedup, records: &[(usize, i64)]) -> Result<Vec<i64>> {
+        let mut kept = Records::default();
+        for &record in records {
+            kept.insert(policy, key(1, "confirmed"), record)?;
+        }
+        Ok(kept.into_values().map(|(.., value)| value).collect())
+    }
+
+    #[test]
+    fn sum_adds_up() {
+        assert_eq!(
+            kept(Dedup::Sum, &[(0, 3), (1, 4), (2, 5)]).unwrap(),
+            vec![12]
+        );
+    }
+
+    #[test]
+    fn last_keeps_the_latest_in_the_inputs() {
+        assert_eq!(
+            kept(Dedup::Last, &[(0, 3), (2, 5), (1, 4)]).unwrap(),
+            vec![5]
+        );
+    }
+
+    #[test]
+    fn max_keeps_the_highest() {
+        assert_eq!(
+            kept(Dedup::Max, &[(0, 3), (1, 7), (2, 5)]).unwrap(),
+            vec![7]
+        );
+    }
+
+    #[test]
+    fn error_fails_on_a_repeat() {
+        assert_eq!(kept(Dedup::Error, &[(0, 3)]).unwrap(), vec![3]);
+        let err = kept(Dedup::Error, &[(0, 3), (1, 4)]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Duplicate confirmed for New York - Kings (Kings) on 2020-04-01: 3 and 4"
+        );
+    }
+
+    #[test]
+    fn other_dates_and_metrics_are_apart() {
+        let mut records = Records::default();
+        records
+            .insert(Dedup::Error, key(1, "confirmed"), (0, 3))
+            .unwrap();
+        records
+            .insert(Dedup::Error, key(1, "deaths"), (1, 1))
+            .unwrap();
+        records
+            .insert(Dedup::Error, key(2, "confirmed"), (2, 4))
+            .unwrap();
+        let first = records.take_date(key(1, "").0);
+        let mut values = first
+            .into_values()
+            .map(|(.., value)| value)
+            .collect::<Vec<_>>();
+        values.sort();
+        assert_eq!(values, vec![1, 3]);
+        assert_eq!(records.into_values().count(), 1);
+    }
+
+    #[test]
+    fn merging_keeps_the_input_order() {
+        // Parts grouped in parallel come back in any order, `last` still goes by position
+        let mut later = Records::default();
+        later
+            .insert(Dedup::Last, key(1, "confirmed"), (5, 9))
+            .unwrap();
+        let mut earlier = Records::default();
+        earlier
+            .insert(Dedup::Last, key(1, "confirmed"), (2, 4))
+            .unwrap();
+        later.merge(Dedup::Last, earlier).unwrap();
+        let values = later
+            .into_values()
+            .map(|(.., value)| value)
+            .collect::<Vec<_>>();
+        assert_eq!(values, vec![9]);
+    }
+}
@@ -0,0 +1,300 @@
+//! `diff` subcommand: what changed between two runs' JSON output, or between two dates
+//! of one run
+
+use crate::log;
+use crate::source::{dir_files, open_input};
+use crate::MetricValue;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, NaiveDate};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct DiffOpt {
+    /// Output directory, or single `graphs.json` / `graphs.ndjson` file, of the earlier run
+    #[structopt(parse(from_os_str))]
+    old: PathBuf,
+
+    /// Output of the later run. Without it, `--from` and `--to` are compared within `old`
+    #[structopt(parse(from_os_str))]
+    new: Option<PathBuf>,
+
+    /// Compare the graph of this date (YYYY-MM-DD) in the old output...
+    #[structopt(long, requires = "to")]
+    from: Option<NaiveDate>,
+
+    /// ...with the graph of this date in the new one
+    #[structopt(long, requires = "from")]
+    to: Option<NaiveDate>,
+
+    /// Print the differences as JSON instead of a listing
+    #[structopt(long)]
+    json: bool,
+}
+
+#[derive(Deserialize)]
+struct Graph {
+    timestamp: String,
+    nodes: Vec<Node>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    name: String,
+    #[serde(default)]
+    metrics: BTreeMap<String, MetricValue>,
+}
+
+/// Graphs of an output by timestamp
+type Graphs = BTreeMap<String, BTreeMap<String, BTreeMap<String, MetricValue>>>;
+
+/// Parse one output file, a graph, an array of graphs or a graph per line. `None` if
+/// it holds something else, like a manifest or report next to the graphs
+fn parse<G: DeserializeOwned>(data: &[u8]) -> Option<Vec<G>> {
+    if let Ok(graph) = serde_json::from_slice::<G>(data) {
+        return Some(vec![graph]);
+    }
+    if let Ok(graphs) = serde_json::from_slice::<Vec<G>>(data) {
+        return Some(graphs);
+    }
+    serde_json::Deserializer::from_slice(data)
+        .into_iter::<G>()
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .filter(|graphs| !graphs.is_empty())
+}
+
+/// The graphs of a JSON output, a directory of them or a single file, in the order read
+pub fn read_graphs<G: DeserializeOwned>(path: &Path) -> Result<Vec<G>> {
+    let files = if path.is_dir() {
+        dir_files(path)?
+    } else {
+        vec![path.to_path_buf()]
+    };
+    let mut graphs = Vec::new();
+    for file in files {
+        let mut data = Vec::new();
+        open_input(&file)?
+            .read_to_end(&mut data)
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        graphs.extend(parse(&data).into_iter().flatten());
+    }
+    if graphs.is_empty() {
+        return Err(anyhow!("No JSON graphs in {}", path.display()));
+    }
+    Ok(graphs)
+}
+
+fn load(path: &Path) -> Result<Graphs> {
+    Ok(read_graphs::<Graph>(path)?
+        .into_iter()
+        .map(|graph| {
+            let nodes = graph
+                .nodes
+                .into_iter()
+                .map(|node| (node.name, node.metrics))
+                .collect();
+            (graph.timestamp, nodes)
+        })
+        .collect())
+}
+
+/// Timestamp of the graph for a date
+fn find(graphs: &Graphs, date: NaiveDate, path: &Path) -> Result<String> {
+    graphs
+        .keys()
+        .find(|timestamp| {
+            DateTime::parse_from_rfc3339(timestamp)
+                .map(|t| t.date_naive() == date)
+                .unwrap_or(false)
+        })
+        .cloned()
+        .ok_or_else(|| anyhow!("No graph for {} in {}", date, path.display()))
+}
+
+#[derive(Serialize)]
+struct MetricChange {
+    old: Option<MetricValue>,
+    new: Option<MetricValue>,
+}
+
+#[derive(Serialize)]
+struct NodeChange {
+    name: String,
+    metrics: BTreeMap<String, MetricChange>,
+}
+
+#[derive(Serialize)]
+struct GraphDiff {
+    old: String,
+    new: String,
+    added: Vec<String>,
+    removed: Vec<String>,
+    changed: Vec<NodeChange>,
+}
+
+#[derive(Serialize, Default)]
+pub struct Report {
+    /// Dates only the new output has
+    dates_added: Vec<String>,
+    /// Dates only the old output has
+    dates_removed: Vec<String>,
+    /// Graphs with differences, by the timestamps compared
+    graphs: Vec<GraphDiff>,
+}
+
+impl Report {
+    /// The outputs compared are the same
+    pub fn is_empty(&self) -> bool {
+        self.dates_added.is_empty() && self.dates_removed.is_empty() && self.graphs.is_empty()
+    }
+}
+
+fn diff_graphs(
+    (old, old_nodes): (&str, &BTreeMap<String, BTreeMap<String, MetricValue>>),
+    (new, new_nodes): (&str, &BTreeMap<String, BTreeMap<String, MetricValue>>),
+) -> Option<GraphDiff> {
+    let added = new_nodes
+        .keys()
+        .filter(|name| !old_nodes.contains_key(*name))
+        .cloned()
+        .collect::<Vec<_>>();
+    let removed = old_nodes
+        .keys()
+        .filter(|name| !new_nodes.contains_key(*name))
+        .cloned()
+        .collect::<Vec<_>>();
+    let changed = old_nodes
+        .iter()
+        .filter_map(|(name, old_metrics)| {
+            let new_metrics = new_nodes.get(name)?;
+            let metrics = old_metrics
+                .keys()
+                .chain(new_metrics.keys())
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .filter_map(|metric| {
+                    let change = MetricChange {
+                        old: old_metrics.get(metric).copied(),
+                        new: new_metrics.get(metric).copied(),
+                    };
+                    (change.old != change.new).then(|| (metric.clone(), change))
+                })
+                .collect::<BTreeMap<_, _>>();
+            (!metrics.is_empty()).then(|| NodeChange {
+                name: name.clone(),
+                metrics,
+            })
+        })
+        .collect::<Vec<_>>();
+
+    if added.is_empty() && removed.is_empty() && changed.is_empty() {
+        return None;
+    }
+    Some(GraphDiff {
+        old: old.to_string(),
+        new: new.to_string(),
+        added,
+        removed,
+        changed,
+    })
+}
+
+fn print(report: &Report) {
+    let value =
+        |v: Option<MetricValue>| v.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string());
+    for date in &report.dates_added {
+        println!("+ {}", date);
+    }
+    for date in &report.dates_removed {
+        println!("- {}", date);
+    }
+    for graph in &report.graphs {
+        if graph.old == graph.new {
+            println!("{}", graph.old);
+        } else {
+            println!("{} -> {}", graph.old, graph.new);
+        }
+        for name in &graph.added {
+            println!("  + {}", name);
+        }
+        for name in &graph.removed {
+            println!("  - {}", name);
+        }
+        for node in &graph.changed {
+            let metrics = node
+                .metrics
+                .iter()
+                .map(|(metric, change)| {
+                    format!("{} {} -> {}", metric, value(change.old), value(change.new))
+                })
+                .collect::<Vec<_>>();
+            println!("  ~ {}: {}", node.name, metrics.join(", "));
+        }
+    }
+}
+
+/// Print the differences and return them
+pub fn run(opt: DiffOpt) -> Result<Report> {
+    let DiffOpt {
+        old,
+        new,
+        from,
+        to,
+        json,
+    } = opt;
+    if new.is_none() && from.is_none() {
+        return Err(anyhow!(
+            "Comparing dates within one output needs --from and --to"
+        ));
+    }
+
+    let report = log::stage("diff", || {
+        let old_graphs = load(&old)?;
+        let new_path = new.as_deref().unwrap_or(&old);
+        let new_graphs = match &new {
+            Some(new) => load(new)?,
+            None => old_graphs.clone(),
+        };
+
+        let mut report = Report::default();
+        match from.zip(to) {
+            Some((from, to)) => {
+                let from = find(&old_graphs, from, &old)?;
+                let to = find(&new_graphs, to, new_path)?;
+                report.graphs.extend(diff_graphs(
+                    (&from, &old_graphs[&from]),
+                    (&to, &new_graphs[&to]),
+                ));
+            }
+            None => {
+                for (timestamp, nodes) in &old_graphs {
+                    match new_graphs.get(timestamp) {
+                        Some(new_nodes) => report
+                            .graphs
+                            .extend(diff_graphs((timestamp, nodes), (timestamp, new_nodes))),
+                        None => report.dates_removed.push(timestamp.clone()),
+                    }
+                }
+                report.dates_added = new_graphs
+                    .keys()
+                    .filter(|timestamp| !old_graphs.contains_key(*timestamp))
+                    .cloned()
+                    .collect();
+            }
+        }
+        tracing::info!(graphs_changed = report.graphs.len());
+        Ok(report)
+    })?;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print(&report);
+    }
+    Ok(report)
+}
@@ -0,0 +1,112 @@
+//! `CovidDataError`: what the library's entry points fail with, for embedders to match on
+//!
+//! The stages themselves use `anyhow`, an error gets its variant from its causes on its
+//! way out, or is made as one where it starts, like a join file missing a column or a
+//! file that couldn't be written
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io;
+use thiserror::Error;
+
+pub type Result<T, E = CovidDataError> = std::result::Result<T, E>;
+
+#[derive(Debug, Error)]
+pub enum CovidDataError {
+    /// A record, or a whole input, that isn't in the format it should be
+    #[error("{0}")]
+    Parse(String),
+    /// Entries of types no metric maps to, with `GraphBuilder::strict` or `--strict`
+    #[error(
+        "{} entries have unrecognized types ({}). Samples:\n  {}",
+        .counts.values().sum::<usize>(),
+        .counts.iter().map(|(t, n)| format!("{}: {}", t, n)).collect::<Vec<_>>().join(", "),
+        .samples.join("\n  ")
+    )]
+    UnknownEntryType {
+        counts: BTreeMap<String, usize>,
+        samples: Vec<String>,
+    },
+    /// A join file without a column it's joined on or read from
+    #[error("{file} has no {column} column")]
+    JoinMiss { file: String, column: String },
+    /// Reading an input, or a join file, failed
+    #[error("{message}")]
+    Io {
+        message: String,
+        kind: io::ErrorKind,
+    },
+    /// Writing the output failed
+    #[error("{message}")]
+    Output {
+        message: String,
+        kind: io::ErrorKind,
+    },
+    /// Anything else, like an invalid option
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+impl CovidDataError {
+    pub(crate) fn join_miss(file: impl Into<String>, column: &str) -> Self {
+        CovidDataError::JoinMiss {
+            file: file.into(),
+            column: column.to_string(),
+        }
+    }
+
+    /// A failed write of the output, `message` saying what was being written
+    pub(crate) fn output(message: impl fmt::Display, err: io::Error) -> Self {
+        CovidDataError::Output {
+            message: format!("{}: {}", message, err),
+            kind: err.kind(),
+        }
+    }
+}
+
+/// Whether an error is one of the parsers' the sources and joins use
+fn is_parse(cause: &(dyn std::error::Error + 'static)) -> bool {
+    let parse = cause.is::<csv::Error>()
+        || cause.is::<serde_json::Error>()
+        || cause.is::<rmp_serde::decode::Error>()
+        || cause.is::<calamine::Error>()
+        || cause.is::<chrono::ParseError>()
+        || cause.is::<std::num::ParseIntError>()
+        || cause.is::<std::num::ParseFloatError>();
+    #[cfg(feature = "simd-json")]
+    let parse = parse || cause.is::<simd_json::Error>();
+    parse
+}
+
+impl From<anyhow::Error> for CovidDataError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<CovidDataError>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        let message = format!("{:#}", err);
+        if let Some(kind) = err
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<io::Error>())
+            .map(io::Error::kind)
+        {
+            CovidDataError::Io { message, kind }
+        } else if err.chain().any(is_parse) {
+            CovidDataError::Parse(message)
+        } else {
+            CovidDataError::Other(err)
+        }
+    }
+}
+
+impl From<csv::Error> for CovidDataError {
+    fn from(err: csv::Error) -> Self {
+        anyhow::Error::from(err).into()
+    }
+}
+
+impl From<chrono::ParseError> for CovidDataError {
+    fn from(err: chrono::ParseError) -> Self {
+        CovidDataError::Parse(err.to_string())
+    }
+}
@@ -0,0 +1,358 @@
+//! `--metric` definitions: integer arithmetic over a node's other metrics, like
+//! `cfr=deaths*10000/confirmed`
+//!
+//! Expressions have `+`, `-`, `*`, `/`, `%`, parentheses, integer literals and metric
+//! names. Division rounds towards zero, unless a float metric like `confirmed_per_100k`
+//! is involved, which makes the result a float too. A node missing one of the metrics, or
+//! where the result divides by zero or overflows, doesn't get the metric.
+
+use crate::{Graph, MetricName, MetricValue};
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl Operator {
+    fn apply(self, a: MetricValue, b: MetricValue) -> Option<MetricValue> {
+        let (a, b) = match (a, b) {
+            (MetricValue::Int(a), MetricValue::Int(b)) => {
+                return match self {
+                    Operator::Add => a.checked_add(b),
+                    Operator::Subtract => a.checked_sub(b),
+                    Operator::Multiply => a.checked_mul(b),
+                    Operator::Divide => a.checked_div(b),
+                    Operator::Remainder => a.checked_rem(b),
+                }
+                .map(MetricValue::Int)
+            }
+            (a, b) => (a.as_f64(), b.as_f64()),
+        };
+        let value = match self {
+            Operator::Add => a + b,
+            Operator::Subtract => a - b,
+            Operator::Multiply => a * b,
+            Operator::Divide => a / b,
+            Operator::Remainder => a % b,
+        };
+        value.is_finite().then_some(MetricValue::Float(value))
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(i64),
+    Metric(String),
+    Negate(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, metrics: &BTreeMap<MetricName, MetricValue>) -> Option<MetricValue> {
+        match self {
+            Expr::Number(n) => Some(MetricValue::Int(*n)),
+            Expr::Metric(name) => metrics.get(name.as_str()).copied(),
+            Expr::Negate(e) => match e.eval(metrics)? {
+                MetricValue::Int(n) => n.checked_neg().map(MetricValue::Int),
+                MetricValue::Float(x) => Some(MetricValue::Float(-x)),
+            },
+            Expr::Binary(op, a, b) => op.apply(a.eval(metrics)?, b.eval(metrics)?),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Operator(Operator),
+    Open,
+    Close,
+}
+
+impl Token {
+    /// The token as written, for error messages
+    fn describe(&self) -> String {
+        match self {
+            Token::Number(n) => n.to_string(),
+            Token::Name(name) => name.clone(),
+            Token::Operator(op) => match op {
+                Operator::Add => "+",
+                Operator::Subtract => "-",
+                Operator::Multiply => "*",
+                Operator::Divide => "/",
+                Operator::Remainder => "%",
+            }
+            .to_string(),
+            Token::Open => "(".to_string(),
+            Token::Close => ")".to_string(),
+        }
+    }
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        let token = match c {
+            c if c.is_whitespace() => {
+                chars.next();
+                continue;
+            }
+            '+' => Token::Operator(Operator::Add),
+            '-' => Token::Operator(Operator::Subtract),
+            '*' => Token::Operator(Operator::Multiply),
+            '/' => Token::Operator(Operator::Divide),
+            '%' => Token::Operator(Operator::Remainder),
+            '(' => Token::Open,
+            ')' => Token::Close,
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut end = start;
+                while let Some(&(i, c)) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                let word = &s[start..end];
+                tokens.push(if word.starts_with(|c: char| c.is_ascii_digit()) {
+                    Token::Number(
+                        word.parse()
+                            .map_err(|_| anyhow!("Invalid number {}", word))?,
+                    )
+                } else {
+                    Token::Name(word.to_string())
+                });
+                continue;
+            }
+            c => return Err(anyhow!("Unexpected {}", c)),
+        };
+        tokens.push(token);
+        chars.next();
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens, `*`, `/` and `%` binding tighter than `+` and `-`
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn binary(
+        &mut self,
+        operators: &[Operator],
+        operand: fn(&mut Self) -> Result<Expr>,
+    ) -> Result<Expr> {
+        let mut expr = operand(self)?;
+        while let Some(&Token::Operator(op)) = self.peek() {
+            if !operators.contains(&op) {
+                break;
+            }
+            self.next();
+            expr = Expr::Binary(op, Box::new(expr), Box::new(operand(self)?));
+        }
+        Ok(expr)
+    }
+
+    fn sum(&mut self) -> Result<Expr> {
+        self.binary(&[Operator::Add, Operator::Subtract], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expr> {
+        self.binary(
+            &[Operator::Multiply, Operator::Divide, Operator::Remainder],
+            Self::unary,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Name(name)) => Ok(Expr::Metric(name)),
+            Some(Token::Operator(Operator::Subtract)) => Ok(Expr::Negate(Box::new(self.unary()?))),
+            Some(Token::Open) => {
+                let expr = self.sum()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err(anyhow!("Missing )")),
+                }
+            }
+            Some(token) => Err(anyhow!("Unexpected {}", token.describe())),
+            None => Err(anyhow!("Unexpected end of expression")),
+        }
+    }
+}
+
+/// A `NAME=EXPR` metric definition
+#[derive(Debug, Clone)]
+pub struct MetricDef {
+    name: MetricName,
+    expr: Expr,
+}
+
+impl FromStr for MetricDef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, expr) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Invalid metric {}, expected NAME=EXPR", s))?;
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(anyhow!("Invalid metric name {:?}", name));
+        }
+        let parse = || {
+            let mut parser = Parser {
+                tokens: tokenize(expr)?,
+                position: 0,
+            };
+            let parsed = parser.sum()?;
+            match parser.peek() {
+                None => Ok(parsed),
+                Some(token) => Err(anyhow!("Unexpected {}", token.describe())),
+            }
+        };
+        let parsed = parse().map_err(|e: anyhow::Error| anyhow!("Invalid metric {}: {}", s, e))?;
+        Ok(MetricDef {
+            name: name.to_string().into(),
+            expr: parsed,
+        })
+    }
+}
+
+/// Add each defined metric to the nodes it can be computed for, in order, so later
+/// definitions can use earlier ones
+pub fn add_metrics(graphs: &mut [Graph], defs: &[MetricDef]) {
+    for node in graphs.iter_mut().flat_map(|graph| &mut graph.nodes) {
+        for def in defs {
+            if let Some(value) = def.expr.eval(&node.metrics) {
+                node.metrics.insert(def.name.clone(), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(def: &str, metrics: &[(&'static str, MetricValue)]) -> Option<MetricValue> {
+        let def = def.parse::<MetricDef>().unwrap();
+        let metrics = metrics
+            .iter()
+            .map(|&(name, value)| (MetricName::from(name), value))
+            .collect();
+        def.expr.eval(&metrics)
+    }
+
+    fn error(def: &str) -> String {
+        def.parse::<MetricDef>().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        assert_eq!(eval("x=1+2*3", &[]), Some(MetricValue::Int(7)));
+        assert_eq!(eval("x=(1+2)*3", &[]), Some(MetricValue::Int(9)));
+        assert_eq!(eval("x=10-4-3", &[]), Some(MetricValue::Int(3)));
+        assert_eq!(eval("x=7%4*2", &[]), Some(MetricValue::Int(6)));
+        assert_eq!(
+            eval(
+                "x=deaths*10000/confirmed",
+                &[("deaths", 3.into()), ("confirmed", 40.into())]
+            ),
+            Some(MetricValue::Int(750))
+        );
+    }
+
+    #[test]
+    fn unary_minus() {
+        assert_eq!(eval("x=-3+5", &[]), Some(MetricValue::Int(2)));
+        assert_eq!(eval("x=2*-(1+2)", &[]), Some(MetricValue::Int(-6)));
+        assert_eq!(eval("x=--4", &[]), Some(MetricValue::Int(4)));
+    }
+
+    #[test]
+    fn division_rounds_towards_zero() {
+        assert_eq!(eval("x=7/2", &[]), Some(MetricValue::Int(3)));
+        assert_eq!(eval("x=-7/2", &[]), Some(MetricValue::Int(-3)));
+    }
+
+    #[test]
+    fn divide_by_zero_and_overflow_leave_the_metric_out() {
+        assert_eq!(eval("x=confirmed/0", &[("confirmed", 5.into())]), None);
+        assert_eq!(eval("x=confirmed%0", &[("confirmed", 5.into())]), None);
+        assert_eq!(eval("x=rate/0", &[("rate", 1.5.into())]), None);
+        assert_eq!(eval("x=big*2", &[("big", i64::MAX.into())]), None);
+        assert_eq!(eval("x=big+1", &[("big", i64::MAX.into())]), None);
+        assert_eq!(eval("x=-big", &[("big", i64::MIN.into())]), None);
+    }
+
+    #[test]
+    fn missing_metric_leaves_the_metric_out() {
+        assert_eq!(eval("x=deaths+1", &[]), None);
+    }
+
+    #[test]
+    fn float_metric_makes_a_float() {
+        assert_eq!(
+            eval("x=rate*2", &[("rate", 1.25.into())]),
+            Some(MetricValue::Float(2.5))
+        );
+        assert_eq!(
+            eval("x=rate/2+1", &[("rate", 3.0.into())]),
+            Some(MetricValue::Float(2.5))
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(error("x=1 2"), "Invalid metric x=1 2: Unexpected 2");
+        assert_eq!(error("x=(1+2"), "Invalid metric x=(1+2: Missing )");
+        assert_eq!(error("x=1+2)"), "Invalid metric x=1+2): Unexpected )");
+        assert_eq!(
+            error("x=1+"),
+            "Invalid metric x=1+: Unexpected end of expression"
+        );
+        assert_eq!(error("x=1^2"), "Invalid metric x=1^2: Unexpected ^");
+        assert_eq!(error("x=1a"), "Invalid metric x=1a: Invalid number 1a");
+        assert_eq!(error("1+2"), "Invalid metric 1+2, expected NAME=EXPR");
+        assert_eq!(error("a b=1"), "Invalid metric name \"a b\"");
+    }
+
+    #[test]
+    fn later_definitions_use_earlier_ones() {
+        let mut graphs = vec![Graph {
+            nodes: vec![crate::Node::default()],
+            ..Default::default()
+        }];
+        graphs[0].nodes[0].metrics.insert("deaths".into(), 4.into());
+        let defs = ["a=deaths*2", "b=a+1", "c=missing+1"]
+            .iter()
+            .map(|def| def.parse().unwrap())
+            .collect::<Vec<MetricDef>>();
+        add_metrics(&mut graphs, &defs);
+        let metrics = &graphs[0].nodes[0].metrics;
+        assert_eq!(metrics.get("b"), Some(&MetricValue::Int(9)));
+        assert!(!metrics.contains_key("c"));
+    }
+}
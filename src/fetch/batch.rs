@@ -0,0 +1,240 @@
+//! Downloading many files at once, like a run of daily reports, for `fetch --urls`
+//!
+//! The downloads share one async client: at most `concurrency` of them are in flight, their
+//! requests start no faster than `rate` a second, and a download cut off part way is picked up
+//! where it stopped, with a `Range` request, the next time round
+
+use anyhow::{anyhow, Result};
+use reqwest::header::{CONTENT_RANGE, RANGE};
+use reqwest::{Client, StatusCode};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, Semaphore};
+use tokio::time::{self, Instant};
+
+/// Added to the path of a file while it's downloading
+const PARTIAL_SUFFIX: &str = ".part";
+
+pub struct Download {
+    pub url: String,
+    /// Where the finished file goes
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Downloaded {
+        bytes: u64,
+        resumed: bool,
+    },
+    /// The file was there from an earlier run
+    Skipped,
+}
+
+pub struct Limits {
+    /// Downloads in flight at once
+    pub concurrency: usize,
+    /// Requests started a second at most, retries included. Unlimited without
+    pub rate: Option<f64>,
+    /// How many times to retry a failed download, with exponential backoff
+    pub retries: u32,
+}
+
+/// Spaces out the starts of requests to `rate` a second
+struct RateLimit {
+    interval: Option<Duration>,
+    next: Mutex<Instant>,
+}
+
+impl RateLimit {
+    fn new(rate: Option<f64>) -> Self {
+        RateLimit {
+            interval: rate.map(|rate| Duration::from_secs_f64(1.0 / rate)),
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn wait(&self) {
+        let interval = match self.interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        // Held while sleeping, so the waiters go one interval apart in turn
+        let mut next = self.next.lock().await;
+        time::sleep_until(*next).await;
+        *next = Instant::now() + interval;
+    }
+}
+
+/// A try at a download that didn't fail for good
+enum Attempt {
+    Done(Outcome),
+    Retry(anyhow::Error),
+}
+
+fn partial_path(download: &Download) -> PathBuf {
+    let mut path = download.path.clone().into_os_string();
+    path.push(PARTIAL_SUFFIX);
+    path.into()
+}
+
+/// Download all of `downloads`, each to its path. One failing doesn't stop the others, so
+/// there's an outcome for each, in order
+pub fn download_all(downloads: Vec<Download>, limits: &Limits) -> Result<Vec<Result<Outcome>>> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let client = Client::builder()
+            .read_timeout(super::READ_TIMEOUT)
+            .build()?;
+        let semaphore = Arc::new(Semaphore::new(limits.concurrency));
+        let rate = Arc::new(RateLimit::new(limits.rate));
+        let retries = limits.retries;
+        let tasks = downloads
+            .into_iter()
+            .map(|download| {
+                let (client, semaphore, rate) = (client.clone(), semaphore.clone(), rate.clone());
+                tokio::spawn(async move {
+                    let _permit = semaphore.acquire().await?;
+                    download_with_retries(&client, &rate, &download, retries).await
+                })
+            })
+            .collect::<Vec<_>>();
+        let mut outcomes = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            outcomes.push(task.await?);
+        }
+        Ok(outcomes)
+    })
+}
+
+async fn download_with_retries(
+    client: &Client,
+    rate: &RateLimit,
+    download: &Download,
+    retries: u32,
+) -> Result<Outcome> {
+    if fs::try_exists(&download.path).await? {
+        return Ok(Outcome::Skipped);
+    }
+    let mut attempt = 0;
+    loop {
+        rate.wait().await;
+        let error = match try_download(client, download).await? {
+            Attempt::Done(outcome) => return Ok(outcome),
+            Attempt::Retry(error) => error,
+        };
+        if attempt >= retries {
+            return Err(error.context(format!(
+                "Giving up on {} after {} attempts",
+                download.url,
+                attempt + 1
+            )));
+        }
+        time::sleep(super::backoff(attempt)).await;
+        attempt += 1;
+    }
+}
+
+async fn try_download(client: &Client, download: &Download) -> Result<Attempt> {
+    let partial = partial_path(download);
+    let offset = match fs::metadata(&partial).await {
+        Ok(metadata) => metadata.len(),
+        Err(_) => 0,
+    };
+    let mut request = client.get(&download.url);
+    if offset > 0 {
+        request = request.header(RANGE, format!("bytes={}-", offset));
+    }
+    let mut response = match request.send().await {
+        Ok(response) => response,
+        Err(e) => return Ok(Attempt::Retry(e.into())),
+    };
+
+    let status = response.status();
+    if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+        return Ok(Attempt::Retry(anyhow!("Server responded with {}", status)));
+    }
+    let resumed = match status {
+        // The rest of the file, as long as it starts where the partial file ends. A range
+        // from the start is the whole file again, any other and it starts over
+        StatusCode::PARTIAL_CONTENT => {
+            let start = response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|range| range.to_str().ok())
+                .and_then(|range| range.strip_prefix("bytes "))
+                .and_then(|range| range.split_once('-'))
+                .and_then(|(start, _)| start.parse::<u64>().ok());
+            match start {
+                Some(start) if start == offset => true,
+                Some(0) => false,
+                _ => {
+                    if offset > 0 {
+                        fs::remove_file(&partial).await?;
+                    }
+                    return Ok(Attempt::Retry(anyhow!(
+                        "{} sent another range than the one asked for",
+                        download.url
+                    )));
+                }
+            }
+        }
+        // Nothing past the end of the partial file: it's finished if it's as long as the
+        // whole file, else the file changed under it and it starts over
+        StatusCode::RANGE_NOT_SATISFIABLE => {
+            let length = response
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|range| range.to_str().ok())
+                .and_then(|range| range.strip_prefix("bytes */"))
+                .and_then(|length| length.parse::<u64>().ok());
+            if length == Some(offset) {
+                fs::rename(&partial, &download.path).await?;
+                return Ok(Attempt::Done(Outcome::Downloaded {
+                    bytes: 0,
+                    resumed: true,
+                }));
+            }
+            fs::remove_file(&partial).await?;
+            return Ok(Attempt::Retry(anyhow!(
+                "{} changed since it was partly downloaded",
+                download.url
+            )));
+        }
+        // Servers without range requests send the whole file again
+        status if status.is_success() => false,
+        status => return Err(anyhow!("{} responded with {}", download.url, status)),
+    };
+
+    let mut options = OpenOptions::new();
+    if resumed {
+        options.append(true);
+    } else {
+        options.write(true).create(true).truncate(true);
+    }
+    let mut file = options.open(&partial).await?;
+    let mut bytes = 0;
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                file.write_all(&chunk).await?;
+                bytes += chunk.len() as u64;
+            }
+            Ok(None) => break,
+            // What came is kept for the next attempt to resume from
+            Err(e) => {
+                file.flush().await?;
+                return Ok(Attempt::Retry(e.into()));
+            }
+        }
+    }
+    file.flush().await?;
+    drop(file);
+    fs::rename(&partial, &download.path).await?;
+    Ok(Attempt::Done(Outcome::Downloaded { bytes, resumed }))
+}
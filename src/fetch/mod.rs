@@ -0,0 +1,299 @@
+//! Download the raw county data straight from the Knowi API, or many files at once from a
+//! list of URLs
+
+mod batch;
+
+use crate::log;
+use anyhow::{anyhow, Context, Result};
+use reqwest::blocking::{Client, Response};
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use structopt::StructOpt;
+
+// https://www.knowi.com/coronavirus-dashboards/covid-19-api/
+const KNOWI_COUNTY_URL: &str = "https://knowi.com/api/data/ipE4xJhLBkn8H8jisFisAdHKvepFR5I4bGzRySZ2aaXlJgie?entityName=Raw%20County%20level%20Data&exportFormat=json";
+
+const CACHE_DATA_FILE: &str = "data";
+const CACHE_META_FILE: &str = "meta.json";
+
+/// Longest wait between retries, in seconds. The wait doubles from one second up to it
+const MAX_BACKOFF: u64 = 60;
+
+/// How long the server can go without sending anything, for the response or while reading
+/// the data, before the request fails. A download as a whole can take longer
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, StructOpt)]
+pub struct FetchOpt {
+    /// URL to download the raw data from
+    #[structopt(long, default_value = KNOWI_COUNTY_URL)]
+    url: String,
+
+    /// File of URLs to download instead, one per line. The output is then a directory the
+    /// files go in, each named after the last segment of its URL
+    #[structopt(long, parse(from_os_str), conflicts_with_all = &["url", "cache-dir"])]
+    urls: Option<PathBuf>,
+
+    /// With `--urls`, how many files to download at once
+    #[structopt(long, default_value = "8")]
+    concurrency: usize,
+
+    /// With `--urls`, the most requests to start a second
+    #[structopt(long)]
+    rate: Option<f64>,
+
+    /// Directory to keep the last download in. Unchanged data is not downloaded again
+    #[structopt(long, parse(from_os_str))]
+    cache_dir: Option<PathBuf>,
+
+    /// How many times to retry a failed download, with exponential backoff up to a minute
+    #[structopt(long, default_value = "5")]
+    retries: u32,
+
+    /// Where to write the downloaded data, or `-` for stdout. A directory with `--urls`
+    #[structopt(parse(from_os_str))]
+    output: PathBuf,
+}
+
+/// Validators from the last successful download, used for conditional requests
+#[derive(Serialize, Deserialize, Debug)]
+struct CacheMeta {
+    url: String,
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl CacheMeta {
+    fn from_response(url: &str, response: &Response) -> Self {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(String::from)
+        };
+        CacheMeta {
+            url: url.to_string(),
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+}
+
+fn read_cache_meta(cache_dir: &Path, url: &str) -> Option<CacheMeta> {
+    let meta = fs::read(cache_dir.join(CACHE_META_FILE)).ok()?;
+    let meta = serde_json::from_slice::<CacheMeta>(&meta).ok()?;
+    if meta.url == url && cache_dir.join(CACHE_DATA_FILE).exists() {
+        Some(meta)
+    } else {
+        None
+    }
+}
+
+fn write_cache(cache_dir: &Path, meta: &CacheMeta, data: &[u8]) -> Result<()> {
+    fs::create_dir_all(cache_dir).context("Failed to create cache dir")?;
+    fs::write(cache_dir.join(CACHE_DATA_FILE), data)?;
+    fs::write(
+        cache_dir.join(CACHE_META_FILE),
+        serde_json::to_string_pretty(meta)?,
+    )?;
+    Ok(())
+}
+
+fn get_with_retries(
+    client: &Client,
+    url: &str,
+    cached: Option<&CacheMeta>,
+    retries: u32,
+) -> Result<Response> {
+    let mut attempt = 0;
+    loop {
+        let mut request = client.get(url);
+        if let Some(meta) = cached {
+            if let Some(etag) = &meta.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &meta.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+
+        let error = match request.send() {
+            Ok(response)
+                if response.status().is_server_error()
+                    || response.status() == StatusCode::TOO_MANY_REQUESTS =>
+            {
+                anyhow!("Server responded with {}", response.status())
+            }
+            Ok(response) => return Ok(response.error_for_status()?),
+            Err(e) => e.into(),
+        };
+
+        if attempt >= retries {
+            return Err(error.context(format!("Giving up after {} attempts", attempt + 1)));
+        }
+        thread::sleep(backoff(attempt));
+        attempt += 1;
+    }
+}
+
+/// How long to wait before retrying after the `attempt`th retry
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_secs(
+        1u64.checked_shl(attempt)
+            .unwrap_or(u64::MAX)
+            .min(MAX_BACKOFF),
+    )
+}
+
+/// The downloads of `--urls`, into `dir`
+fn read_downloads(urls: &Path, dir: &Path) -> Result<Vec<batch::Download>> {
+    let list = fs::read_to_string(urls)
+        .with_context(|| format!("Failed to read URL list {}", urls.display()))?;
+    let mut names = HashSet::new();
+    let mut downloads = Vec::new();
+    for url in list.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let name = reqwest::Url::parse(url)
+            .with_context(|| format!("Invalid URL {}", url))?
+            .path_segments()
+            .and_then(|mut segments| segments.rfind(|s| !s.is_empty()))
+            .map(String::from)
+            .ok_or_else(|| anyhow!("{} has no file name to save it under", url))?;
+        if !names.insert(name.clone()) {
+            return Err(anyhow!(
+                "More than one URL in {} is named {}",
+                urls.display(),
+                name
+            ));
+        }
+        downloads.push(batch::Download {
+            url: url.to_string(),
+            path: dir.join(name),
+        });
+    }
+    Ok(downloads)
+}
+
+fn run_batch(urls: &Path, limits: batch::Limits, output: &Path) -> Result<()> {
+    if limits.concurrency == 0 {
+        return Err(anyhow!("--concurrency needs at least one download"));
+    }
+    if limits.rate.is_some_and(|rate| rate.is_nan() || rate <= 0.0) {
+        return Err(anyhow!("--rate needs to be more than 0"));
+    }
+    let downloads = log::stage("read_urls", || {
+        fs::create_dir_all(output).context("Failed to create output dir")?;
+        let downloads = read_downloads(urls, output)?;
+        tracing::info!(urls = downloads.len());
+        Ok(downloads)
+    })?;
+    log::stage("fetch", || {
+        let count = downloads.len();
+        let outcomes = batch::download_all(downloads, &limits)?;
+        let (mut bytes, mut downloaded, mut resumed, mut skipped) = (0, 0, 0, 0);
+        let mut errors = Vec::new();
+        for outcome in outcomes {
+            match outcome {
+                Ok(batch::Outcome::Downloaded {
+                    bytes: n,
+                    resumed: r,
+                }) => {
+                    bytes += n;
+                    downloaded += 1;
+                    resumed += r as usize;
+                }
+                Ok(batch::Outcome::Skipped) => skipped += 1,
+                Err(e) => errors.push(e),
+            }
+        }
+        tracing::info!(downloaded, resumed, skipped, failed = errors.len());
+        tracing::info!(size_mb = bytes / 1000000);
+        if errors.is_empty() {
+            return Ok(());
+        }
+        let shown = errors
+            .iter()
+            .take(20)
+            .map(|e| format!("{:#}", e))
+            .collect::<Vec<_>>();
+        Err(anyhow!(
+            "{} of {} downloads failed:\n  {}",
+            errors.len(),
+            count,
+            shown.join("\n  ")
+        ))
+    })
+}
+
+pub fn run(opt: FetchOpt) -> Result<()> {
+    let FetchOpt {
+        url,
+        urls,
+        concurrency,
+        rate,
+        cache_dir,
+        retries,
+        output,
+    } = opt;
+
+    if let Some(urls) = urls {
+        let limits = batch::Limits {
+            concurrency,
+            rate,
+            retries,
+        };
+        return run_batch(&urls, limits, &output);
+    }
+
+    let data = log::stage("fetch", || {
+        tracing::info!(url = url.clone());
+        let cached = cache_dir
+            .as_deref()
+            .and_then(|dir| read_cache_meta(dir, &url));
+
+        let client = Client::builder()
+            .timeout(READ_TIMEOUT)
+            .build()
+            .context("Failed to build HTTP client")?;
+        let mut response = get_with_retries(&client, &url, cached.as_ref(), retries)?;
+
+        let data = match (&cache_dir, response.status()) {
+            (Some(dir), StatusCode::NOT_MODIFIED) => {
+                tracing::info!(cache = "hit");
+                fs::read(dir.join(CACHE_DATA_FILE)).context("Failed to read cached data")?
+            }
+            (cache_dir, _) => {
+                let meta = CacheMeta::from_response(&url, &response);
+                // Read rather than `bytes`, which would time the whole download out
+                let mut data = Vec::new();
+                response
+                    .read_to_end(&mut data)
+                    .context("Failed to download data")?;
+                if let Some(dir) = cache_dir {
+                    tracing::info!(cache = "miss");
+                    write_cache(dir, &meta, &data)?;
+                }
+                data
+            }
+        };
+        tracing::info!(size_mb = data.len() / 1000000);
+        Ok(data)
+    })?;
+
+    log::stage("write_file", || {
+        tracing::info!(output = %output.display());
+        if output == Path::new("-") {
+            io::stdout().write_all(&data)?;
+        } else {
+            fs::write(&output, &data).context("Failed to write downloaded data")?;
+        }
+        Ok(())
+    })
+}
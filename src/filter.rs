@@ -0,0 +1,151 @@
+//! Which entries to keep while grouping, and under which county
+
+use crate::states;
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use std::collections::HashSet;
+use std::fmt;
+use std::str::FromStr;
+
+/// What to do with pseudo-counties like `Unassigned` and `Out of NY`
+#[derive(Debug, Clone, Copy)]
+pub enum Unassigned {
+    Keep,
+    Drop,
+    /// Count them towards the state node only, without a county node of their own
+    State,
+}
+
+impl FromStr for Unassigned {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Unassigned::Keep),
+            "drop" => Ok(Unassigned::Drop),
+            "state" => Ok(Unassigned::State),
+            _ => Err(anyhow!(
+                "Unknown unassigned policy {}, expected keep, drop or state",
+                s
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Unassigned {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Unassigned::Keep => "keep",
+            Unassigned::Drop => "drop",
+            Unassigned::State => "state",
+        })
+    }
+}
+
+/// County the five boroughs are merged into with `--merge-nyc`, as NYT style sources
+/// report them
+pub const NEW_YORK_CITY: &str = "New York City";
+
+/// Counties of the five New York City boroughs
+const NYC_BOROUGHS: &[&str] = &["new york", "kings", "queens", "bronx", "richmond"];
+
+fn is_nyc_borough(state: &str, county: &str) -> bool {
+    let county = county.trim().to_ascii_lowercase();
+    let county = county.strip_suffix(" county").unwrap_or(&county);
+    state == "New York" && NYC_BOROUGHS.contains(&county)
+}
+
+/// Whether a county name is a placeholder for cases not assigned to a real county
+pub fn is_pseudo_county(county: &str) -> bool {
+    let county = county.trim().to_ascii_lowercase();
+    county == "unassigned"
+        || county == "unknown"
+        || county.starts_with("out of ")
+        || county.starts_with("out-of-")
+}
+
+#[derive(Debug)]
+pub struct EntryFilter {
+    from: Option<NaiveDate>,
+    to: Option<NaiveDate>,
+    /// Full state names, empty to keep every state
+    states: HashSet<String>,
+    /// County keys, empty to keep every county
+    counties: HashSet<String>,
+    unassigned: Unassigned,
+    merge_nyc: bool,
+}
+
+/// Full name for a state given by abbreviation or name
+fn state_name(state: &str) -> Result<&'static str> {
+    states::normalize(state).ok_or_else(|| anyhow!("Unknown state {}", state.trim()))
+}
+
+impl EntryFilter {
+    /// Counties are given as `STATE - COUNTY`, with the state abbreviated or in full
+    pub fn new(
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+        states: &[String],
+        counties: &[String],
+        unassigned: Unassigned,
+        merge_nyc: bool,
+    ) -> Result<Self> {
+        if let (Some(from), Some(to)) = (from, to) {
+            if from > to {
+                return Err(anyhow!("--from {} is after --to {}", from, to));
+            }
+        }
+        let states = states
+            .iter()
+            .map(|state| state_name(state).map(String::from))
+            .collect::<Result<_>>()?;
+        let counties = counties
+            .iter()
+            .map(|county| {
+                let (state, name) = county
+                    .split_once(" - ")
+                    .ok_or_else(|| anyhow!("Invalid county {}, expected STATE - COUNTY", county))?;
+                Ok(crate::group::county_key(state_name(state)?, name.trim()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(EntryFilter {
+            from,
+            to,
+            states,
+            counties,
+            unassigned,
+            merge_nyc,
+        })
+    }
+
+    /// Whether an entry on `day` for a county is kept. With both states and counties
+    /// given, an entry in either is kept
+    pub fn keeps(&self, day: NaiveDate, state: &str, county: &str) -> bool {
+        if self.from.is_some_and(|from| day < from) || self.to.is_some_and(|to| day > to) {
+            return false;
+        }
+        if matches!(self.unassigned, Unassigned::Drop) && is_pseudo_county(county) {
+            return false;
+        }
+        if self.states.is_empty() && self.counties.is_empty() {
+            return true;
+        }
+        self.states.contains(state)
+            || self
+                .counties
+                .contains(&crate::group::county_key(state, county))
+    }
+
+    /// County name a kept entry is grouped under, empty for entries that only count
+    /// towards their state, and whether that's still the county its FIPS code is for
+    pub fn county(&self, state: &str, county: String) -> (String, bool) {
+        match self.unassigned {
+            Unassigned::State if is_pseudo_county(&county) => (String::new(), false),
+            _ if self.merge_nyc && is_nyc_borough(state, &county) => {
+                (NEW_YORK_CITY.to_string(), false)
+            }
+            _ => (county, true),
+        }
+    }
+}
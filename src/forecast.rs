@@ -0,0 +1,306 @@
+//! `forecast` subcommand: project the states' cases and deaths a week or two past the
+//! end of a run's JSON output, written as future dated graphs
+//!
+//! Both models work on the new cases and deaths per date, which are then added onto the
+//! last totals. `log-linear` fits a line to their logarithm over the last `--window` days.
+//! `holt-winters` smooths level, trend and, for daily graphs, the weekly reporting cycle.
+
+use crate::log;
+use crate::output::{self, OutputOpt, OutputTarget};
+use crate::MetricValue;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, NaiveDate};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+/// Metrics projected
+const METRICS: [&str; 2] = ["confirmed", "deaths"];
+
+/// Smoothing of the level, trend and season in `holt-winters`
+const ALPHA: f64 = 0.5;
+const BETA: f64 = 0.1;
+const GAMMA: f64 = 0.3;
+
+/// Reporting cycle of daily graphs, in dates
+const SEASON: usize = 7;
+
+#[derive(Debug, StructOpt)]
+pub struct ForecastOpt {
+    /// Output directory, or single `graphs.json` / `graphs.ndjson` file, of a run with the
+    /// states in it
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Where to write the forecast graphs, a directory or `sqlite:path.db`
+    output: OutputTarget,
+
+    /// Days past the last graph to project
+    #[structopt(long, default_value = "14")]
+    horizon: i64,
+
+    /// `log-linear` or `holt-winters`
+    #[structopt(
+        long,
+        default_value = "log-linear",
+        possible_values = &["log-linear", "holt-winters"]
+    )]
+    model: Model,
+
+    /// Days of history `log-linear` fits to
+    #[structopt(long, default_value = "28")]
+    window: i64,
+
+    #[structopt(flatten)]
+    output_opt: OutputOpt,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Model {
+    LogLinear,
+    HoltWinters,
+}
+
+impl FromStr for Model {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log-linear" => Ok(Model::LogLinear),
+            "holt-winters" => Ok(Model::HoltWinters),
+            _ => Err(anyhow!(
+                "Unknown model {}, expected log-linear or holt-winters",
+                s
+            )),
+        }
+    }
+}
+
+impl Model {
+    fn name(self) -> &'static str {
+        match self {
+            Model::LogLinear => "log-linear",
+            Model::HoltWinters => "holt-winters",
+        }
+    }
+
+    /// The next `steps` values of a series, `None` when it's too short to fit
+    fn project(self, series: &[f64], steps: usize, season: usize) -> Option<Vec<f64>> {
+        match self {
+            Model::LogLinear => log_linear(series, steps),
+            Model::HoltWinters => holt_winters(series, steps, season),
+        }
+    }
+}
+
+/// Least squares line through `ln(1 + y)`, so dates without anything new still count
+fn log_linear(series: &[f64], steps: usize) -> Option<Vec<f64>> {
+    if series.len() < 2 {
+        return None;
+    }
+    let n = series.len() as f64;
+    let logs = series.iter().map(|y| y.ln_1p()).collect::<Vec<_>>();
+    let mean_t = (n - 1.0) / 2.0;
+    let mean_y = logs.iter().sum::<f64>() / n;
+    let (covariance, variance) = logs.iter().enumerate().fold((0.0, 0.0), |(c, v), (t, y)| {
+        let dt = t as f64 - mean_t;
+        (c + dt * (y - mean_y), v + dt * dt)
+    });
+    let slope = covariance / variance;
+    Some(
+        (0..steps)
+            .map(|step| {
+                let t = n + step as f64 - mean_t;
+                (mean_y + slope * t).exp_m1().max(0.0)
+            })
+            .collect(),
+    )
+}
+
+/// Additive Holt-Winters, started from the first two seasons
+fn holt_winters(series: &[f64], steps: usize, season: usize) -> Option<Vec<f64>> {
+    if series.len() < 2 * season {
+        return None;
+    }
+    let mean = |values: &[f64]| values.iter().sum::<f64>() / values.len() as f64;
+    let mut level = mean(&series[..season]);
+    let mut trend = (mean(&series[season..2 * season]) - level) / season as f64;
+    let mut seasonal = series[..season]
+        .iter()
+        .map(|y| y - level)
+        .collect::<Vec<_>>();
+    for (t, y) in series.iter().enumerate().skip(season) {
+        let s = seasonal[t % season];
+        let previous = level;
+        level = ALPHA * (y - s) + (1.0 - ALPHA) * (level + trend);
+        trend = BETA * (level - previous) + (1.0 - BETA) * trend;
+        seasonal[t % season] = GAMMA * (y - level) + (1.0 - GAMMA) * s;
+    }
+    Some(
+        (1..=steps)
+            .map(|step| {
+                let s = seasonal[(series.len() + step - 1) % season];
+                (level + trend * step as f64 + s).max(0.0)
+            })
+            .collect(),
+    )
+}
+
+#[derive(Deserialize)]
+struct Graph {
+    timestamp: String,
+    nodes: Vec<Node>,
+}
+
+#[derive(Deserialize)]
+struct Node {
+    #[serde(default)]
+    id: String,
+    name: String,
+    level: String,
+    #[serde(default)]
+    metrics: BTreeMap<String, MetricValue>,
+}
+
+/// A state's totals of each metric by date, oldest first
+struct Series {
+    id: String,
+    level: &'static str,
+    totals: BTreeMap<NaiveDate, [i64; 2]>,
+}
+
+pub fn run(opt: ForecastOpt) -> Result<()> {
+    let ForecastOpt {
+        input,
+        output,
+        horizon,
+        model,
+        window,
+        output_opt,
+    } = opt;
+    if horizon < 1 || window < 2 {
+        return Err(anyhow!("--horizon has to be at least 1 and --window 2"));
+    }
+
+    let graphs = log::stage("forecast", || {
+        let mut states = BTreeMap::<String, Series>::new();
+        let mut dates = BTreeMap::new();
+        for graph in crate::diff::read_graphs::<Graph>(&input)? {
+            let date = DateTime::parse_from_rfc3339(&graph.timestamp)?;
+            dates.insert(date.date_naive(), date);
+            for node in graph.nodes {
+                let level = match node.level.as_str() {
+                    "state" => "state",
+                    "country" => "country",
+                    _ => continue,
+                };
+                let totals =
+                    METRICS.map(|metric| node.metrics.get(metric).map_or(0, |v| v.as_i64()));
+                let id = node.id;
+                states
+                    .entry(node.name)
+                    .or_insert_with(|| Series {
+                        id,
+                        level,
+                        totals: BTreeMap::new(),
+                    })
+                    .totals
+                    .insert(date.date_naive(), totals);
+            }
+        }
+        if states.is_empty() {
+            return Err(anyhow!("No state nodes in {}", input.display()));
+        }
+
+        // Project by the graphs' own spacing, so weekly output gets weekly forecasts
+        let mut last_dates = dates.values().rev();
+        let last = *last_dates.next().expect("read_graphs found some");
+        let step = last_dates
+            .next()
+            .map_or(Duration::days(1), |previous| last - *previous);
+        let steps = (horizon / step.num_days().max(1)).max(1) as usize;
+        let season = if step == Duration::days(1) { SEASON } else { 1 };
+        let window = (window / step.num_days().max(1)).max(2) as usize;
+
+        let mut graphs = (1..=steps)
+            .map(|k| crate::Graph {
+                timestamp: (last + step * k as i32).to_rfc3339(),
+                nodes: Vec::new(),
+                meta: None,
+            })
+            .collect::<Vec<_>>();
+        let mut skipped = 0;
+        for (name, series) in states {
+            let totals = series.totals.values().collect::<Vec<_>>();
+            let latest = match totals.last() {
+                Some(latest) if series.totals.keys().next_back() == Some(&last.date_naive()) => {
+                    **latest
+                }
+                _ => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+            let projections = (0..METRICS.len())
+                .map(|m| {
+                    let new = totals
+                        .windows(2)
+                        .map(|pair| (pair[1][m] - pair[0][m]).max(0) as f64)
+                        .collect::<Vec<_>>();
+                    let history = match model {
+                        Model::LogLinear => &new[new.len().saturating_sub(window)..],
+                        Model::HoltWinters => &new[..],
+                    };
+                    let projected = model.project(history, steps, season)?;
+                    Some(
+                        projected
+                            .iter()
+                            .scan(latest[m] as f64, |total, new| {
+                                *total += new;
+                                Some(total.round() as i64)
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                })
+                .collect::<Option<Vec<_>>>();
+            let projections = match projections {
+                Some(projections) => projections,
+                None => {
+                    skipped += 1;
+                    continue;
+                }
+            };
+            for (k, graph) in graphs.iter_mut().enumerate() {
+                graph.nodes.push(crate::Node {
+                    id: series.id.clone(),
+                    name: name.clone(),
+                    level: series.level,
+                    metrics: METRICS
+                        .iter()
+                        .zip(&projections)
+                        .map(|(&metric, projected)| (metric.into(), projected[k].into()))
+                        .collect(),
+                    extra_fields: vec![
+                        ("forecast", "true".to_string()),
+                        ("forecast_model", model.name().to_string()),
+                    ]
+                    .into_iter()
+                    .collect(),
+                    ..Default::default()
+                });
+            }
+        }
+        tracing::info!(dates = graphs.len());
+        tracing::info!(nodes_skipped = skipped);
+        Ok(graphs)
+    })?;
+
+    log::stage("write_files", || {
+        tracing::info!(output = %output);
+        let num_files = output::write(graphs, &output, &output_opt)?;
+        tracing::info!(num_files = num_files);
+        Ok(())
+    })
+}
@@ -0,0 +1,68 @@
+//! Bucketing the grouped entries into weeks or months before the graphs are built
+
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy)]
+pub enum Granularity {
+    Daily,
+    /// ISO weeks, starting on Monday
+    Weekly,
+    Monthly,
+}
+
+impl FromStr for Granularity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "daily" => Ok(Granularity::Daily),
+            "weekly" => Ok(Granularity::Weekly),
+            "monthly" => Ok(Granularity::Monthly),
+            _ => Err(anyhow!(
+                "Unknown granularity {}, expected daily, weekly or monthly",
+                s
+            )),
+        }
+    }
+}
+
+impl Granularity {
+    /// First day of the period a date is in
+    fn period_start(self, day: NaiveDate) -> NaiveDate {
+        match self {
+            Granularity::Daily => day,
+            Granularity::Weekly => {
+                day - Duration::days(day.weekday().num_days_from_monday().into())
+            }
+            Granularity::Monthly => day.with_day(1).expect("every month has a first day"),
+        }
+    }
+
+    /// Merge the dates of each period into one, timestamped with the period's first day.
+    /// Every key keeps its value from the last date in the period it was on, so cumulative
+    /// metrics end up with their value at the end of the period
+    pub fn bucket<V>(
+        self,
+        by_date: HashMap<DateTime<Utc>, HashMap<String, V>>,
+    ) -> HashMap<DateTime<Utc>, HashMap<String, V>> {
+        if let Granularity::Daily = self {
+            return by_date;
+        }
+        let mut dates = by_date.into_iter().collect::<Vec<_>>();
+        dates.sort_by_key(|(date, _)| *date);
+
+        let mut buckets = HashMap::<DateTime<Utc>, HashMap<String, V>>::new();
+        for (date, values) in dates {
+            let start = self
+                .period_start(date.date_naive())
+                .and_hms_opt(0, 0, 0)
+                .expect("midnight is a valid time")
+                .and_utc();
+            buckets.entry(start).or_default().extend(values);
+        }
+        buckets
+    }
+}
@@ -0,0 +1,428 @@
+//! Building a graph per date from the grouped entries: county and ZIP nodes, the states
+//! above them and the regions beside, and the edges between them
+
+use crate::filter::Unassigned;
+use crate::group::{county_key, GroupedEntries, StateMetrics};
+use crate::join::adjacency::Adjacency;
+use crate::join::cbsa::Crosswalk;
+use crate::join::commuting::Commuting;
+use crate::join::population::Population;
+use crate::join::testing;
+use crate::meta::Meta;
+use crate::states::{self, Grouping};
+use crate::value::{MetricName, MetricValue};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+pub type Rfc3339 = String;
+
+/// Name of the `--national` root node
+pub const NATIONAL_NODE: &str = "United States";
+
+/// ID a node gets when no other node in its graph has that ID already
+fn node_id(node: &Node) -> String {
+    let code = match node.level {
+        "country" => Some("US".to_string()),
+        "state" => states::fips(&node.name).map(String::from),
+        "county" => node.fips.clone(),
+        "zcta" => node
+            .extra_fields
+            .get("display_name")
+            .map(|zcta| format!("zcta:{}", zcta)),
+        "metro" => node
+            .extra_fields
+            .get("cbsa")
+            .map(|code| format!("cbsa:{}", code)),
+        _ => None,
+    };
+    code.unwrap_or_else(|| node.name.clone())
+}
+
+/// Give the nodes their IDs and point their edges at those rather than at names. A node
+/// whose ID is taken, like a second spelling of a county, falls back on its name
+fn assign_ids(nodes: &mut [Node]) {
+    let mut taken = HashSet::new();
+    let mut ids = HashMap::new();
+    for node in nodes.iter_mut() {
+        let mut id = node_id(node);
+        if !taken.insert(id.clone()) {
+            id = node.name.clone();
+            taken.insert(id.clone());
+        }
+        ids.insert(node.name.clone(), id.clone());
+        node.id = id;
+    }
+    for node in nodes {
+        for edge in &mut node.edges {
+            if let Some(id) = ids.get(&edge.target) {
+                edge.target = id.clone();
+            }
+        }
+        node.edges
+            .sort_by(|a, b| (&a.target, a.kind).cmp(&(&b.target, b.kind)));
+        node.edges
+            .dedup_by(|a, b| a.target == b.target && a.kind == b.kind);
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone, PartialEq)]
+pub struct Node {
+    /// Stable across runs and sources: the FIPS code of states and counties, prefixed
+    /// codes like `zcta:10001` and `cbsa:35620` for other areas with one, else the name
+    pub id: String,
+    pub name: String,
+    /// `country`, `state`, `county` or `zcta`, or `metro`, `hhs_region`, `census_region`
+    /// and `census_division` for the groupings beside that hierarchy
+    pub level: &'static str,
+    pub metrics: BTreeMap<MetricName, MetricValue>,
+    /// Ordered by target
+    pub edges: Vec<Edge>,
+    /// The targets of `edges` in the format from before edges had kinds, for
+    /// `--legacy-edges`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub edges_directed: Option<BTreeSet<String>>,
+    pub extra_fields: BTreeMap<&'static str, String>,
+    /// County FIPS code, for output formats that join on it
+    #[serde(skip)]
+    pub fips: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeKind {
+    /// From a region to one inside it
+    Contains,
+    /// Between neighbouring counties
+    Adjacent,
+    /// From a county to one its residents work in, weighted by the number of jobs
+    Commute,
+}
+
+impl EdgeKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EdgeKind::Contains => "contains",
+            EdgeKind::Adjacent => "adjacent",
+            EdgeKind::Commute => "commute",
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Edge {
+    /// ID of the node the edge points to
+    pub target: String,
+    pub kind: EdgeKind,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<f64>,
+}
+
+impl Edge {
+    pub fn new(target: String, kind: EdgeKind) -> Self {
+        Edge {
+            target,
+            kind,
+            weight: None,
+        }
+    }
+}
+
+impl Node {
+    pub fn add_metric(&mut self, m: impl Into<MetricName>, v: impl Into<MetricValue>) {
+        let total = self.metrics.entry(m.into()).or_default();
+        *total = *total + v.into();
+    }
+
+    /// A region totalling the counts of its members, with their ratios worked out again from
+    /// the totals, edges to them and a population when every member has one
+    fn rollup<'a>(
+        name: String,
+        level: &'static str,
+        members: impl IntoIterator<Item = &'a Node>,
+    ) -> Node {
+        let mut rollup = Node {
+            name,
+            level,
+            ..Default::default()
+        };
+        let mut population = Some(0);
+        for member in members {
+            for (metric, &value) in &member.metrics {
+                if !testing::RATIOS.contains(&metric.as_ref()) {
+                    rollup.add_metric(metric.clone(), value);
+                }
+            }
+            rollup
+                .edges
+                .push(Edge::new(member.name.clone(), EdgeKind::Contains));
+            let count = member
+                .extra_fields
+                .get("population")
+                .and_then(|p| p.parse::<i64>().ok());
+            population = population.zip(count).map(|(a, b)| a + b);
+        }
+        testing::rederive_ratios(&mut rollup.metrics);
+        if let Some(population) = population.filter(|_| !rollup.edges.is_empty()) {
+            rollup
+                .extra_fields
+                .insert("population", population.to_string());
+        }
+        rollup
+    }
+}
+
+#[derive(Serialize, Debug, Default, Clone)]
+pub struct Graph {
+    pub timestamp: Rfc3339,
+    pub nodes: Vec<Node>,
+    /// Set by the `build` command, none for graphs built through the library
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Meta>,
+}
+
+/// Drop all but the `k` county nodes with the highest `metric` from every graph, with
+/// the ZIP codes inside them and the edges to any of them. Returns how many nodes went
+pub fn keep_top(graphs: &mut [Graph], k: usize, metric: &str) -> usize {
+    let mut removed = 0;
+    for graph in graphs {
+        let mut counties = graph
+            .nodes
+            .iter()
+            .filter(|node| node.level == "county")
+            .collect::<Vec<_>>();
+        // Ties go to the lower ID, so the same counties are kept from run to run
+        counties.sort_by(|a, b| {
+            let value = |node: &Node| node.metrics.get(metric).copied();
+            match (value(a), value(b)) {
+                (Some(a), Some(b)) => b.total_cmp(&a),
+                (a, b) => b.is_some().cmp(&a.is_some()),
+            }
+            .then_with(|| a.id.cmp(&b.id))
+        });
+        let mut dropped = HashSet::new();
+        for county in counties.into_iter().skip(k) {
+            dropped.insert(county.id.clone());
+            let zctas = county
+                .edges
+                .iter()
+                .filter(|edge| edge.kind == EdgeKind::Contains)
+                .map(|edge| edge.target.clone());
+            dropped.extend(zctas);
+        }
+        let before = graph.nodes.len();
+        graph.nodes.retain(|node| !dropped.contains(&node.id));
+        removed += before - graph.nodes.len();
+        for node in &mut graph.nodes {
+            node.edges.retain(|edge| !dropped.contains(&edge.target));
+        }
+    }
+    removed
+}
+
+/// What goes into the graphs besides the counties' own entries
+#[derive(Default)]
+pub struct GraphOpt {
+    /// Metrics only the states have, like testing, by date then state
+    pub state_metrics: StateMetrics,
+    /// Neighbours of each county, by key, for the `adjacent` edges
+    pub adjacency: Adjacency,
+    /// Where each county's residents work, by key, for the `commute` edges
+    pub commuting: Commuting,
+    /// Metro areas to add `metro` nodes for
+    pub cbsa: Option<Crosswalk>,
+    /// Noted on the state nodes, along with the population
+    pub population: Option<Population>,
+    pub unassigned: Option<Unassigned>,
+    /// Groupings of states to add nodes for
+    pub hierarchy: Vec<Grouping>,
+    /// Add a `United States` node above the states
+    pub national: bool,
+}
+
+/// A graph per date: the county and ZIP nodes with the state nodes totalling them, the
+/// regions from `opt` and the edges between them all
+pub fn build_graphs(grouped: GroupedEntries, opt: &GraphOpt) -> Vec<Graph> {
+    grouped
+        .into_par_iter()
+        .map(|(date, entries)| {
+            let mut states = HashMap::new();
+
+            let mut all_nodes: Vec<Node> = Vec::new();
+
+            let neighbours = |key: &str| -> Vec<Edge> {
+                let adjacent = opt
+                    .adjacency
+                    .get(key)
+                    .into_iter()
+                    .flatten()
+                    .filter(|neighbour| entries.contains_key(*neighbour))
+                    .map(|neighbour| Edge::new(neighbour.clone(), EdgeKind::Adjacent));
+                let commutes = opt
+                    .commuting
+                    .get(key)
+                    .into_iter()
+                    .flatten()
+                    .filter(|(work, _)| entries.contains_key(*work))
+                    .map(|(work, &jobs)| Edge {
+                        weight: Some(jobs),
+                        ..Edge::new(work.clone(), EdgeKind::Commute)
+                    });
+                adjacent.chain(commutes).collect()
+            };
+            let mut edges_by_county = entries
+                .keys()
+                .map(|key| (key.clone(), neighbours(key)))
+                .collect::<HashMap<_, _>>();
+            // ZIP nodes hang off their county, or the state when the county has no entry
+            let mut zctas_by_state = HashMap::<String, Vec<Edge>>::new();
+            for (key, county_entry) in &entries {
+                if county_entry.zcta.is_none() {
+                    continue;
+                }
+                let county = county_key(&county_entry.state, &county_entry.name);
+                match edges_by_county.get_mut(&county) {
+                    Some(edges) if !county_entry.name.is_empty() => {
+                        edges.push(Edge::new(key.clone(), EdgeKind::Contains));
+                    }
+                    _ => {
+                        zctas_by_state
+                            .entry(county_entry.state.clone())
+                            .or_default()
+                            .push(Edge::new(key.clone(), EdgeKind::Contains));
+                    }
+                }
+            }
+
+            for (key, county_entry) in entries {
+                if !states.contains_key(&county_entry.state) {
+                    states.insert(
+                        county_entry.state.clone(),
+                        Node {
+                            name: county_entry.state.clone(),
+                            level: "state",
+                            edges: zctas_by_state
+                                .remove(&county_entry.state)
+                                .unwrap_or_default(),
+                            ..Default::default()
+                        },
+                    );
+                }
+                let state_entry = states
+                    .get_mut(&county_entry.state)
+                    .expect("state must be there");
+
+                let mut extra_fields = county_entry.extra_fields;
+                let edges = edges_by_county.remove(&key).unwrap_or_default();
+                let level = match county_entry.zcta {
+                    Some(zcta) => {
+                        extra_fields.insert("display_name", zcta);
+                        "zcta"
+                    }
+                    None => {
+                        for (metric, &value) in &county_entry.metrics {
+                            state_entry.add_metric(metric.clone(), value);
+                        }
+                        // State level records have no county node of their own
+                        if county_entry.name.is_empty() {
+                            continue;
+                        }
+                        state_entry
+                            .edges
+                            .push(Edge::new(key.clone(), EdgeKind::Contains));
+                        extra_fields.insert("display_name", county_entry.name);
+                        "county"
+                    }
+                };
+                all_nodes.push(Node {
+                    name: key,
+                    level,
+                    metrics: county_entry.metrics,
+                    extra_fields,
+                    edges,
+                    fips: county_entry.fips,
+                    ..Default::default()
+                })
+            }
+
+            if let Some(metrics_by_state) = opt.state_metrics.get(&date) {
+                for (name, state) in states.iter_mut() {
+                    for (&metric, &value) in metrics_by_state.get(name).into_iter().flatten() {
+                        state.add_metric(metric, value);
+                    }
+                }
+            }
+
+            if let Some(unassigned) = opt.unassigned {
+                for state in states.values_mut() {
+                    state
+                        .extra_fields
+                        .insert("unassigned", unassigned.to_string());
+                }
+            }
+
+            if let Some(population) = &opt.population {
+                for (name, state) in states.iter_mut() {
+                    if let Some(count) = population.state(name) {
+                        state.extra_fields.insert("population", count.to_string());
+                    }
+                }
+            }
+
+            if let Some(cbsa) = &opt.cbsa {
+                let mut members = BTreeMap::<_, Vec<&Node>>::new();
+                for county in all_nodes.iter().filter(|node| node.level == "county") {
+                    if let Some(metro) = cbsa.metro(&county.name, county.fips.as_deref()) {
+                        members
+                            .entry((&metro.title, &metro.code))
+                            .or_default()
+                            .push(county);
+                    }
+                }
+                let metros = members
+                    .into_iter()
+                    .map(|((title, code), counties)| {
+                        let mut metro = Node::rollup(title.clone(), "metro", counties);
+                        metro.extra_fields.insert("cbsa", code.clone());
+                        metro.extra_fields.insert("display_name", title.clone());
+                        metro
+                    })
+                    .collect::<Vec<_>>();
+                all_nodes.extend(metros);
+            }
+
+            for &grouping in &opt.hierarchy {
+                let mut members = BTreeMap::<_, Vec<&Node>>::new();
+                for state in states.values() {
+                    if let Some(group) = grouping.group(&state.name) {
+                        members.entry(group).or_default().push(state);
+                    }
+                }
+                all_nodes.extend(
+                    members
+                        .into_iter()
+                        .map(|(group, states)| Node::rollup(group, grouping.level(), states)),
+                );
+            }
+
+            if opt.national {
+                all_nodes.push(Node::rollup(
+                    NATIONAL_NODE.to_string(),
+                    "country",
+                    states.values(),
+                ));
+            }
+
+            for (_, state) in states {
+                all_nodes.push(state);
+            }
+            assign_ids(&mut all_nodes);
+
+            Graph {
+                timestamp: date.to_rfc3339(),
+                nodes: all_nodes,
+                meta: None,
+            }
+        })
+        .collect()
+}
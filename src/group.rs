@@ -0,0 +1,328 @@
+//! Grouping the raw entries into a county entry per date and county
+
+use crate::dedup::{Dedup, Records};
+use crate::error::{CovidDataError, Result};
+use crate::filter::EntryFilter;
+use crate::lag::Lags;
+use crate::progress::Progress;
+use crate::source::{Entries, RawEntry};
+use crate::timezone::Timezone;
+use crate::value::{MetricName, MetricValue};
+use crate::{counties, states};
+use chrono::{DateTime, NaiveTime, Utc};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug, Clone)]
+pub struct CountyEntry {
+    pub name: String,
+    pub state: String,
+    pub fips: Option<String>,
+    /// Set for ZIP level entries, which hang off their county instead of rolling up into it
+    pub zcta: Option<String>,
+    pub metrics: BTreeMap<MetricName, MetricValue>,
+    pub extra_fields: BTreeMap<&'static str, String>,
+}
+
+impl CountyEntry {
+    pub fn new(name: String, state: String, fips: Option<String>, zcta: Option<String>) -> Self {
+        CountyEntry {
+            name,
+            state,
+            fips,
+            zcta,
+            metrics: vec![("confirmed".into(), 0.into()), ("deaths".into(), 0.into())]
+                .into_iter()
+                .collect(),
+            extra_fields: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_metric(&mut self, m: impl Into<MetricName>, v: impl Into<MetricValue>) {
+        let total = self.metrics.entry(m.into()).or_default();
+        *total = *total + v.into();
+    }
+}
+
+/// Key counties are grouped under.
+/// Make sure we namespace by state in case there are similar county names
+pub fn county_key(state: &str, county: &str) -> String {
+    if county.is_empty() {
+        state.to_string()
+    } else {
+        format!("{} - {}", state, counties::canonical_name(state, county))
+    }
+}
+
+/// Key ZIP level entries are grouped under, below their county's key
+fn zcta_key(state: &str, county: &str, zcta: &str) -> String {
+    format!("{} - {}", county_key(state, county), zcta)
+}
+
+/// Metric an upstream entry type is counted towards
+fn metric_for_entry_type(entry_type: &str) -> Option<&'static str> {
+    match entry_type {
+        "Confirmed" => Some("confirmed"),
+        "Deaths" => Some("deaths"),
+        "Recovered" => Some("recovered"),
+        "Active" => Some("active"),
+        _ => None,
+    }
+}
+
+pub type GroupedEntries = HashMap<DateTime<Utc>, HashMap<String, CountyEntry>>;
+
+/// Metrics that only exist at the state level, keyed by date then state name
+pub type StateMetrics = HashMap<DateTime<Utc>, HashMap<String, BTreeMap<&'static str, i64>>>;
+
+/// Entry types that don't map to any metric, with how many entries had them
+#[derive(Default)]
+pub struct UnmappedTypes {
+    pub counts: BTreeMap<String, usize>,
+    /// First few offending entries, for error messages
+    pub samples: Vec<String>,
+}
+
+impl UnmappedTypes {
+    const MAX_SAMPLES: usize = 5;
+
+    fn add(&mut self, entry: &RawEntry) {
+        *self.counts.entry(entry.entry_type.clone()).or_insert(0) += 1;
+        if self.samples.len() < Self::MAX_SAMPLES {
+            self.samples.push(format!(
+                "{} {}: {}={}",
+                entry.date,
+                county_key(&entry.state, &entry.county),
+                entry.entry_type,
+                entry.values
+            ));
+        }
+    }
+
+    fn merge(&mut self, other: UnmappedTypes) {
+        for (entry_type, count) in other.counts {
+            *self.counts.entry(entry_type).or_insert(0) += count;
+        }
+        let room = Self::MAX_SAMPLES.saturating_sub(self.samples.len());
+        self.samples.extend(other.samples.into_iter().take(room));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    /// The error `strict` builds fail with when there are any
+    pub fn into_error(self) -> CovidDataError {
+        CovidDataError::UnknownEntryType {
+            counts: self.counts,
+            samples: self.samples,
+        }
+    }
+}
+
+/// Carry every county's last entry forward onto the later dates it didn't report on,
+/// marked with a `backfilled` extra field. Returns how many entries were added
+pub fn carry_forward(grouped: &mut GroupedEntries) -> usize {
+    let mut dates = grouped.keys().copied().collect::<Vec<_>>();
+    dates.sort();
+    let mut last = HashMap::<String, CountyEntry>::new();
+    let mut added = 0;
+    for date in dates {
+        let entries = grouped.get_mut(&date).expect("date must be there");
+        for (key, county_entry) in &last {
+            if !entries.contains_key(key) {
+                let mut carried = county_entry.clone();
+                carried
+                    .extra_fields
+                    .insert("backfilled", "true".to_string());
+                entries.insert(key.clone(), carried);
+                added += 1;
+            }
+        }
+        for (key, county_entry) in entries.iter() {
+            last.insert(key.clone(), county_entry.clone());
+        }
+    }
+    added
+}
+
+/// Date an entry is grouped under: its timestamp, in `timezone` for feeds with a time of
+/// day, moved back by its state's lag. Normalizes the entry's state on the way
+pub(crate) fn entry_date(
+    entry: &mut RawEntry,
+    timezone: Timezone,
+    lags: Option<&Lags>,
+) -> Result<DateTime<Utc>> {
+    if let Some(state) = states::normalize(&entry.state) {
+        entry.state = state.to_string();
+    }
+    // raw data is in milisseconds
+    let mut date = DateTime::from_timestamp(entry.date / 1000, 0)
+        .ok_or_else(|| CovidDataError::Parse(format!("Date out of range: {}", entry.date)))?;
+    // Date-only feeds keep their date. Graphs are still timestamped at midnight UTC, of the
+    // date in the zone
+    if timezone != Timezone::UTC && !entry.date_only {
+        date = timezone.date(date).and_time(NaiveTime::MIN).and_utc();
+    }
+    if let Some(lag) = lags.and_then(|lags| lags.get(&entry.state)) {
+        date -= lag;
+    }
+    Ok(date)
+}
+
+/// Entries grouped so far, the types without a metric and, unless `--dedup sum`, the
+/// records still to resolve into the entries
+#[derive(Default)]
+pub(crate) struct Grouping {
+    pub grouped: GroupedEntries,
+    pub unmapped: UnmappedTypes,
+    records: Records,
+}
+
+impl Grouping {
+    /// Add an entry under `date`, unless the filter drops it. `position` is where it came
+    /// in the inputs, for `--dedup last`
+    pub fn add(
+        &mut self,
+        filter: &EntryFilter,
+        dedup: Dedup,
+        position: usize,
+        entry: RawEntry,
+        date: DateTime<Utc>,
+    ) -> Result<()> {
+        if !filter.keeps(date.date_naive(), &entry.state, &entry.county) {
+            return Ok(());
+        }
+        let metric = metric_for_entry_type(&entry.entry_type);
+        if metric.is_none() {
+            self.unmapped.add(&entry);
+        }
+        let date_entry = self.grouped.entry(date).or_default();
+        // Summing needs no bookkeeping, the values go straight into the entry
+        let input_county = (dedup != Dedup::Sum).then(|| entry.county.clone());
+        let state = entry.state;
+        let (mut name, same_county) = filter.county(&state, entry.county);
+        let mut fips = entry.fips.filter(|_| same_county);
+        if let Some((canonical, code)) = counties::canonical(&state, &name) {
+            name = canonical.to_string();
+            fips = fips.or_else(|| code.map(String::from));
+        }
+        // The FIPS code of a ZIP level entry is its county's, keep it off the entry so
+        // joins by FIPS only ever find the county
+        let (key, fips, zcta) = match entry.zcta.filter(|zcta| !zcta.is_empty()) {
+            Some(zcta) => (zcta_key(&state, &name, &zcta), None, Some(zcta)),
+            None => (county_key(&state, &name), fips, None),
+        };
+        let record_key = input_county
+            .zip(metric)
+            .map(|(county, metric)| (date, key.clone(), county, metric));
+        let county_entry = date_entry
+            .entry(key)
+            .or_insert_with(|| CountyEntry::new(name, state, None, zcta));
+        if county_entry.fips.is_none() {
+            county_entry.fips = fips;
+        }
+
+        match (record_key, metric) {
+            (Some(record_key), _) => {
+                self.records
+                    .insert(dedup, record_key, (position, entry.values))?
+            }
+            (None, Some(metric)) => county_entry.add_metric(metric, entry.values),
+            (None, None) => {}
+        }
+        Ok(())
+    }
+
+    fn merge(mut self, from: Grouping, dedup: Dedup) -> Result<Self> {
+        self.unmapped.merge(from.unmapped);
+        self.records.merge(dedup, from.records)?;
+
+        for (date, from_county_entries) in from.grouped {
+            let into_date_entry = self.grouped.entry(date).or_default();
+
+            for (county, from_county_entry) in from_county_entries {
+                let CountyEntry {
+                    name,
+                    state,
+                    fips,
+                    zcta,
+                    metrics,
+                    extra_fields,
+                } = from_county_entry;
+
+                let into_county_entry = into_date_entry
+                    .entry(county)
+                    .or_insert_with(|| CountyEntry::new(name, state, None, zcta));
+                if into_county_entry.fips.is_none() {
+                    into_county_entry.fips = fips;
+                }
+
+                for (metric, value) in metrics {
+                    into_county_entry.add_metric(metric, value);
+                }
+                into_county_entry.extra_fields.extend(extra_fields);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Take one date's entries out, with its records resolved
+    pub fn take_date(&mut self, date: DateTime<Utc>) -> GroupedEntries {
+        let mut grouped = GroupedEntries::new();
+        if let Some(entries) = self.grouped.remove(&date) {
+            grouped.insert(date, entries);
+        }
+        add_records(&mut grouped, self.records.take_date(date));
+        grouped
+    }
+
+    fn finish(mut self) -> (GroupedEntries, UnmappedTypes) {
+        add_records(&mut self.grouped, self.records);
+        (self.grouped, self.unmapped)
+    }
+}
+
+fn add_records(grouped: &mut GroupedEntries, records: Records) {
+    for (date, key, metric, value) in records.into_values() {
+        grouped
+            .get_mut(&date)
+            .and_then(|entries| entries.get_mut(&key))
+            .expect("records have an entry")
+            .add_metric(metric, value);
+    }
+}
+
+/// Group the entries by date, dropping those the filter doesn't keep
+pub fn group_by_date(
+    entries: Entries,
+    filter: &EntryFilter,
+    dedup: Dedup,
+    timezone: Timezone,
+    lags: Option<&Lags>,
+) -> Result<(GroupedEntries, UnmappedTypes)> {
+    let progress = Progress::new("group", "entries", None);
+    let grouping = entries
+        .enumerate()
+        .inspect(|(position, _)| {
+            if position % 4096 == 0 {
+                progress.set(*position as u64);
+            }
+        })
+        .par_bridge()
+        .try_fold(
+            Grouping::default,
+            |mut grouping, (position, entry)| -> Result<Grouping> {
+                let mut entry = entry?;
+                let date = entry_date(&mut entry, timezone, lags)?;
+                grouping.add(filter, dedup, position, entry, date)?;
+                Ok(grouping)
+            },
+        )
+        .try_reduce(Grouping::default, |into, from| into.merge(from, dedup))?;
+    Ok(grouping.finish())
+}
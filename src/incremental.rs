@@ -0,0 +1,224 @@
+//! Which dates `build --incremental` rebuilds: those without files in the output directory's
+//! manifest, and those whose entries, or the options they were built with, changed since
+//!
+//! Each date's fingerprint hashes its grouped entries with the options, so a date is up to
+//! date when the manifest has its files under the same fingerprint and they're still there,
+//! at the size they were written
+
+use crate::meta::SCHEMA_VERSION;
+use crate::output::{self, ManifestEntry};
+use crate::GroupedEntries;
+use anyhow::Result;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Fingerprints of the dates, by RFC 3339 timestamp like `Graph::timestamp`
+pub type Fingerprints = HashMap<String, String>;
+
+/// Everything that goes into the graphs besides the entries, like the flags and the joined
+/// files, by name. Only what changes the graphs should be added, or a change to something
+/// else rebuilds every date for nothing
+#[derive(Default)]
+pub struct Options(Vec<String>);
+
+impl Options {
+    pub fn add(&mut self, name: &str, value: impl fmt::Debug) -> &mut Self {
+        self.0.push(format!("{}={:?}", name, value));
+        self
+    }
+}
+
+/// Fingerprints of the dates of `grouped` built with `options`. With `history` each
+/// date's fingerprint takes in the ones before it, for metrics derived from earlier dates
+pub fn fingerprints(grouped: &GroupedEntries, options: &Options, history: bool) -> Fingerprints {
+    let mut dates = grouped
+        .par_iter()
+        .map(|(date, entries)| {
+            let mut hasher = Sha256::new();
+            hasher.update(env!("CARGO_PKG_VERSION"));
+            hasher.update(SCHEMA_VERSION.to_le_bytes());
+            for option in &options.0 {
+                hasher.update(option);
+                hasher.update([0]);
+            }
+            let mut keys = entries.keys().collect::<Vec<_>>();
+            keys.sort();
+            for key in keys {
+                hasher.update(key);
+                hasher.update(format!("{:?}", entries[key]));
+            }
+            (*date, format!("{:x}", hasher.finalize()))
+        })
+        .collect::<Vec<_>>();
+    if history {
+        dates.sort();
+        for i in 1..dates.len() {
+            let chained = Sha256::new()
+                .chain_update(&dates[i - 1].1)
+                .chain_update(&dates[i].1)
+                .finalize();
+            dates[i].1 = format!("{:x}", chained);
+        }
+    }
+    dates
+        .into_iter()
+        .map(|(date, fingerprint)| (date.to_rfc3339(), fingerprint))
+        .collect()
+}
+
+/// The manifest of the last build to a directory
+pub struct Previous(Vec<ManifestEntry>);
+
+impl Previous {
+    /// Empty if the directory has no manifest yet
+    pub fn load(dir: &Path) -> Result<Self> {
+        Ok(Previous(output::read_manifest(dir)?.unwrap_or_default()))
+    }
+
+    /// Timestamps of `fingerprints` that aren't up to date in `dir`
+    pub fn stale(&self, dir: &Path, fingerprints: &Fingerprints) -> BTreeSet<String> {
+        let mut files = HashMap::<&str, Vec<&ManifestEntry>>::new();
+        for entry in &self.0 {
+            if let Some(timestamp) = &entry.timestamp {
+                files.entry(timestamp).or_default().push(entry);
+            }
+        }
+        fingerprints
+            .iter()
+            .filter(|(timestamp, fingerprint)| {
+                let up_to_date = files.get(timestamp.as_str()).is_some_and(|files| {
+                    files.iter().all(|entry| {
+                        entry.input_sha256.as_ref() == Some(*fingerprint)
+                            && fs::metadata(dir.join(&entry.file))
+                                .is_ok_and(|metadata| metadata.len() == entry.bytes as u64)
+                    })
+                });
+                !up_to_date
+            })
+            .map(|(timestamp, _)| timestamp.clone())
+            .collect()
+    }
+
+    /// The entries of the files left as they are when `rebuilt` are written again. Those of
+    /// dates not in this build, like outside `--from` and `--to`, stay too
+    pub fn keep(self, rebuilt: &BTreeSet<String>) -> Vec<ManifestEntry> {
+        self.0
+            .into_iter()
+            .filter(|entry| {
+                entry
+                    .timestamp
+                    .as_ref()
+                    .is_some_and(|timestamp| !rebuilt.contains(timestamp))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CountyEntry;
+    use chrono::{DateTime, TimeZone, Utc};
+
+    fn date(day: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2020, 4, day, 0, 0, 0).unwrap()
+    }
+
+    /// A county's confirmed cases on each of the days
+    fn grouped(confirmed: &[i64]) -> GroupedEntries {
+        confirmed
+            .iter()
+            .enumerate()
+            .map(|(i, &confirmed)| {
+                let mut entry = CountyEntry::new(
+                    "Kings".to_string(),
+                    "New York".to_string(),
+                    Some("36047".to_string()),
+                    None,
+                );
+                entry.add_metric("confirmed", confirmed);
+                let mut entries = HashMap::new();
+                entries.insert("New York - Kings".to_string(), entry);
+                (date(i as u32 + 1), entries)
+            })
+            .collect()
+    }
+
+    fn options(deltas: bool) -> Options {
+        let mut options = Options::default();
+        options.add("deltas", deltas);
+        options
+    }
+
+    /// Which of the days' fingerprints differ between `a` and `b`
+    fn changed(a: &Fingerprints, b: &Fingerprints) -> Vec<u32> {
+        let mut days = (1..=a.len() as u32)
+            .filter(|&day| a[&date(day).to_rfc3339()] != b[&date(day).to_rfc3339()])
+            .collect::<Vec<_>>();
+        days.sort();
+        days
+    }
+
+    #[test]
+    fn a_change_only_changes_its_date() {
+        let before = fingerprints(&grouped(&[1, 2, 3]), &options(false), false);
+        let after = fingerprints(&grouped(&[1, 5, 3]), &options(false), false);
+        assert_eq!(changed(&before, &after), vec![2]);
+    }
+
+    #[test]
+    fn with_history_a_change_carries_forward() {
+        let before = fingerprints(&grouped(&[1, 2, 3, 4]), &options(true), true);
+        let after = fingerprints(&grouped(&[1, 5, 3, 4]), &options(true), true);
+        assert_eq!(changed(&before, &after), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn an_option_change_changes_every_date() {
+        let before = fingerprints(&grouped(&[1, 2, 3]), &options(false), false);
+        let after = fingerprints(&grouped(&[1, 2, 3]), &options(true), false);
+        assert_eq!(changed(&before, &after), vec![1, 2, 3]);
+        let again = fingerprints(&grouped(&[1, 2, 3]), &options(false), false);
+        assert_eq!(changed(&before, &again), Vec::<u32>::new());
+    }
+
+    #[test]
+    fn stale_dates() {
+        let dir = std::env::temp_dir().join(format!("covid-incremental-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let fingerprints = fingerprints(&grouped(&[1, 2, 3, 4]), &options(false), false);
+        let entry = |day: u32, written: &str, fingerprint: &str| {
+            let file = format!("{}.json", day);
+            fs::write(dir.join(&file), written).unwrap();
+            ManifestEntry {
+                file,
+                timestamp: Some(date(day).to_rfc3339()),
+                nodes: Some(1),
+                bytes: 2,
+                sha256: String::new(),
+                input_sha256: Some(fingerprint.to_string()),
+            }
+        };
+        let fingerprint = |day: u32| fingerprints[&date(day).to_rfc3339()].clone();
+        let previous = Previous(vec![
+            // Up to date
+            entry(1, "{}", &fingerprint(1)),
+            // Built from other entries
+            entry(2, "{}", "0"),
+            // Changed since it was written
+            entry(3, "{ }", &fingerprint(3)),
+            // and the 4th was never built
+        ]);
+        let stale = previous.stale(&dir, &fingerprints);
+        let expected = [2, 3, 4].iter().map(|&day| date(day).to_rfc3339());
+        assert_eq!(stale, expected.collect());
+        let kept = previous.keep(&stale);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].file, "1.json");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,24 @@
+//! Reading the raw entries of the inputs, through the `source` adapters
+
+use crate::error::Result;
+use crate::source::{self, DataSource, Entries, SourceOpt};
+use std::path::PathBuf;
+
+pub type Sources = Vec<Box<dyn DataSource>>;
+
+/// Expand globs and directories among the input paths and open each input with the
+/// source's adapter. Returns the inputs found with their sources, in the same order
+pub fn open_sources(paths: Vec<PathBuf>, opt: &SourceOpt) -> Result<(Vec<PathBuf>, Sources)> {
+    let inputs = source::expand_inputs(paths, opt)?;
+    let sources = inputs
+        .iter()
+        .map(|input| source::create(input.clone(), opt))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok((inputs, sources))
+}
+
+/// Every entry of the sources in one stream, with records repeated across inputs only
+/// once. Inputs that can't be parsed incrementally are parsed up front, in parallel
+pub fn parse_entries(sources: &[Box<dyn DataSource>]) -> Result<Entries<'_>> {
+    Ok(source::merge(sources)?)
+}
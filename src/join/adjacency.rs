@@ -0,0 +1,68 @@
+//! Census county adjacency file (`county_adjacency.txt`)
+//!
+//! Tab separated `"County, ST"  FIPS  "Neighbour, ST"  FIPS` rows, where the first
+//! two columns are only filled in on the first neighbour of each county.
+//! Counties are resolved by FIPS code, falling back on name.
+
+use super::{bare_county_name, keys_by_fips};
+use crate::error::Result;
+use crate::group::county_key;
+use crate::{states, GroupedEntries};
+use anyhow::Context;
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+/// Neighbouring county keys by county key
+pub type Adjacency = HashMap<String, BTreeSet<String>>;
+
+/// The published file is Latin-1 encoded
+fn decode(data: Vec<u8>) -> String {
+    match String::from_utf8(data) {
+        Ok(s) => s,
+        Err(e) => e.into_bytes().iter().map(|&b| b as char).collect(),
+    }
+}
+
+/// Turn `"Kings County, NY"` into a county key
+fn key_from_name(name: &str) -> Option<String> {
+    let (county, state) = name.trim_matches('"').rsplit_once(", ")?;
+    let state = states::normalize(state).unwrap_or(state);
+    Some(county_key(state, bare_county_name(county)))
+}
+
+pub fn load(path: &Path, grouped: &GroupedEntries) -> Result<Adjacency> {
+    let keys_by_fips = keys_by_fips(grouped);
+    let resolve = |name: &str, fips: &str| {
+        keys_by_fips
+            .get(fips.trim())
+            .cloned()
+            .or_else(|| key_from_name(name))
+    };
+
+    let data = fs::read(path)
+        .with_context(|| format!("Failed to read adjacency file {}", path.display()))?;
+    let mut adjacency = Adjacency::new();
+    let mut current = None;
+    for line in decode(data).lines() {
+        let columns = line.split('\t').collect::<Vec<_>>();
+        if columns.len() < 4 {
+            continue;
+        }
+        if !columns[0].is_empty() {
+            current = resolve(columns[0], columns[1]);
+        }
+        let (county, neighbour) = match (&current, resolve(columns[2], columns[3])) {
+            (Some(county), Some(neighbour)) => (county, neighbour),
+            _ => continue,
+        };
+        // Every county is listed as its own neighbour
+        if *county != neighbour {
+            adjacency
+                .entry(county.clone())
+                .or_default()
+                .insert(neighbour);
+        }
+    }
+    Ok(adjacency)
+}
@@ -0,0 +1,93 @@
+//! County to core based statistical area crosswalk, the Census delineation file saved as
+//! CSV or NBER's `cbsa2fipsxw.csv`
+//!
+//! Columns are found by name with case, spaces and punctuation ignored, so `CBSA Code`
+//! and `cbsacode` both work. Counties are matched by FIPS code, falling back on state +
+//! county name when the file has the `County/County Equivalent` and `State Name` columns.
+
+use super::bare_county_name;
+use crate::error::{CovidDataError, Result};
+use crate::group::county_key;
+use anyhow::Context;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct Metro {
+    pub code: String,
+    pub title: String,
+}
+
+#[derive(Default)]
+pub struct Crosswalk {
+    by_fips: HashMap<String, Metro>,
+    by_key: HashMap<String, Metro>,
+}
+
+impl Crosswalk {
+    pub fn metro(&self, key: &str, fips: Option<&str>) -> Option<&Metro> {
+        fips.and_then(|fips| self.by_fips.get(fips))
+            .or_else(|| self.by_key.get(key))
+    }
+
+    /// Number of metro areas in the file
+    pub fn num_metros(&self) -> usize {
+        let mut codes = self
+            .by_fips
+            .values()
+            .chain(self.by_key.values())
+            .map(|metro| metro.code.as_str())
+            .collect::<Vec<_>>();
+        codes.sort_unstable();
+        codes.dedup();
+        codes.len()
+    }
+}
+
+fn normalize(header: &str) -> String {
+    header
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+pub fn load(path: &Path) -> Result<Crosswalk> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .from_path(path)
+        .with_context(|| format!("Failed to open CBSA crosswalk {}", path.display()))?;
+
+    let headers = reader.headers()?.iter().map(normalize).collect::<Vec<_>>();
+    let find = |name: &str| headers.iter().position(|h| h == name);
+    let column =
+        |name: &str| find(name).ok_or_else(|| CovidDataError::join_miss("CBSA crosswalk", name));
+    let (code, title, state_fips, county_fips) = (
+        column("cbsacode")?,
+        column("cbsatitle")?,
+        column("fipsstatecode")?,
+        column("fipscountycode")?,
+    );
+    let names = find("countycountyequivalent").zip(find("statename"));
+
+    let mut crosswalk = Crosswalk::default();
+    for record in reader.records() {
+        let record = record.context("Failed to parse CBSA crosswalk row")?;
+        let field = |i: usize| record.get(i).unwrap_or("").trim();
+        // The delineation file ends in a few lines of notes
+        if field(code).is_empty() || field(county_fips).is_empty() {
+            continue;
+        }
+        let metro = Metro {
+            code: field(code).to_string(),
+            title: field(title).to_string(),
+        };
+        let fips = format!("{:0>2}{:0>3}", field(state_fips), field(county_fips));
+        if let Some((county, state)) = names {
+            let key = county_key(field(state), bare_county_name(field(county)));
+            crosswalk.by_key.insert(key, metro.clone());
+        }
+        crosswalk.by_fips.insert(fips, metro);
+    }
+    Ok(crosswalk)
+}
@@ -0,0 +1,57 @@
+//! Census LODES origin-destination files (`<st>_od_main_JT00_<year>.csv.gz`)
+//!
+//! Each row counts the jobs (`S000`) held by residents of the home census block
+//! `h_geocode` in the work block `w_geocode`. Blocks are summed into their counties,
+//! the first five digits of the geocode, leaving out people working in their home county.
+
+use super::keys_by_fips;
+use crate::error::{CovidDataError, Result};
+use crate::source::open_input;
+use crate::GroupedEntries;
+use anyhow::Context;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+
+/// Commuters from a county key to the county keys they work in
+pub type Commuting = HashMap<String, BTreeMap<String, f64>>;
+
+pub fn load(paths: &[PathBuf], grouped: &GroupedEntries) -> Result<Commuting> {
+    let keys_by_fips = keys_by_fips(grouped);
+    let mut commuting = Commuting::new();
+    for path in paths {
+        let mut reader = csv::Reader::from_reader(open_input(path)?);
+        let headers = reader.headers()?.clone();
+        let column = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.eq_ignore_ascii_case(name))
+                .ok_or_else(|| CovidDataError::join_miss(path.display().to_string(), name))
+        };
+        let (work, home, jobs) = (column("w_geocode")?, column("h_geocode")?, column("S000")?);
+
+        for record in reader.records() {
+            let record = record.with_context(|| format!("Failed to parse {}", path.display()))?;
+            let county = |i: usize| {
+                let geocode = record.get(i).unwrap_or("");
+                geocode.get(..5).and_then(|fips| keys_by_fips.get(fips))
+            };
+            let (home, work) = match (county(home), county(work)) {
+                (Some(home), Some(work)) if home != work => (home, work),
+                _ => continue,
+            };
+            let jobs = record
+                .get(jobs)
+                .unwrap_or("")
+                .parse::<f64>()
+                .with_context(|| {
+                    format!("Invalid S000 {:?} in {}", record.get(jobs), path.display())
+                })?;
+            *commuting
+                .entry(home.clone())
+                .or_default()
+                .entry(work.clone())
+                .or_default() += jobs;
+        }
+    }
+    Ok(commuting)
+}
@@ -0,0 +1,107 @@
+//! Weekly observed and expected all-cause deaths per state, in the layout of the CDC's
+//! "Excess Deaths Associated with COVID-19" file
+//!
+//! Columns are found by name with case, spaces and punctuation ignored: the week ending
+//! date, state, observed number and average expected count. When the file has `Type` and
+//! `Outcome` columns only the weighted predictions of all causes are used.
+
+use crate::error::{CovidDataError, Result};
+use crate::{states, Graph};
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Deaths over the expected count, by full state name, as (week ending, excess) in date
+/// order. `United States` rows stay under that name, which is the `--national` node's
+pub type Excess = HashMap<String, Vec<(NaiveDate, i64)>>;
+
+fn normalize(header: &str) -> String {
+    header
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(s, "%m/%d/%Y"))
+        .map_err(|_| CovidDataError::Parse(format!("Invalid week ending date {}", s)))
+}
+
+/// Counts are written with thousands separators, or left empty when suppressed
+fn parse_count(s: &str) -> Option<f64> {
+    s.replace(',', "").trim().parse().ok()
+}
+
+pub fn load(path: &Path) -> Result<Excess> {
+    let mut reader = csv::Reader::from_path(path)
+        .with_context(|| format!("Failed to open baseline mortality file {}", path.display()))?;
+    let headers = reader.headers()?.iter().map(normalize).collect::<Vec<_>>();
+    let find = |name: &str| headers.iter().position(|h| h == name);
+    let column = |name: &str| {
+        find(name).ok_or_else(|| CovidDataError::join_miss("Baseline mortality file", name))
+    };
+    let (date, state, observed, expected) = (
+        column("weekendingdate")?,
+        column("state")?,
+        column("observednumber")?,
+        column("averageexpectedcount")?,
+    );
+    let (kind, outcome) = (find("type"), find("outcome"));
+
+    let mut excess = Excess::new();
+    for record in reader.records() {
+        let record = record.context("Failed to parse baseline mortality row")?;
+        let field = |i: usize| record.get(i).unwrap_or("").trim();
+        if kind.is_some_and(|i| field(i) != "Predicted (weighted)")
+            || outcome.is_some_and(|i| field(i) != "All causes")
+        {
+            continue;
+        }
+        let (observed, expected) =
+            match (parse_count(field(observed)), parse_count(field(expected))) {
+                (Some(observed), Some(expected)) => (observed, expected),
+                _ => continue,
+            };
+        let state = states::normalize(field(state))
+            .map(String::from)
+            .unwrap_or_else(|| field(state).to_string());
+        excess.entry(state).or_default().push((
+            parse_date(field(date))?,
+            (observed - expected).round() as i64,
+        ));
+    }
+    for weeks in excess.values_mut() {
+        weeks.sort_unstable();
+    }
+    Ok(excess)
+}
+
+/// Add `excess_deaths_estimate` to the state and national nodes: the deaths over the
+/// expected count in the weeks ending on or before the graph's date
+pub fn add(graphs: &mut [Graph], excess: &Excess) -> Result<()> {
+    for graph in graphs {
+        let date = DateTime::parse_from_rfc3339(&graph.timestamp)?.date_naive();
+        for node in &mut graph.nodes {
+            if !matches!(node.level, "state" | "country") {
+                continue;
+            }
+            let weeks = match excess.get(&node.name) {
+                Some(weeks) => weeks,
+                None => continue,
+            };
+            let to_date = weeks
+                .iter()
+                .take_while(|(week_ending, _)| *week_ending <= date)
+                .map(|(_, excess)| excess)
+                .sum::<i64>();
+            if weeks.first().is_some_and(|(first, _)| *first <= date) {
+                node.metrics
+                    .insert("excess_deaths_estimate".into(), to_date.into());
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,100 @@
+//! HHS "COVID-19 Reported Patient Impact and Hospital Capacity by Facility" dataset
+//!
+//! Facilities report 7 day averages once per collection week. Each facility is
+//! matched to a county by FIPS code, falling back on state + county name, and its
+//! averages are added to that county for every day of the collection week.
+
+use super::{bare_county_name, keys_by_fips};
+use crate::error::{CovidDataError, Result};
+use crate::group::county_key;
+use crate::{states, GroupedEntries};
+use anyhow::Context;
+use chrono::{Duration, NaiveDate, NaiveTime};
+use serde::Deserialize;
+use std::path::Path;
+
+/// HHS marks suppressed small counts with this value
+const SUPPRESSED: f64 = -999999.0;
+
+#[derive(Deserialize)]
+struct FacilityRow {
+    collection_week: String,
+    state: String,
+    #[serde(default)]
+    fips_code: Option<String>,
+    #[serde(default)]
+    county: Option<String>,
+    #[serde(default)]
+    inpatient_beds_used_covid_7_day_avg: Option<f64>,
+    #[serde(default)]
+    staffed_icu_adult_patients_confirmed_and_suspected_covid_7_day_avg: Option<f64>,
+}
+
+fn parse_week(s: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%Y/%m/%d")
+        .or_else(|_| NaiveDate::parse_from_str(s, "%Y-%m-%d"))
+        .map_err(|_| CovidDataError::Parse(format!("Invalid collection week {}", s)))
+}
+
+/// Add the hospital metrics to matching county entries.
+/// Returns the number of facility rows that didn't match any county.
+pub fn merge(grouped: &mut GroupedEntries, path: &Path) -> Result<usize> {
+    let keys_by_fips = keys_by_fips(grouped);
+
+    let mut unmatched = 0;
+    let mut reader = csv::Reader::from_path(path)
+        .with_context(|| format!("Failed to open hospitals file {}", path.display()))?;
+
+    for row in reader.deserialize::<FacilityRow>() {
+        let row = row.context("Failed to parse hospitals row")?;
+        let fips = row
+            .fips_code
+            .as_deref()
+            .and_then(crate::source::normalize_fips);
+        let key = match (fips.and_then(|f| keys_by_fips.get(&f)), &row.county) {
+            (Some(key), _) => key.clone(),
+            (None, Some(county)) => {
+                let state = states::normalize(&row.state).unwrap_or(&row.state);
+                county_key(state, bare_county_name(county))
+            }
+            (None, None) => {
+                unmatched += 1;
+                continue;
+            }
+        };
+
+        let metrics = [
+            (
+                "inpatient_beds_used_covid",
+                row.inpatient_beds_used_covid_7_day_avg,
+            ),
+            (
+                "icu_beds_used_covid",
+                row.staffed_icu_adult_patients_confirmed_and_suspected_covid_7_day_avg,
+            ),
+        ];
+
+        let week = parse_week(&row.collection_week)?
+            .and_time(NaiveTime::MIN)
+            .and_utc();
+        let mut matched = false;
+        for day in 0..7 {
+            let date = week + Duration::days(day);
+            let county_entry = match grouped.get_mut(&date).and_then(|d| d.get_mut(&key)) {
+                Some(county_entry) => county_entry,
+                None => continue,
+            };
+            matched = true;
+            for (metric, value) in &metrics {
+                match value {
+                    Some(value) if *value != SUPPRESSED => county_entry.add_metric(*metric, *value),
+                    _ => (),
+                }
+            }
+        }
+        if !matched {
+            unmatched += 1;
+        }
+    }
+    Ok(unmatched)
+}
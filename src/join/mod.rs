@@ -0,0 +1,43 @@
+//! Secondary datasets merged into the grouped case data
+
+use crate::GroupedEntries;
+use std::collections::HashMap;
+
+pub mod adjacency;
+pub mod cbsa;
+pub mod commuting;
+pub mod excess;
+pub mod hospitals;
+pub mod population;
+pub mod testing;
+pub mod vaccinations;
+
+/// Strip the suffix other datasets append to county names ("Kings County", "Orleans Parish")
+/// so they match the bare names used by the case feeds
+pub fn bare_county_name(name: &str) -> &str {
+    const SUFFIXES: &[&str] = &[
+        " County",
+        " Parish",
+        " City and Borough",
+        " Borough",
+        " Census Area",
+        " Municipality",
+    ];
+    SUFFIXES
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .unwrap_or(name)
+}
+
+/// County keys by FIPS code, for datasets that only identify counties by code
+pub fn keys_by_fips(grouped: &GroupedEntries) -> HashMap<String, String> {
+    let mut keys_by_fips = HashMap::new();
+    for county_entries in grouped.values() {
+        for (key, county_entry) in county_entries {
+            if let Some(fips) = &county_entry.fips {
+                keys_by_fips.insert(fips.clone(), key.clone());
+            }
+        }
+    }
+    keys_by_fips
+}
@@ -0,0 +1,107 @@
+//! Census county population estimates (`co-est20xx-alldata.csv`)
+//!
+//! Counties are matched by FIPS code, falling back on state + county name.
+//! The most recent `POPESTIMATE` column in the file is used.
+
+use super::bare_county_name;
+use crate::error::{CovidDataError, Result};
+use crate::group::county_key;
+use crate::GroupedEntries;
+use anyhow::Context;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+/// Census summary level of county rows, state rows are "040"
+const COUNTY_SUMLEV: &str = "050";
+
+#[derive(Default)]
+pub struct Population {
+    counties_by_fips: HashMap<String, i64>,
+    counties_by_key: HashMap<String, i64>,
+    states: HashMap<String, i64>,
+}
+
+impl Population {
+    pub fn county(&self, key: &str, fips: Option<&str>) -> Option<i64> {
+        fips.and_then(|fips| self.counties_by_fips.get(fips))
+            .or_else(|| self.counties_by_key.get(key))
+            .copied()
+    }
+
+    pub fn state(&self, name: &str) -> Option<i64> {
+        self.states.get(name).copied()
+    }
+}
+
+pub fn load(path: &Path) -> Result<Population> {
+    let mut reader = csv::Reader::from_path(path)
+        .with_context(|| format!("Failed to open population file {}", path.display()))?;
+
+    let headers = reader.headers()?.clone();
+    let column = |name: &str| {
+        headers
+            .iter()
+            .position(|h| h == name)
+            .ok_or_else(|| CovidDataError::join_miss("Population file", name))
+    };
+    let (sumlev, state, county, stname, ctyname) = (
+        column("SUMLEV")?,
+        column("STATE")?,
+        column("COUNTY")?,
+        column("STNAME")?,
+        column("CTYNAME")?,
+    );
+    let estimate = headers
+        .iter()
+        .enumerate()
+        .filter(|(_, h)| {
+            h.strip_prefix("POPESTIMATE")
+                .is_some_and(|year| year.len() == 4)
+        })
+        .max_by_key(|(_, h)| *h)
+        .map(|(i, _)| i)
+        .ok_or_else(|| CovidDataError::join_miss("Population file", "POPESTIMATE"))?;
+
+    let mut population = Population::default();
+    for record in reader.records() {
+        let record = record.context("Failed to parse population row")?;
+        let count = record[estimate]
+            .parse::<i64>()
+            .with_context(|| format!("Invalid population estimate {}", &record[estimate]))?;
+        let state_name = record[stname].to_string();
+
+        if &record[sumlev] == COUNTY_SUMLEV {
+            let fips = format!("{}{}", &record[state], &record[county]);
+            let key = county_key(&state_name, bare_county_name(&record[ctyname]));
+            population.counties_by_fips.insert(fips, count);
+            population.counties_by_key.insert(key, count);
+        } else {
+            population.states.insert(state_name, count);
+        }
+    }
+    Ok(population)
+}
+
+/// Store the population of every matched county in its extra fields.
+/// Returns the keys of counties that couldn't be matched.
+pub fn merge(grouped: &mut GroupedEntries, population: &Population) -> BTreeSet<String> {
+    let mut unmatched = BTreeSet::new();
+    for county_entries in grouped.values_mut() {
+        for (key, county_entry) in county_entries.iter_mut() {
+            if county_entry.name.is_empty() || county_entry.zcta.is_some() {
+                continue;
+            }
+            match population.county(key, county_entry.fips.as_deref()) {
+                Some(count) => {
+                    county_entry
+                        .extra_fields
+                        .insert("population", count.to_string());
+                }
+                None => {
+                    unmatched.insert(key.clone());
+                }
+            }
+        }
+    }
+    unmatched
+}
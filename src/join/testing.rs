@@ -0,0 +1,98 @@
+//! Per-state daily testing counts, in the COVID Tracking Project `daily.csv` layout
+//!
+//! Testing is only reported per state, so it is grouped in its own pass keyed on
+//! state and merged into the state nodes, along with the derived positivity rate.
+
+use crate::error::{CovidDataError, Result};
+use crate::group::StateMetrics;
+use crate::states;
+use crate::value::{MetricName, MetricValue};
+use anyhow::Context;
+use chrono::{NaiveDate, NaiveTime};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct TestingRow {
+    date: String,
+    state: String,
+    #[serde(rename = "totalTestResults", default)]
+    total_test_results: Option<i64>,
+    #[serde(default)]
+    positive: Option<i64>,
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(s, "%Y%m%d")
+        .or_else(|_| NaiveDate::parse_from_str(s, "%Y-%m-%d"))
+        .map_err(|_| CovidDataError::Parse(format!("Invalid testing date {}", s)))
+}
+
+/// Metrics worked out from a node's own counts, which don't add up across nodes
+pub const RATIOS: &[&str] = &["positivity_rate_bp"];
+
+/// Work the ratio metrics out again from the counts, for a node totalling others
+pub fn rederive_ratios(metrics: &mut BTreeMap<MetricName, MetricValue>) {
+    let count = |metric| {
+        metrics
+            .get(metric)
+            .map(|value: &MetricValue| value.as_i64())
+    };
+    if let Some(rate) = count("positive_tests")
+        .zip(count("tests"))
+        .and_then(|(positive, tests)| positivity_rate_bp(positive, tests))
+    {
+        metrics.insert("positivity_rate_bp".into(), rate.into());
+    }
+}
+
+/// Share of positive tests in basis points, so it stays an integer metric
+fn positivity_rate_bp(positive: i64, tests: i64) -> Option<i64> {
+    if tests > 0 {
+        Some(positive * 10000 / tests)
+    } else {
+        None
+    }
+}
+
+pub fn group_by_state(path: &Path) -> Result<StateMetrics> {
+    let mut grouped: HashMap<_, HashMap<String, (i64, i64)>> = HashMap::new();
+    let mut reader = csv::Reader::from_path(path)
+        .with_context(|| format!("Failed to open testing file {}", path.display()))?;
+
+    for row in reader.deserialize::<TestingRow>() {
+        let row = row.context("Failed to parse testing row")?;
+        let date = parse_date(&row.date)?.and_time(NaiveTime::MIN).and_utc();
+        let state = states::normalize(&row.state)
+            .map(String::from)
+            .unwrap_or(row.state);
+
+        let totals = grouped
+            .entry(date)
+            .or_default()
+            .entry(state)
+            .or_insert((0, 0));
+        totals.0 += row.total_test_results.unwrap_or(0);
+        totals.1 += row.positive.unwrap_or(0);
+    }
+
+    Ok(grouped
+        .into_iter()
+        .map(|(date, by_state)| {
+            let by_state = by_state
+                .into_iter()
+                .map(|(state, (tests, positive))| {
+                    let mut metrics = BTreeMap::new();
+                    metrics.insert("tests", tests);
+                    metrics.insert("positive_tests", positive);
+                    if let Some(rate) = positivity_rate_bp(positive, tests) {
+                        metrics.insert("positivity_rate_bp", rate);
+                    }
+                    (state, metrics)
+                })
+                .collect();
+            (date, by_state)
+        })
+        .collect())
+}
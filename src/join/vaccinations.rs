@@ -0,0 +1,59 @@
+//! CDC "COVID-19 Vaccinations in the United States, County" dataset
+//!
+//! Rows are matched to county entries by date and state + county name, adding
+//! `doses_administered` and `fully_vaccinated` metrics.
+
+use super::bare_county_name;
+use crate::error::Result;
+use crate::group::county_key;
+use crate::{states, GroupedEntries};
+use anyhow::Context;
+use chrono::{NaiveDate, NaiveTime};
+use serde::Deserialize;
+use std::path::Path;
+
+#[derive(Deserialize)]
+struct VaccinationRow {
+    #[serde(rename = "Date")]
+    date: String,
+    #[serde(rename = "Recip_County")]
+    county: String,
+    #[serde(rename = "Recip_State")]
+    state: String,
+    #[serde(rename = "Administered", alias = "Administered_Dose1_Recip", default)]
+    doses_administered: Option<i64>,
+    #[serde(rename = "Series_Complete_Yes", default)]
+    fully_vaccinated: Option<i64>,
+}
+
+/// Add the vaccination metrics to matching county entries.
+/// Returns the number of rows that didn't match any county.
+pub fn merge(grouped: &mut GroupedEntries, path: &Path) -> Result<usize> {
+    let mut unmatched = 0;
+    let mut reader = csv::Reader::from_path(path)
+        .with_context(|| format!("Failed to open vaccinations file {}", path.display()))?;
+
+    for row in reader.deserialize::<VaccinationRow>() {
+        let row = row.context("Failed to parse vaccinations row")?;
+        let date = NaiveDate::parse_from_str(&row.date, "%m/%d/%Y")
+            .with_context(|| format!("Invalid vaccination date {}", row.date))?;
+        let date = date.and_time(NaiveTime::MIN).and_utc();
+        let state = states::normalize(&row.state).unwrap_or(&row.state);
+        let key = county_key(state, bare_county_name(&row.county));
+
+        let county_entry = match grouped.get_mut(&date).and_then(|d| d.get_mut(&key)) {
+            Some(county_entry) => county_entry,
+            None => {
+                unmatched += 1;
+                continue;
+            }
+        };
+        if let Some(doses) = row.doses_administered {
+            county_entry.add_metric("doses_administered", doses);
+        }
+        if let Some(fully_vaccinated) = row.fully_vaccinated {
+            county_entry.add_metric("fully_vaccinated", fully_vaccinated);
+        }
+    }
+    Ok(unmatched)
+}
@@ -0,0 +1,48 @@
+//! `--lag`: days states take to report, moved back off the entries' dates so the graphs
+//! line up by when cases happened rather than when they were reported
+
+use crate::states;
+use anyhow::anyhow;
+use chrono::Duration;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// Reporting lag by full state name
+#[derive(Debug, Clone)]
+pub struct Lags(BTreeMap<&'static str, Duration>);
+
+impl FromStr for Lags {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lags = BTreeMap::new();
+        for lag in s.split(',').map(str::trim).filter(|lag| !lag.is_empty()) {
+            let (state, days) = lag
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Invalid lag {}, expected STATE=DAYS", lag))?;
+            let state = states::normalize(state)
+                .ok_or_else(|| anyhow!("Unknown state {}", state.trim()))?;
+            let days = days
+                .trim()
+                .parse::<i64>()
+                .map_err(|_| anyhow!("Invalid lag {}, expected a number of days", lag))?;
+            lags.insert(state, Duration::days(days));
+        }
+        Ok(Lags(lags))
+    }
+}
+
+impl Lags {
+    /// How far to move a state's entries back
+    pub fn get(&self, state: &str) -> Option<Duration> {
+        self.0.get(state).copied()
+    }
+
+    /// The longest lag, none for lags that move entries forward
+    pub fn max(&self) -> Duration {
+        self.0
+            .values()
+            .copied()
+            .fold(Duration::zero(), Duration::max)
+    }
+}
@@ -0,0 +1,53 @@
+//! Graphs of covid cases and deaths per date, built from the county level feeds: a node
+//! per county and ZIP code, the states above them and the regions beside, with edges
+//! between them
+//!
+//! The pipeline is `parse_entries` reading the inputs, `group_by_date` grouping the
+//! entries per date and county, `build_graphs` turning those into graphs, the `transform`
+//! stages run over them and `output::write`. `stream::by_date` overlaps the first stages
+//! with the rest, a date at a time, and `incremental` picks the dates an earlier build's
+//! output is missing or out of date on. `GraphBuilder` puts the stages together for
+//! embedding, the `covid` binary is a CLI over them.
+
+pub mod alert;
+pub mod builder;
+pub mod check;
+pub mod config;
+pub mod counties;
+pub mod dedup;
+pub mod diff;
+pub mod error;
+pub mod expr;
+pub mod fetch;
+pub mod filter;
+pub mod forecast;
+pub mod granularity;
+pub mod graph;
+pub mod group;
+pub mod incremental;
+pub mod ingest;
+pub mod join;
+pub mod lag;
+pub mod log;
+pub mod meta;
+pub mod metrics;
+pub mod output;
+pub mod progress;
+pub mod query;
+pub mod report;
+pub mod schema;
+pub mod serve;
+pub mod source;
+pub mod states;
+pub mod stream;
+pub mod timezone;
+pub mod transform;
+pub mod validate;
+pub mod value;
+
+pub use builder::GraphBuilder;
+pub use error::CovidDataError;
+pub use graph::{build_graphs, Edge, EdgeKind, Graph, GraphOpt, Node};
+pub use group::{group_by_date, CountyEntry, GroupedEntries};
+pub use ingest::parse_entries;
+pub use value::{MetricName, MetricValue};
@@ -0,0 +1,336 @@
+//! Logging through `tracing`. Each stage of a command is a `stage` span, and what it
+//! reports, like sizes and entry counts, are events in the span without a message
+//!
+//! `Logger` is the subscriber the binary installs: it prints a stage when it closes, with
+//! its data and duration, `--log-format pretty` for people or `json` for a log aggregator.
+//! Embedders install their own subscriber instead
+
+use anyhow::Result;
+use chrono::Utc;
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+/// Run `f` in the span of the stage `name`. An error is logged in the span, and gets
+/// the stage as context
+pub fn stage<T>(name: &str, f: impl FnOnce() -> Result<T>) -> Result<T> {
+    let span = tracing::info_span!("stage", name);
+    let _entered = span.enter();
+    f().map_err(|err| {
+        tracing::error!(error = ?err);
+        err.context(format!("[in stage] {}", name))
+    })
+}
+
+/// `stage` for the library's entry points: the error is logged in the span but passed on
+/// as it is, for embedders to match on
+pub fn lib_stage<T>(
+    name: &str,
+    f: impl FnOnce() -> crate::error::Result<T>,
+) -> crate::error::Result<T> {
+    let span = tracing::info_span!("stage", name);
+    let _entered = span.enter();
+    f().inspect_err(|err| tracing::error!(error = ?err))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(anyhow::anyhow!("Unknown log format {}", s)),
+        }
+    }
+}
+
+/// Install a `Logger` as the subscriber of every thread
+pub fn init(format: LogFormat) -> Result<()> {
+    tracing::subscriber::set_global_default(Logger::new(format))?;
+    Ok(())
+}
+
+/// The fields of a span or event, in the order they were recorded
+#[derive(Default)]
+struct Fields(Vec<(String, Value)>);
+
+impl Fields {
+    fn take(&mut self, name: &str) -> Option<Value> {
+        let i = self.0.iter().position(|(field, _)| field == name)?;
+        Some(self.0.remove(i).1)
+    }
+
+    fn to_json(&self) -> Value {
+        Value::Object(self.0.iter().cloned().collect::<Map<_, _>>())
+    }
+}
+
+impl Visit for Fields {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .push((field.name().to_string(), format!("{:?}", value).into()));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name().to_string(), value.into()));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.push((field.name().to_string(), value.into()));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.push((field.name().to_string(), value.into()));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.push((field.name().to_string(), value.into()));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.push((field.name().to_string(), value.into()));
+    }
+}
+
+/// Text of a field value, strings without their quotes
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        value => value.to_string(),
+    }
+}
+
+struct Stage {
+    name: String,
+    timestamp: String,
+    start: Instant,
+    fields: Fields,
+    error: Option<String>,
+    refs: usize,
+}
+
+thread_local! {
+    /// The spans entered on this thread, innermost last
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+pub struct Logger {
+    format: LogFormat,
+    next_id: AtomicU64,
+    stages: Mutex<HashMap<u64, Stage>>,
+}
+
+impl Logger {
+    pub fn new(format: LogFormat) -> Self {
+        Logger {
+            format,
+            next_id: AtomicU64::new(1),
+            stages: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn timestamp() -> String {
+        Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string()
+    }
+
+    fn print(&self, line: String) {
+        // Nowhere to report a failure to log to
+        let _ = io::stderr().lock().write_all(line.as_bytes());
+    }
+
+    fn print_stage(&self, stage: Stage) {
+        let duration = stage.start.elapsed().as_millis();
+        let line = match self.format {
+            LogFormat::Json => {
+                let mut line = json!({
+                    "timestamp": stage.timestamp,
+                    "level": if stage.error.is_some() { "ERROR" } else { "INFO" },
+                    "stage": stage.name,
+                    "duration_ms": duration as u64,
+                    "fields": stage.fields.to_json(),
+                });
+                if let Some(error) = stage.error {
+                    line["error"] = error.into();
+                }
+                format!("{}\n", line)
+            }
+            LogFormat::Pretty => {
+                let name = match stage.error {
+                    Some(_) => format!("[ERR] {}", stage.name),
+                    None => stage.name,
+                };
+                let mut line = format!("[{}] {:<60}|{:>6}ms\n", stage.timestamp, name, duration);
+                for (field, value) in &stage.fields.0 {
+                    line.push_str(&format!("  |      {}: {}\n", field, text(value)));
+                }
+                if let Some(error) = &stage.error {
+                    line.push_str("  |\n");
+                    for error_line in error.lines() {
+                        line.push_str(&format!("  |  {}\n", error_line));
+                    }
+                }
+                line
+            }
+        };
+        self.print(line);
+    }
+
+    fn print_event(&self, level: &Level, stage: Option<&str>, mut fields: Fields) {
+        let message = fields.take("message").map(|message| text(&message));
+        let line = match self.format {
+            LogFormat::Json => {
+                let mut line = json!({
+                    "timestamp": Self::timestamp(),
+                    "level": level.as_str(),
+                    "fields": fields.to_json(),
+                });
+                if let Some(message) = message {
+                    line["message"] = message.into();
+                }
+                if let Some(stage) = stage {
+                    line["stage"] = stage.into();
+                }
+                format!("{}\n", line)
+            }
+            LogFormat::Pretty => {
+                let mut line = format!("[{}] {}", Self::timestamp(), level);
+                if let Some(stage) = stage {
+                    line.push_str(&format!(" {}:", stage));
+                }
+                if let Some(message) = message {
+                    line.push_str(&format!(" {}", message));
+                }
+                for (field, value) in &fields.0 {
+                    line.push_str(&format!(" {}={}", field, text(value)));
+                }
+                line.push('\n');
+                line
+            }
+        };
+        self.print(line);
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= Level::INFO
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        let name = fields
+            .take("name")
+            .map(|name| text(&name))
+            .unwrap_or_else(|| attrs.metadata().name().to_string());
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.stages.lock().unwrap().insert(
+            id,
+            Stage {
+                name,
+                timestamp: Self::timestamp(),
+                start: Instant::now(),
+                fields,
+                error: None,
+                refs: 1,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(stage) = self.stages.lock().unwrap().get_mut(&span.into_u64()) {
+            values.record(&mut stage.fields);
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::default();
+        event.record(&mut fields);
+        let id = match event.parent() {
+            Some(id) => Some(id.into_u64()),
+            None if event.is_contextual() => {
+                ENTERED.with(|entered| entered.borrow().last().copied())
+            }
+            None => None,
+        };
+        let mut stages = self.stages.lock().unwrap();
+        let stage = match id.and_then(|id| stages.get_mut(&id)) {
+            Some(stage) => stage,
+            None => return self.print_event(event.metadata().level(), None, fields),
+        };
+        // Data goes into the stage's line, messages get their own
+        if fields.0.iter().any(|(field, _)| field == "message") {
+            let name = stage.name.clone();
+            drop(stages);
+            return self.print_event(event.metadata().level(), Some(&name), fields);
+        }
+        match fields.take("error") {
+            Some(error) if *event.metadata().level() == Level::ERROR => {
+                stage.error = Some(text(&error));
+            }
+            error => {
+                stage
+                    .fields
+                    .0
+                    .extend(error.map(|error| ("error".to_string(), error)));
+                stage.fields.0.extend(fields.0);
+            }
+        }
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(i) = entered.iter().rposition(|&id| id == span.into_u64()) {
+                entered.remove(i);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(stage) = self.stages.lock().unwrap().get_mut(&span.into_u64()) {
+            stage.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut stages = self.stages.lock().unwrap();
+        let closed = match stages.get_mut(&span.into_u64()) {
+            Some(stage) => {
+                stage.refs -= 1;
+                stage.refs == 0
+            }
+            None => return false,
+        };
+        if closed {
+            let stage = stages.remove(&span.into_u64()).unwrap();
+            drop(stages);
+            self.print_stage(stage);
+        }
+        closed
+    }
+}
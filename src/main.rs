@@ -1,224 +1,1019 @@
-//  cargo run --release -- ~/p/covid_county.json ./out
+//  cargo run --release -- build ~/p/covid_county.json ./out
 
-use anyhow::{Context, Result};
-use chrono::{TimeZone, Utc};
-use rayon::prelude::*;
-use serde::{Deserialize, Serialize};
-use std::collections::{BTreeMap, BTreeSet, HashMap};
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate};
+use covid::group::{self, StateMetrics};
+use covid::source::SourceOpt;
+use covid::transform::{Pipeline, Stage};
+use covid::{
+    alert, builder, check, config, counties, dedup, diff, expr, fetch, filter, forecast,
+    granularity, incremental, ingest, join, lag, log, meta, metrics, output, progress, query,
+    report, schema, serve, states, stream, timezone, validate, GraphOpt, GroupedEntries,
+};
+use std::collections::BTreeSet;
+use std::env;
+use std::ffi::OsString;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use structopt::clap::{self, AppSettings};
 use structopt::StructOpt;
 
-type Rfc3339 = String;
-
 #[derive(Debug, StructOpt)]
-#[structopt(name = "example", about = "An example of StructOpt usage.")]
+#[structopt(name = "covid", about = "Graphs of covid cases and deaths by county")]
+#[structopt(setting = AppSettings::SubcommandRequiredElseHelp)]
 struct Opt {
-    /// Input file containing covid API response
+    /// `pretty` prints each stage's data under it, `json` a line per stage for a log
+    /// aggregator. The logs go to stderr
+    #[structopt(
+        long,
+        global = true,
+        default_value = "pretty",
+        possible_values = &["pretty", "json"]
+    )]
+    log_format: log::LogFormat,
+
+    /// Don't draw progress bars, which are only drawn when stderr is a terminal anyway
+    #[structopt(long, global = true)]
+    quiet: bool,
+
+    /// Threads to run on. `RAYON_NUM_THREADS` by default when it's set, else one per core
+    #[structopt(long, global = true, value_name = "N")]
+    jobs: Option<usize>,
+
+    #[structopt(subcommand)]
+    cmd: Command,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Download the raw county data from the Knowi API
+    Fetch(fetch::FetchOpt),
+    /// Build a graph per date from the inputs and write them out
+    Build(Box<BuildOpt>),
+    /// Check the input for bad records and print a JSON report, without writing graphs
+    Validate(validate::ValidateOpt),
+    /// Print nodes' metrics from a run's JSON output, by date, node and level
+    Query(query::QueryOpt),
+    /// Compare two runs' JSON output, or two dates of one, and list the nodes added,
+    /// removed or with changed metrics
+    Diff(diff::DiffOpt),
+    /// Project the states' confirmed cases and deaths past the end of a run's JSON
+    /// output, as future dated graphs with a `forecast` extra field
+    Forecast(forecast::ForecastOpt),
+    /// Serve a run's JSON output over HTTP
+    Serve(serve::ServeOpt),
+    /// Print the JSON Schema of the graphs in the JSON output
+    Schema,
+}
+
+#[derive(Debug, StructOpt)]
+struct BuildOpt {
+    /// Input files containing covid API responses followed by the output directory, unless
+    /// `--output` is given.
+    /// Inputs may be globs, directories of dump files, or `-` to read from stdin. Identical records across inputs are merged
     // https://www.knowi.com/coronavirus-dashboards/covid-19-api/
     //
     // per county level:
     // curl https://knowi.com/api/data/ipE4xJhLBkn8H8jisFisAdHKvepFR5I4bGzRySZ2aaXlJgie\?entityName\=Raw%20County%20level%20Data\&exportFormat\=json
-    #[structopt(parse(from_os_str))]
-    input: PathBuf,
+    //
+    // clap only allows a variadic positional last, so the inputs and the output dir,
+    // which `--output` makes optional, share one list that `build` splits.
+    #[structopt(parse(from_os_str), value_name = "PATH", min_values = 1)]
+    paths: Vec<PathBuf>,
 
-    #[structopt(parse(from_os_str))]
-    output_dir: PathBuf,
-}
+    /// `.toml` or `.json` file of flags to build with, e.g. `pipeline.toml`. Flags given on
+    /// the command line override the file's, inputs given replace its `inputs`
+    #[structopt(long, parse(from_os_str))]
+    config: Option<PathBuf>,
 
-#[derive(Deserialize)]
-struct CovidCountyRawDataEntry {
-    #[serde(rename(deserialize = "Date"))]
-    pub date: i64,
-    #[serde(rename(deserialize = "County"))]
-    pub county: String,
-    #[serde(rename(deserialize = "State"))]
-    pub state: String,
+    /// Write somewhere other than a directory, e.g. `sqlite:covid.db` (needs the `sqlite`
+    /// feature). Takes the place of the output directory and ignores the other output options
+    #[structopt(long)]
+    output: Option<output::OutputTarget>,
 
-    pub values: i64,
+    #[structopt(flatten)]
+    source: SourceOpt,
 
-    #[serde(rename(deserialize = "Type"))]
-    pub entry_type: String,
-}
+    /// Fail the run when an entry has an unrecognized `Type`, instead of ignoring it. With
+    /// `--stream` the types are only all known after the graphs are written, so the run fails
+    /// with its output left in place
+    #[structopt(long)]
+    strict: bool,
+
+    /// Check the graphs as built, before the metric stages: every edge's target is a node,
+    /// no node is there twice and the states' metrics are the sums of their counties'.
+    /// Fails the run on a violation
+    #[structopt(long)]
+    check: bool,
+
+    /// Overlap parsing, grouping and writing: each date's graphs are written as soon as
+    /// the inputs are past it, so only the dates in progress are held in memory. Needs the
+    /// inputs in date order, like a file per day, and an output directory in a format with
+    /// a file per date. Doesn't go with the flags that need every date at once. A run that
+    /// fails part way leaves the dates written before it
+    #[structopt(
+        long,
+        conflicts_with_all = &[
+            "backfill", "monotonic", "outliers", "deltas", "doubling-time", "rt", "active",
+            "pct-change", "waves", "vaccinations", "hospitals", "adjacency", "commuting",
+            "report",
+        ]
+    )]
+    stream: bool,
+
+    /// Only write the dates the output directory is missing, or whose entries or flags
+    /// changed since its last build, going by the manifest it leaves. Needs a format with a
+    /// file per date
+    #[structopt(long, conflicts_with_all = &["stream", "report", "skip-existing"])]
+    incremental: bool,
+
+    /// What to do when the same date, county and entry type comes up more than once, as
+    /// with overlapping inputs: `sum` the values, the default, keep the `last` one in the
+    /// input order or the `max`, or fail with an `error`
+    #[structopt(long, default_value = "sum", possible_values = &["sum", "last", "max", "error"])]
+    dedup: dedup::Dedup,
+
+    /// Zone the entries' timestamps are turned into dates in, e.g. `America/New_York` so
+    /// late evening reports stay on their day. UTC, a fixed offset like `-05:00` or the
+    /// zone of a US state or territory. Dates without a time, as in the NYT and JHU
+    /// files, spreadsheet date cells and Parquet dates, are left alone
+    #[structopt(long, default_value = "UTC", allow_hyphen_values = true)]
+    timezone: timezone::Timezone,
+
+    /// Days states take to report, e.g. `--lag "FL=2,CA=1"`. Their entries are moved back
+    /// that many days, dating the graphs by event rather than report
+    #[structopt(long, value_name = "STATE=DAYS,...")]
+    lag: Option<lag::Lags>,
+
+    #[structopt(flatten)]
+    output_opt: output::OutputOpt,
+
+    /// Build a graph per day, per ISO week or per calendar month. Weekly and monthly
+    /// graphs are timestamped with the period's first day and have the last values
+    /// reported in it
+    #[structopt(
+        long,
+        default_value = "daily",
+        possible_values = &["daily", "weekly", "monthly"]
+    )]
+    granularity: granularity::Granularity,
+
+    /// Carry counties forward onto the dates they didn't report on, with their last
+    /// values and a `backfilled` extra field, so every graph has the same nodes
+    #[structopt(long)]
+    backfill: bool,
+
+    /// Only write the graph for this date (YYYY-MM-DD)
+    #[structopt(long, conflicts_with_all = &["from", "to"])]
+    date: Option<NaiveDate>,
+
+    /// Drop entries before this date (YYYY-MM-DD) as they're read. Metrics derived from
+    /// earlier dates, like `--deltas`, start from the first date kept
+    #[structopt(long)]
+    from: Option<NaiveDate>,
+
+    /// Drop entries after this date (YYYY-MM-DD) as they're read
+    #[structopt(long)]
+    to: Option<NaiveDate>,
+
+    /// Only keep entries in these states, by abbreviation or name, e.g. `NY,NJ,CT`
+    #[structopt(
+        long,
+        value_name = "STATES",
+        use_delimiter = true,
+        number_of_values = 1
+    )]
+    states: Vec<String>,
+
+    /// Only keep entries in these counties, e.g. `NY - Kings,NY - Queens`. Together with
+    /// `--states`, entries in either are kept
+    #[structopt(
+        long,
+        value_name = "COUNTIES",
+        use_delimiter = true,
+        number_of_values = 1
+    )]
+    counties: Vec<String>,
+
+    /// What to do with pseudo-counties like `Unassigned` and `Out of NY`: keep them as
+    /// counties, drop them, or count them towards the state node only. The choice is
+    /// recorded on the state nodes as `unassigned`
+    #[structopt(long, possible_values = &["keep", "drop", "state"])]
+    unassigned: Option<filter::Unassigned>,
+
+    /// Merge the five boroughs into a single `New York City` county, like NYT style
+    /// sources report them, so the output matches across sources
+    #[structopt(long)]
+    merge_nyc: bool,
+
+    /// Parse each input once on its own first and log the parse throughput
+    #[structopt(long)]
+    bench_parse: bool,
+
+    /// CDC county vaccination CSV to merge into the county metrics
+    #[structopt(long, parse(from_os_str))]
+    vaccinations: Option<PathBuf>,
+
+    /// HHS hospital capacity by facility CSV to merge into the county metrics
+    #[structopt(long, parse(from_os_str))]
+    hospitals: Option<PathBuf>,
+
+    /// Per-state daily testing CSV (COVID Tracking Project layout) to merge into the state metrics
+    #[structopt(long, parse(from_os_str))]
+    testing: Option<PathBuf>,
+
+    /// Census population estimates CSV, joined into the nodes' `population` field
+    #[structopt(long, parse(from_os_str))]
+    population: Option<PathBuf>,
+
+    /// Weekly observed and expected all-cause deaths per state, as in the CDC's excess
+    /// deaths file. Adds an `excess_deaths_estimate` metric to the state nodes, the deaths
+    /// over the expected count up to the date
+    #[structopt(long, parse(from_os_str))]
+    baseline_mortality: Option<PathBuf>,
+
+    /// Census county adjacency file, adds edges between neighbouring counties
+    #[structopt(long, parse(from_os_str))]
+    adjacency: Option<PathBuf>,
+
+    /// Census LODES origin-destination CSV, adds `commute` edges from counties to the
+    /// ones their residents work in, weighted by the number of jobs. Can be given once
+    /// per state file
+    #[structopt(long, parse(from_os_str), number_of_values = 1)]
+    commuting: Vec<PathBuf>,
+
+    /// County to CBSA crosswalk CSV, adds `metro` nodes totalling their member counties
+    /// alongside the state hierarchy
+    #[structopt(long, parse(from_os_str))]
+    cbsa: Option<PathBuf>,
 
-#[derive(Debug)]
-struct CountyEntry {
-    name: String,
-    state: String,
-    confirmed: i64,
-    deaths: i64,
+    /// Correct `confirmed` and `deaths` where the feed revised them downwards: hold them
+    /// at their highest earlier value, spread the revision over the days before it, or
+    /// only flag it. Touched nodes get a `monotonic` extra field
+    #[structopt(long, possible_values = &["clamp", "redistribute", "flag"])]
+    monotonic: Option<metrics::Monotonic>,
+
+    /// Look for days that reported a backlog all at once, listing the metrics with a spike
+    /// in an `outlier` extra field, and either leave them or spread the excess over the
+    /// reports before
+    #[structopt(long, possible_values = &["annotate", "redistribute"])]
+    outliers: Option<metrics::Outliers>,
+
+    /// How many times the median of the previous 14 reported increments a day's has to
+    /// be for `--outliers` to count it
+    #[structopt(long, default_value = "10")]
+    outlier_factor: f64,
+
+    /// Add `confirmed_new` and `deaths_new` metrics, the change since the previous date
+    #[structopt(long)]
+    deltas: bool,
+
+    /// What `--deltas` does with negative changes, from totals revised downwards: `keep`
+    /// them, `zero` them or `omit` the metric
+    #[structopt(long, default_value = "keep", possible_values = &["keep", "zero", "omit"])]
+    negative_deltas: metrics::NegativeDeltas,
+
+    /// Add `confirmed_per_100k` and `deaths_per_100k` metrics to the nodes with a population
+    #[structopt(long, requires = "population")]
+    per_capita: bool,
+
+    /// Add a `cfr_bp` metric, deaths per confirmed case in basis points
+    #[structopt(long)]
+    cfr: bool,
+
+    /// Leave `cfr_bp` out for nodes with fewer confirmed cases than this, where a single
+    /// death swings the rate
+    #[structopt(long, default_value = "1")]
+    cfr_min_cases: i64,
+
+    /// Add a `doubling_days` metric, the days it took confirmed cases to double
+    #[structopt(long)]
+    doubling_time: bool,
+
+    /// Add an `rt_estimate` metric to the state nodes, the reproduction number in
+    /// hundredths, from an exponential fit to the daily new cases
+    #[structopt(long)]
+    rt: bool,
+
+    /// Days of new cases `--rt` fits the growth rate over
+    #[structopt(long, default_value = "7")]
+    rt_window: i64,
+
+    /// Generation interval `--rt` assumes, the mean and optionally standard deviation of
+    /// a gamma distribution in days
+    #[structopt(long, value_name = "MEAN[,SD]", default_value = "4.7,2.9")]
+    generation_interval: metrics::GenerationInterval,
+
+    /// Add an `active_estimated` metric, the cases confirmed within the last
+    /// `--recovery-days` less deaths, since the feed has no reliable recovered counts
+    #[structopt(long)]
+    active: bool,
+
+    /// Days after which `--active` counts a confirmed case as recovered
+    #[structopt(long, default_value = "14")]
+    recovery_days: i64,
+
+    /// Add `confirmed_pct_change_<n>d` and `deaths_pct_change_<n>d` metrics for each of
+    /// these windows in days, e.g. `--pct-change 7,14`: the change over the window in
+    /// basis points
+    #[structopt(long, value_name = "DAYS", use_delimiter = true, number_of_values = 1)]
+    pct_change: Vec<i64>,
+
+    /// Find the waves of new cases in each node's 7 day average and add `wave` and
+    /// `peak_date` extra fields: which wave a date is in, numbered from 1, and when it
+    /// peaked
+    #[structopt(long)]
+    waves: bool,
+
+    /// Define a metric from the others, e.g. `--metric "cfr=deaths*10000/confirmed"`,
+    /// with `+`, `-`, `*`, `/`, `%` and parentheses over integers, or floats where a metric
+    /// is one. Computed after the
+    /// built-in metrics, in order, so a definition can use earlier ones. Can be given more
+    /// than once
+    #[structopt(long, value_name = "NAME=EXPR", number_of_values = 1)]
+    metric: Vec<expr::MetricDef>,
+
+    /// Threshold rule like `confirmed_per_100k>500`, comparing a metric with `>`, `>=`,
+    /// `<`, `<=` or `==`. Nodes matching any list them in an `alerts` extra field. Can be
+    /// given more than once
+    #[structopt(long, value_name = "RULE", number_of_values = 1)]
+    alert: Vec<alert::Alert>,
+
+    /// Add a `United States` node above the states, totalling their metrics, so each
+    /// graph has a single root
+    #[structopt(long)]
+    national: bool,
+
+    /// Add nodes for these groupings of states, each with edges to its member states:
+    /// `hhs-region`, `census-region` or `census-division`
+    #[structopt(
+        long,
+        value_name = "GROUPINGS",
+        use_delimiter = true,
+        number_of_values = 1,
+        possible_values = &["hhs-region", "census-region", "census-division"]
+    )]
+    hierarchy: Vec<states::Grouping>,
+
+    /// Keep only this many county nodes per date, the largest by `--by`, along with their
+    /// ZIP codes. State and other rollup nodes still total every county
+    #[structopt(long, value_name = "K")]
+    top: Option<usize>,
+
+    /// Metric `--top` ranks counties by. Counties without it rank last
+    #[structopt(long, value_name = "METRIC", default_value = "confirmed")]
+    by: String,
+
+    /// Also render a static HTML summary of the latest date, e.g. `summary.html`
+    #[structopt(long, parse(from_os_str))]
+    report: Option<PathBuf>,
 }
 
-#[derive(Serialize, Debug, Default)]
-struct Node {
-    name: String,
-    metrics: BTreeMap<&'static str, i64>,
-    edges_directed: BTreeSet<String>,
-    extra_fields: BTreeMap<&'static str, String>,
+fn missing_argument(name: &str) -> clap::Error {
+    clap::Error::with_description(
+        &format!(
+            "The following required argument was not provided: <{}>",
+            name
+        ),
+        clap::ErrorKind::MissingRequiredArgument,
+    )
 }
 
-impl Node {
-    pub fn add_metric(&mut self, m: &'static str, v: i64) {
-        if !self.metrics.contains_key(m) {
-            self.metrics.insert(m, 0);
+fn main() -> Result<()> {
+    let matches = Opt::clap().get_matches();
+    let opt = Opt::from_clap(&matches);
+    log::init(opt.log_format)?;
+    progress::enable(!opt.quiet && io::stderr().is_terminal());
+    if let Some(jobs) = opt.jobs {
+        if jobs == 0 {
+            return Err(anyhow!("--jobs needs at least one thread"));
         }
-        *self.metrics.get_mut(m).unwrap() += v;
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(jobs)
+            .build_global()?;
+    }
+    match opt.cmd {
+        Command::Fetch(opt) => fetch::run(opt),
+        Command::Build(opt) => match &opt.config {
+            Some(path) => build(with_config(
+                path,
+                &opt,
+                matches.subcommand_matches("build"),
+            )?),
+            None => build(*opt),
+        },
+        Command::Validate(opt) => exit_if(!validate::run(opt)?.is_valid()),
+        Command::Query(opt) => query::run(opt),
+        Command::Diff(opt) => exit_if(!diff::run(opt)?.is_empty()),
+        Command::Forecast(opt) => forecast::run(opt),
+        Command::Serve(opt) => serve::run(opt),
+        Command::Schema => schema::run(),
     }
 }
 
-#[derive(Serialize, Debug, Default)]
-struct Graph {
-    timestamp: Rfc3339,
-    nodes: Vec<Node>,
+/// Exit with status 1 when `validate` found problems or `diff` differences, like `diff(1)`
+fn exit_if(found: bool) -> Result<()> {
+    if found {
+        std::process::exit(1);
+    }
+    Ok(())
 }
 
-fn main() -> Result<()> {
-    let l = ll::Logger::stdout();
-    let Opt { input, output_dir } = Opt::from_args();
+/// Parse the arguments again with the config's ahead of the `build` subcommand's own.
+/// `given` are the subcommand's matches, for the flags the command line overrides
+fn with_config(path: &Path, opt: &BuildOpt, given: Option<&clap::ArgMatches>) -> Result<BuildOpt> {
+    let mut args: Vec<OsString> = env::args_os().collect();
+    let at = 1 + args
+        .iter()
+        .skip(1)
+        .position(|arg| arg == "build")
+        .ok_or_else(|| anyhow!("--config only goes with the build subcommand"))?;
+    let config = config::load(path)?;
+    // The last path is the output directory, unless `--output` is given here or there
+    let outputs = usize::from(opt.output.is_none() && !config.sets("output"));
+    let inputs_given = opt.paths.len() > outputs;
+    let config = config.args(
+        |flag| given.is_some_and(|given| given.occurrences_of(flag) > 0),
+        inputs_given,
+    )?;
+    args.splice(at + 1..at + 1, config.into_iter().map(OsString::from));
+    match Opt::from_iter_safe(args) {
+        Ok(Opt {
+            cmd: Command::Build(opt),
+            ..
+        }) => Ok(*opt),
+        Ok(_) => unreachable!(),
+        // Only clap's first line, the usage after it is the command line's
+        Err(e) => {
+            let message = e.message.lines().next().unwrap_or_default();
+            let message = message.strip_prefix("error: ").unwrap_or(message);
+            Err(anyhow!("In config {}: {}", path.display(), message))
+        }
+    }
+}
 
-    let raw_data = l.event("read_file", |e| {
-        let data = fs::read(input).context("Failed to read raw covid JSON data")?;
-        e.add_data("size MB", data.capacity() / 1000000);
-        Ok(data)
-    })?;
+impl BuildOpt {
+    /// Metrics derived from earlier dates need those graphs built too, until they're done
+    fn needs_history(&self) -> bool {
+        self.monotonic.is_some()
+            || self.outliers.is_some()
+            || self.deltas
+            || self.doubling_time
+            || self.rt
+            || self.active
+            || !self.pct_change.is_empty()
+            || self.waves
+    }
 
-    let data = l.event("parse", |e| {
-        let result = serde_json::from_slice::<Vec<CovidCountyRawDataEntry>>(&raw_data[..])
-            .context("Failed to parse JSON")?;
-        e.add_data("entries", result.len());
-        Ok(result)
-    })?;
+    /// What `--date`, `--from`, `--to`, `--states`, `--counties`, `--unassigned` and
+    /// `--merge-nyc` keep of the entries
+    fn filter(&self) -> Result<filter::EntryFilter> {
+        // Streamed dates are written as they're done, so only the one date is grouped at all
+        let (from, to) = match self.date.filter(|_| self.stream) {
+            Some(date) => (Some(date), Some(date)),
+            None => (self.from, self.to),
+        };
+        filter::EntryFilter::new(
+            from,
+            to,
+            &self.states,
+            &self.counties,
+            self.unassigned.unwrap_or(filter::Unassigned::Keep),
+            self.merge_nyc,
+        )
+    }
 
-    let grouped = l.event("group by", |_| {
-        let result =
-            data.into_par_iter()
-                .fold(
-                    || HashMap::new(),
-                    |mut result, entry| {
-                        // raw data is in milisseconds
-                        let date = Utc.timestamp(entry.date / 1000, 0);
-                        let date_entry = result.entry(date).or_insert_with(HashMap::new);
-                        let state = entry.state;
-                        let name = entry.county;
-                        // Make sure we namespace by state in case there are similar county names
-                        let county_key = format!("{} - {}", &state, &name);
-                        let county_entry =
-                            date_entry.entry(county_key).or_insert_with(|| CountyEntry {
-                                name,
-                                state,
-                                confirmed: 0,
-                                deaths: 0,
-                            });
-
-                        match entry.entry_type.as_str() {
-                            "Confirmed" => county_entry.confirmed += entry.values,
-                            "Deaths" => county_entry.deaths += entry.values,
-                            _ => (),
-                        }
-                        result
-                    },
+    /// The inputs' and the joined files' hashes, for the graphs' `meta`
+    fn hash_inputs(&self, inputs: &[PathBuf]) -> Result<meta::Meta> {
+        log::stage("hash inputs", || {
+            let joined = [
+                ("vaccinations", &self.vaccinations),
+                ("hospitals", &self.hospitals),
+                ("testing", &self.testing),
+                ("population", &self.population),
+                ("baseline-mortality", &self.baseline_mortality),
+                ("adjacency", &self.adjacency),
+                ("cbsa", &self.cbsa),
+            ];
+            let meta_inputs = inputs
+                .iter()
+                .map(|input| (self.source.source.clone(), input.as_path()))
+                .chain(
+                    joined
+                        .iter()
+                        .filter_map(|(flag, path)| Some((flag.to_string(), path.as_deref()?))),
                 )
-                .reduce(
-                    || HashMap::new(),
-                    |from, mut into| {
-                        for (date, from_county_entries) in from {
-                            let into_date_entry = into.entry(date).or_insert_with(HashMap::new);
-
-                            for (county, from_county_entry) in from_county_entries {
-                                let from_confirmed = from_county_entry.confirmed;
-                                let from_deaths = from_county_entry.deaths;
-
-                                let into_county_entry = into_date_entry
-                                    .entry(county)
-                                    .or_insert_with(|| CountyEntry {
-                                        name: from_county_entry.name,
-                                        state: from_county_entry.state,
-                                        confirmed: 0,
-                                        deaths: 0,
-                                    });
-
-                                into_county_entry.confirmed += from_confirmed;
-                                into_county_entry.deaths += from_deaths;
-                            }
-                        }
-                        into
-                    },
-                );
+                .chain(
+                    self.commuting
+                        .iter()
+                        .map(|path| ("commuting".to_string(), path.as_path())),
+                )
+                .collect();
+            let meta = meta::Meta::new(meta_inputs)?;
+            tracing::info!(inputs = meta.inputs.len());
+            Ok(meta)
+        })
+    }
+
+    /// The options of the graphs that don't need the entries to load. `--adjacency` and
+    /// `--commuting` match their counties to the entries', so they're loaded after grouping
+    fn graph_opt(&self) -> Result<GraphOpt> {
+        let population = match &self.population {
+            Some(path) => Some(log::stage("load population", || {
+                Ok(join::population::load(path)?)
+            })?),
+            None => None,
+        };
+        let cbsa = match &self.cbsa {
+            Some(path) => Some(log::stage("load cbsa", || {
+                let crosswalk = join::cbsa::load(path)?;
+                tracing::info!(metros = crosswalk.num_metros());
+                Ok(crosswalk)
+            })?),
+            None => None,
+        };
+        let state_metrics = match &self.testing {
+            Some(path) => log::stage("group testing by state", || {
+                let testing = join::testing::group_by_state(path)?;
+                tracing::info!(dates = testing.len());
+                Ok(testing)
+            })?,
+            None => StateMetrics::new(),
+        };
+        Ok(GraphOpt {
+            state_metrics,
+            cbsa,
+            population,
+            unassigned: self.unassigned,
+            hierarchy: self.hierarchy.clone(),
+            national: self.national,
+            ..Default::default()
+        })
+    }
+
+    /// The flags that change the graphs written, for `--incremental`. Not the paths read and
+    /// written, the joined files go in by hash
+    fn options(&self) -> incremental::Options {
+        let mut options = incremental::Options::default();
+        let source = &self.source;
+        options
+            .add("source", &source.source)
+            .add("format", source.format)
+            .add("entity", source.entity)
+            .add("query", &source.query)
+            .add("sheet", &source.sheet)
+            .add("columns", &source.columns);
+        let output = &self.output_opt;
+        options
+            .add("output-format", output.output_format)
+            .add("single-file", output.single_file)
+            .add("pretty", output.pretty)
+            .add("compress", output.compress)
+            .add("geometries", &output.geometries)
+            .add("partition-by", output.partition_by)
+            .add("filename-template", &output.filename_template)
+            .add("manifest", output.manifest)
+            .add("chart", &output.chart)
+            .add("legacy-edges", output.legacy_edges)
+            .add("graph-deltas", output.graph_deltas)
+            .add("states-as", output.states_as);
+        options
+            .add("dedup", self.dedup)
+            .add("timezone", self.timezone)
+            .add("lag", &self.lag)
+            .add("granularity", self.granularity)
+            .add("backfill", self.backfill)
+            .add("date", self.date)
+            .add("from", self.from)
+            .add("to", self.to)
+            .add("states", &self.states)
+            .add("counties", &self.counties)
+            .add("unassigned", self.unassigned)
+            .add("merge-nyc", self.merge_nyc)
+            .add("monotonic", self.monotonic)
+            .add("outliers", self.outliers)
+            .add("outlier-factor", self.outlier_factor)
+            .add("deltas", self.deltas)
+            .add("negative-deltas", self.negative_deltas)
+            .add("per-capita", self.per_capita)
+            .add("cfr", self.cfr)
+            .add("cfr-min-cases", self.cfr_min_cases)
+            .add("doubling-time", self.doubling_time)
+            .add("rt", self.rt)
+            .add("rt-window", self.rt_window)
+            .add("generation-interval", self.generation_interval)
+            .add("active", self.active)
+            .add("recovery-days", self.recovery_days)
+            .add("pct-change", &self.pct_change)
+            .add("waves", self.waves)
+            .add("metric", &self.metric)
+            .add("alert", &self.alert)
+            .add("national", self.national)
+            .add("hierarchy", &self.hierarchy)
+            .add("top", self.top)
+            .add("by", &self.by);
+        options
+    }
+
+    /// The stages the flags add to the built graphs, in the order they run
+    fn pipeline(&self) -> Result<Pipeline> {
+        let mut pipeline = Pipeline::new();
+        if let Some(monotonic) = self.monotonic {
+            pipeline.push(Stage::Monotonic(monotonic));
+        }
+        if let Some(policy) = self.outliers {
+            pipeline.push(Stage::Outliers {
+                policy,
+                factor: self.outlier_factor,
+            });
+        }
+        if self.deltas {
+            pipeline.push(Stage::Deltas(self.negative_deltas));
+        }
+        if self.per_capita {
+            pipeline.push(Stage::PerCapita);
+        }
+        if self.cfr {
+            pipeline.push(Stage::Cfr {
+                min_cases: self.cfr_min_cases,
+            });
+        }
+        if self.doubling_time {
+            pipeline.push(Stage::DoublingTime);
+        }
+        if self.rt {
+            pipeline.push(Stage::Rt {
+                window: self.rt_window,
+                interval: self.generation_interval,
+            });
+        }
+        if self.active {
+            pipeline.push(Stage::Active {
+                recovery_days: self.recovery_days,
+            });
+        }
+        if let Some(path) = &self.baseline_mortality {
+            let excess = log::stage("load baseline mortality", || Ok(join::excess::load(path)?))?;
+            pipeline.push(Stage::ExcessDeaths(excess));
+        }
+        if !self.pct_change.is_empty() {
+            pipeline.push(Stage::PctChange(self.pct_change.clone()));
+        }
+        if self.waves {
+            pipeline.push(Stage::Waves);
+        }
+        if !self.metric.is_empty() {
+            pipeline.push(Stage::Custom(self.metric.clone()));
+        }
+        if !self.alert.is_empty() {
+            pipeline.push(Stage::Alerts(self.alert.clone()));
+        }
+        if let Some(k) = self.top {
+            pipeline.push(Stage::KeepTop {
+                k,
+                metric: self.by.clone(),
+            });
+        }
+        Ok(pipeline)
+    }
+}
+
+/// Parse each input on its own and log how fast, for `--bench-parse`
+fn parse_throughput(inputs: &[PathBuf], sources: &ingest::Sources) -> Result<()> {
+    for (input, source) in inputs.iter().zip(sources) {
+        if input == Path::new("-") {
+            return Err(anyhow!("--bench-parse can't read stdin twice"));
+        }
+        log::stage("parse", || {
+            tracing::info!(input = %input.display());
+            let size = fs::metadata(input)?.len() as f64 / 1000000.0;
+            let start = Instant::now();
+            let mut entries = 0;
+            for entry in source.entries()? {
+                entry?;
+                entries += 1;
+            }
+            let elapsed = start.elapsed().as_secs_f64();
+            tracing::info!(entries = entries);
+            tracing::info!(size_mb = %format_args!("{:.1}", size));
+            tracing::info!(mb_per_s = %format_args!("{:.1}", size / elapsed));
+            Ok(())
+        })?;
+    }
+    Ok(())
+}
+
+fn build(mut opt: BuildOpt) -> Result<()> {
+    let mut paths = std::mem::take(&mut opt.paths);
+    let output = match opt.output.take() {
+        Some(output) => output,
+        None if paths.len() >= 2 => output::OutputTarget::Dir(paths.pop().unwrap()),
+        None => missing_argument("input>... <output-dir").exit(),
+    };
+    if paths.is_empty() {
+        missing_argument("input>...").exit();
+    }
+    let mut options = opt.incremental.then(|| opt.options());
+    let incremental_dir = match opt.incremental {
+        true => Some(output::per_date_dir(
+            &output,
+            &opt.output_opt,
+            "--incremental",
+        )?),
+        false => None,
+    };
+    let filter = opt.filter()?;
+    let (inputs, sources) = ingest::open_sources(paths, &opt.source)?;
+    let meta = opt.hash_inputs(&inputs)?;
+    if let Some(options) = &mut options {
+        for joined in &meta.inputs[inputs.len()..] {
+            options.add(&joined.source, &joined.sha256);
+        }
+    }
+    if opt.bench_parse {
+        parse_throughput(&inputs, &sources)?;
+    }
+    let mut graph_opt = opt.graph_opt()?;
+    let pipeline = opt.pipeline()?;
+    if opt.stream {
+        return build_stream(&opt, &sources, &filter, graph_opt, pipeline, &output, meta);
+    }
+
+    let (mut grouped, unmapped_types) = log::stage("group by", || {
+        tracing::info!(inputs = sources.len());
+        let result = covid::group_by_date(
+            covid::parse_entries(&sources)?,
+            &filter,
+            opt.dedup,
+            opt.timezone,
+            opt.lag.as_ref(),
+        )?;
+        tracing::info!(dates = result.0.len());
         Ok(result)
     })?;
 
-    let with_state_nodes = l.event("add state nodes", |_| {
-        let nodes_by_date = grouped
-            .into_par_iter()
-            .map(|(date, entries)| {
-                let mut states = HashMap::new();
-
-                let mut all_nodes: Vec<Node> = Vec::new();
-
-                for (key, county_entry) in entries {
-                    if !states.contains_key(&county_entry.state) {
-                        states.insert(
-                            county_entry.state.clone(),
-                            Node {
-                                name: county_entry.state.clone(),
-                                ..Default::default()
-                            },
-                        );
-                    }
-                    let state_entry = states
-                        .get_mut(&county_entry.state)
-                        .expect("state must be there");
-
-                    state_entry.add_metric("confirmed", county_entry.confirmed);
-                    state_entry.add_metric("deaths", county_entry.deaths);
-                    state_entry.edges_directed.insert(key.clone());
-
-                    all_nodes.push(Node {
-                        name: key,
-                        metrics: vec![
-                            ("confirmed", county_entry.confirmed),
-                            ("deaths", county_entry.deaths),
-                        ]
-                        .into_iter()
-                        .collect(),
-                        extra_fields: vec![("display_name", county_entry.name)]
-                            .into_iter()
-                            .collect(),
-                        edges_directed: BTreeSet::new(),
-                    })
-                }
-
-                for (_, state) in states {
-                    all_nodes.push(state);
-                }
-
-                Graph {
-                    timestamp: date.to_rfc3339(),
-                    nodes: all_nodes,
-                }
-            })
-            .collect::<Vec<Graph>>();
-
-        Ok(nodes_by_date)
+    let unmatched_counties = counties::unmatched(&grouped);
+
+    if opt.strict && !unmapped_types.is_empty() {
+        return Err(unmapped_types.into_error().into());
+    }
+
+    if let Some(path) = &opt.vaccinations {
+        log::stage("merge vaccinations", || {
+            let unmatched = join::vaccinations::merge(&mut grouped, path)?;
+            tracing::info!(unmatched = unmatched);
+            Ok(())
+        })?;
+    }
+
+    if let Some(path) = &opt.hospitals {
+        log::stage("merge hospitals", || {
+            let unmatched = join::hospitals::merge(&mut grouped, path)?;
+            tracing::info!(unmatched = unmatched);
+            Ok(())
+        })?;
+    }
+
+    if let Some(path) = &opt.adjacency {
+        graph_opt.adjacency = log::stage("load adjacency", || {
+            let adjacency = join::adjacency::load(path, &grouped)?;
+            tracing::info!(counties = adjacency.len());
+            Ok(adjacency)
+        })?;
+    }
+
+    if !opt.commuting.is_empty() {
+        graph_opt.commuting = log::stage("load commuting", || {
+            let commuting = join::commuting::load(&opt.commuting, &grouped)?;
+            tracing::info!(counties = commuting.len());
+            Ok(commuting)
+        })?;
+    }
+
+    let unmatched_population =
+        builder::prepare(&mut grouped, &mut graph_opt, opt.granularity, opt.backfill)?;
+
+    let needs_history = opt.needs_history();
+    if let Some(date) = opt.date {
+        grouped.retain(|timestamp, _| {
+            let day = timestamp.date_naive();
+            day == date || (needs_history && day < date)
+        });
+        if grouped
+            .keys()
+            .all(|timestamp| timestamp.date_naive() != date)
+        {
+            return Err(anyhow!("No entries on {}", date));
+        }
+    }
+
+    let incremental = match (incremental_dir, &options) {
+        (Some(dir), Some(options)) => Some(log::stage("find changed dates", || {
+            let mut fingerprints = incremental::fingerprints(&grouped, options, needs_history);
+            if let Some(date) = opt.date {
+                fingerprints.retain(|timestamp, _| {
+                    DateTime::parse_from_rfc3339(timestamp)
+                        .is_ok_and(|timestamp| timestamp.date_naive() == date)
+                });
+            }
+            let previous = incremental::Previous::load(dir)?;
+            let mut rebuilt = previous.stale(dir, &fingerprints);
+            tracing::info!(dates = fingerprints.len());
+            tracing::info!(changed = rebuilt.len());
+            // These move values between dates both ways, so a change anywhere changes them all
+            if (opt.monotonic.is_some() || opt.outliers.is_some() || opt.waves)
+                && !rebuilt.is_empty()
+            {
+                rebuilt = fingerprints.keys().cloned().collect();
+            }
+            let kept = previous.keep(&rebuilt);
+            Ok((fingerprints, rebuilt, kept))
+        })?),
+        _ => None,
+    };
+    if let Some((_, rebuilt, _)) = incremental.as_ref().filter(|_| !needs_history) {
+        grouped.retain(|timestamp, _| rebuilt.contains(&timestamp.to_rfc3339()));
+    }
+
+    let mut with_state_nodes = log::stage("add state nodes", || {
+        Ok(covid::build_graphs(grouped, &graph_opt))
     })?;
+    if opt.check {
+        log::stage("check", || {
+            let violations = check::check(&with_state_nodes);
+            tracing::info!(violations = violations.len());
+            fail_on_violations(violations)
+        })?;
+    }
 
-    l.event("write_files", |e| {
-        e.add_data("output_dir", output_dir.display().to_string());
-        e.add_data("num_files", with_state_nodes.len());
+    pipeline.run(&mut with_state_nodes)?;
 
-        with_state_nodes
-            .into_par_iter()
-            .map(|graph| {
-                let mut filepath = output_dir.clone();
-                filepath.push(&graph.timestamp);
+    if let Some(date) = opt.date.filter(|_| needs_history) {
+        with_state_nodes.retain(|graph| {
+            DateTime::parse_from_rfc3339(&graph.timestamp)
+                .map(|timestamp| timestamp.date_naive() == date)
+                .unwrap_or(true)
+        });
+    }
+    if let Some((_, rebuilt, _)) = incremental.as_ref().filter(|_| needs_history) {
+        with_state_nodes.retain(|graph| rebuilt.contains(&graph.timestamp));
+    }
 
-                let json = serde_json::to_string_pretty(&graph)?;
+    if let Some(report) = &opt.report {
+        log::stage("write_report", || {
+            tracing::info!(report = %report.display());
+            report::write(&with_state_nodes, report)
+        })?;
+    }
 
-                fs::write(filepath, json)?;
-                Ok(())
-            })
-            .collect::<Result<()>>()?;
+    for graph in &mut with_state_nodes {
+        graph.meta = Some(meta.clone());
+    }
+    log::stage("write_files", || {
+        tracing::info!(output = %output);
+        let num_files = match incremental_dir.zip(incremental) {
+            Some((dir, (fingerprints, _, kept))) => {
+                let incremental = output::Incremental {
+                    fingerprints: &fingerprints,
+                    kept,
+                };
+                output::write_incremental(with_state_nodes, dir, &opt.output_opt, incremental)?
+            }
+            None => output::write(with_state_nodes, &output, &opt.output_opt)?,
+        };
+        tracing::info!(num_files = num_files);
         Ok(())
     })?;
+
+    log_unmatched(unmatched_counties, unmatched_population, unmapped_types)
+}
+
+/// `build --stream`: group, build and write a date at a time as `stream::by_date` hands
+/// them over
+fn build_stream(
+    opt: &BuildOpt,
+    sources: &ingest::Sources,
+    filter: &filter::EntryFilter,
+    mut graph_opt: GraphOpt,
+    pipeline: Pipeline,
+    output: &output::OutputTarget,
+    meta: meta::Meta,
+) -> Result<()> {
+    if !matches!(opt.granularity, granularity::Granularity::Daily) {
+        return Err(anyhow!(
+            "--granularity needs all dates, it doesn't go with --stream"
+        ));
+    }
+    let mut writer = output::DirStream::new(output, &opt.output_opt)?;
+    let mut unmatched_counties = BTreeSet::new();
+    let mut unmatched_population = graph_opt.population.as_ref().map(|_| BTreeSet::new());
+    let mut dates = 0;
+    let unmapped_types = log::stage("stream", || {
+        tracing::info!(inputs = sources.len());
+        let on_date = |mut grouped: GroupedEntries| {
+            unmatched_counties.append(&mut counties::unmatched(&grouped));
+            let unmatched = builder::prepare(&mut grouped, &mut graph_opt, opt.granularity, false)?;
+            if let (Some(all), Some(mut unmatched)) = (&mut unmatched_population, unmatched) {
+                all.append(&mut unmatched);
+            }
+            let mut graphs = covid::build_graphs(grouped, &graph_opt);
+            if opt.check {
+                fail_on_violations(check::check(&graphs))?;
+            }
+            pipeline.run_quietly(&mut graphs)?;
+            for mut graph in graphs {
+                graph.meta = Some(meta.clone());
+                writer.write(graph)?;
+            }
+            dates += 1;
+            Ok(())
+        };
+        let unmapped_types = stream::by_date(
+            sources,
+            filter,
+            opt.dedup,
+            opt.timezone,
+            opt.lag.as_ref(),
+            on_date,
+        )?;
+        tracing::info!(dates);
+        Ok(unmapped_types)
+    })?;
+    if dates == 0 {
+        if let Some(date) = opt.date {
+            return Err(anyhow!("No entries on {}", date));
+        }
+    }
+    log::stage("write_files", || {
+        tracing::info!(output = %output);
+        let num_files = writer.finish()?;
+        tracing::info!(num_files);
+        Ok(())
+    })?;
+    // The graphs are out by the time every entry's type is known
+    if opt.strict && !unmapped_types.is_empty() {
+        return Err(unmapped_types.into_error().into());
+    }
+    log_unmatched(unmatched_counties, unmatched_population, unmapped_types)
+}
+
+/// Fail when `--check` found anything, listing the first few
+fn fail_on_violations(violations: Vec<check::Violation>) -> Result<()> {
+    if violations.is_empty() {
+        return Ok(());
+    }
+    let samples = violations
+        .iter()
+        .take(20)
+        .map(ToString::to_string)
+        .collect::<Vec<_>>();
+    Err(anyhow!(
+        "{} invariant violations:\n  {}",
+        violations.len(),
+        samples.join("\n  ")
+    ))
+}
+
+/// Log what the inputs had that nothing matched
+fn log_unmatched(
+    unmatched_counties: BTreeSet<String>,
+    unmatched_population: Option<BTreeSet<String>>,
+    unmapped_types: group::UnmappedTypes,
+) -> Result<()> {
+    if !unmatched_counties.is_empty() {
+        log::stage("county names unmatched", || {
+            tracing::info!(counties = unmatched_counties.len());
+            let samples = unmatched_counties
+                .iter()
+                .take(20)
+                .cloned()
+                .collect::<Vec<_>>();
+            tracing::info!(samples = samples.join(", "));
+            Ok(())
+        })?;
+    }
+
+    if let Some(unmatched) = unmatched_population {
+        log::stage("population unmatched", || {
+            tracing::info!(counties = unmatched.len());
+            let samples = unmatched.iter().take(20).cloned().collect::<Vec<_>>();
+            tracing::info!(samples = samples.join(", "));
+            Ok(())
+        })?;
+    }
+
+    if !unmapped_types.is_empty() {
+        log::stage("unmapped entry types", || {
+            let counts = unmapped_types
+                .counts
+                .iter()
+                .map(|(entry_type, count)| format!("{}: {}", entry_type, count))
+                .collect::<Vec<_>>();
+            tracing::info!(types = %counts.join(", "));
+            Ok(())
+        })?;
+    }
     Ok(())
 }
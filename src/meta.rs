@@ -0,0 +1,65 @@
+//! The `meta` block of the graphs: the format version, when and by what they were made,
+//! and from which inputs
+
+use anyhow::{Context, Result};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// Bumped when a field of the output changes meaning or goes away, not when one is added
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Meta {
+    pub schema_version: u32,
+    /// RFC 3339, in UTC
+    pub generated_at: String,
+    pub tool_version: String,
+    pub inputs: Vec<Input>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Input {
+    /// The `--source` of a main input, or the flag a file was joined with, e.g. `population`
+    pub source: String,
+    pub path: String,
+    /// Of the file's content. None for stdin and directories
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+impl Meta {
+    /// Hashes the input files, in parallel
+    pub fn new(inputs: Vec<(String, &Path)>) -> Result<Self> {
+        let inputs = inputs
+            .into_par_iter()
+            .map(|(source, path)| {
+                Ok(Input {
+                    source,
+                    path: path.display().to_string(),
+                    sha256: match path.is_file() {
+                        true => Some(sha256(path)?),
+                        false => None,
+                    },
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Meta {
+            schema_version: SCHEMA_VERSION,
+            generated_at: chrono::Utc::now().to_rfc3339(),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            inputs,
+        })
+    }
+}
+
+fn sha256(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut file =
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
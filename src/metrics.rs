@@ -0,0 +1,857 @@
+//! Metrics derived from the built graphs rather than read from an input
+
+use crate::error::Result;
+use crate::{Graph, MetricName, MetricValue};
+use anyhow::anyhow;
+use chrono::{DateTime, NaiveDate};
+use rayon::prelude::*;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+
+/// One node's value of a metric on a date, with the index of the date's graph
+#[derive(Clone, Copy)]
+struct Point {
+    graph: usize,
+    date: NaiveDate,
+    value: i64,
+}
+
+/// The graphs re-indexed by node: each node's values of `metric`, oldest first, for the
+/// nodes of a level or all of them. The graphs have to be sorted by date
+fn series_by_node<'a>(
+    graphs: &'a [Graph],
+    metric: &str,
+    level: Option<&str>,
+) -> Result<HashMap<&'a str, Vec<Point>>> {
+    let mut series = HashMap::<&str, Vec<Point>>::new();
+    for (i, graph) in graphs.iter().enumerate() {
+        let date = DateTime::parse_from_rfc3339(&graph.timestamp)?.date_naive();
+        for node in &graph.nodes {
+            if level.is_some_and(|level| node.level != level) {
+                continue;
+            }
+            if let Some(&value) = node.metrics.get(metric) {
+                series.entry(&node.name).or_default().push(Point {
+                    graph: i,
+                    date,
+                    value: value.as_i64(),
+                });
+            }
+        }
+    }
+    Ok(series)
+}
+
+/// Set a derived metric from per node, per graph values computed off the series
+fn insert_computed(
+    graphs: &mut [Graph],
+    metric: impl Into<MetricName>,
+    computed: Vec<(String, Vec<(usize, i64)>)>,
+) {
+    let mut by_graph = vec![HashMap::new(); graphs.len()];
+    for (name, values) in computed {
+        for (graph, value) in values {
+            by_graph[graph].insert(name.clone(), value);
+        }
+    }
+    let metric = metric.into();
+    for (graph, values) in graphs.iter_mut().zip(by_graph) {
+        for node in &mut graph.nodes {
+            if let Some(&value) = values.get(&node.name) {
+                node.metrics.insert(metric.clone(), value.into());
+            }
+        }
+    }
+}
+
+/// Cumulative metrics and the daily increments `--deltas` derives from them
+const DELTAS: &[(&str, &str)] = &[("confirmed", "confirmed_new"), ("deaths", "deaths_new")];
+
+/// Metrics and their `--per-capita` counterparts
+const PER_CAPITA: &[(&str, &str)] = &[
+    ("confirmed", "confirmed_per_100k"),
+    ("deaths", "deaths_per_100k"),
+];
+
+/// What to do with a negative increment, when the feed revised a total downwards
+#[derive(Debug, Clone, Copy)]
+pub enum NegativeDeltas {
+    Keep,
+    Zero,
+    Omit,
+}
+
+impl FromStr for NegativeDeltas {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(NegativeDeltas::Keep),
+            "zero" => Ok(NegativeDeltas::Zero),
+            "omit" => Ok(NegativeDeltas::Omit),
+            _ => Err(anyhow!(
+                "Unknown negative delta policy {}, expected keep, zero or omit",
+                s
+            )),
+        }
+    }
+}
+
+/// How `--monotonic` corrects cumulative metrics the feed revised downwards
+#[derive(Debug, Clone, Copy)]
+pub enum Monotonic {
+    /// Hold the metric at its highest earlier value
+    Clamp,
+    /// Scale the earlier values down so the revision is spread over the days before it
+    Redistribute,
+    /// Leave the values as they are
+    Flag,
+}
+
+impl FromStr for Monotonic {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "clamp" => Ok(Monotonic::Clamp),
+            "redistribute" => Ok(Monotonic::Redistribute),
+            "flag" => Ok(Monotonic::Flag),
+            _ => Err(anyhow!(
+                "Unknown monotonic policy {}, expected clamp, redistribute or flag",
+                s
+            )),
+        }
+    }
+}
+
+impl Monotonic {
+    /// Value of the `monotonic` extra field on the nodes the policy touched
+    fn mark(self) -> &'static str {
+        match self {
+            Monotonic::Clamp => "clamped",
+            Monotonic::Redistribute => "redistributed",
+            Monotonic::Flag => "decreased",
+        }
+    }
+
+    /// Correct a series in place, returning which of its values were touched
+    fn correct(self, values: &mut [i64]) -> Vec<bool> {
+        let mut touched = vec![false; values.len()];
+        let mut highest = values.first().copied().unwrap_or_default();
+        for j in 1..values.len() {
+            match self {
+                Monotonic::Clamp if values[j] < values[j - 1] => {
+                    values[j] = values[j - 1];
+                    touched[j] = true;
+                }
+                Monotonic::Redistribute if values[j] < values[j - 1] => {
+                    // Counts don't go below zero, and there's no scaling down from zero, so
+                    // those values are clamped to it instead
+                    if values[j] < 0 {
+                        values[j] = 0;
+                        touched[j] = true;
+                    }
+                    let previous = values[j - 1];
+                    let ratio = values[j] as f64 / previous as f64;
+                    for i in 0..j {
+                        let scaled = match previous > 0 {
+                            true => (values[i] as f64 * ratio).round() as i64,
+                            false => values[i].max(0),
+                        };
+                        touched[i] |= scaled != values[i];
+                        values[i] = scaled;
+                    }
+                }
+                Monotonic::Flag => touched[j] = values[j] < highest,
+                _ => {}
+            }
+            highest = highest.max(values[j]);
+        }
+        touched
+    }
+}
+
+/// Correct `confirmed` and `deaths` wherever they go down on a node, marking the nodes
+/// that were touched with a `monotonic` extra field. Sorts the graphs by date
+pub fn enforce_monotonic(graphs: &mut [Graph], policy: Monotonic) -> Result<()> {
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    let mut touched = vec![HashSet::new(); graphs.len()];
+    for &(metric, _) in DELTAS {
+        let computed = series_by_node(graphs, metric, None)?
+            .into_par_iter()
+            .map(|(name, series)| {
+                let mut values = series.iter().map(|point| point.value).collect::<Vec<_>>();
+                let corrected = policy
+                    .correct(&mut values)
+                    .into_iter()
+                    .zip(series.iter().zip(values))
+                    .filter(|(touched, _)| *touched)
+                    .map(|(_, (point, value))| (point.graph, value))
+                    .collect();
+                (name.to_string(), corrected)
+            })
+            .collect::<Vec<(String, Vec<_>)>>();
+        for (name, values) in &computed {
+            for &(graph, _) in values {
+                touched[graph].insert(name.clone());
+            }
+        }
+        insert_computed(graphs, metric, computed);
+    }
+    for (graph, touched) in graphs.iter_mut().zip(touched) {
+        for node in &mut graph.nodes {
+            if touched.contains(&node.name) {
+                node.extra_fields
+                    .insert("monotonic", policy.mark().to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reports before a day `--outliers` compares its increment with
+const OUTLIER_WINDOW: usize = 14;
+
+/// What `--outliers` does with a day that reported a backlog all at once
+#[derive(Debug, Clone, Copy)]
+pub enum Outliers {
+    Annotate,
+    /// Spread the excess over the reports before it
+    Redistribute,
+}
+
+impl FromStr for Outliers {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "annotate" => Ok(Outliers::Annotate),
+            "redistribute" => Ok(Outliers::Redistribute),
+            _ => Err(anyhow!(
+                "Unknown outlier policy {}, expected annotate or redistribute",
+                s
+            )),
+        }
+    }
+}
+
+/// Find the increments more than `factor` times the median of the last `OUTLIER_WINDOW`
+/// ones, redistributing their excess if asked to. Returns the outliers' indexes and
+/// which values changed
+fn find_outliers(values: &mut [i64], policy: Outliers, factor: f64) -> (Vec<usize>, Vec<bool>) {
+    let mut outliers = Vec::new();
+    let mut changed = vec![false; values.len()];
+    for j in 1..values.len() {
+        let start = j.saturating_sub(OUTLIER_WINDOW).max(1);
+        let mut increments = (start..j)
+            .map(|i| values[i] - values[i - 1])
+            .collect::<Vec<_>>();
+        // Too little history to say what's typical
+        if increments.len() < OUTLIER_WINDOW / 2 {
+            continue;
+        }
+        increments.sort_unstable();
+        let baseline = increments[increments.len() / 2].max(0);
+        let increment = values[j] - values[j - 1];
+        if increment as f64 <= factor * baseline.max(1) as f64 {
+            continue;
+        }
+        outliers.push(j);
+        if let Outliers::Redistribute = policy {
+            let excess = increment - baseline;
+            let days = (j - start) as i64;
+            for (m, i) in (start..j).enumerate() {
+                values[i] += excess * (m as i64 + 1) / days;
+                changed[i] = true;
+            }
+        }
+    }
+    (outliers, changed)
+}
+
+/// Look for single day spikes in `confirmed` and `deaths`, listing the metrics with one in
+/// an `outlier` extra field and optionally spreading them over the days before. Sorts the
+/// graphs by date
+pub fn smooth_outliers(graphs: &mut [Graph], policy: Outliers, factor: f64) -> Result<()> {
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    let mut flagged = vec![HashMap::<String, Vec<&str>>::new(); graphs.len()];
+    for &(metric, _) in DELTAS {
+        let results = series_by_node(graphs, metric, None)?
+            .into_par_iter()
+            .map(|(name, series)| {
+                let mut values = series.iter().map(|point| point.value).collect::<Vec<_>>();
+                let (outliers, changed) = find_outliers(&mut values, policy, factor);
+                let outliers = outliers
+                    .into_iter()
+                    .map(|j| series[j].graph)
+                    .collect::<Vec<_>>();
+                let changed = changed
+                    .into_iter()
+                    .zip(series.iter().zip(values))
+                    .filter(|(changed, _)| *changed)
+                    .map(|(_, (point, value))| (point.graph, value))
+                    .collect::<Vec<_>>();
+                (name.to_string(), outliers, changed)
+            })
+            .collect::<Vec<_>>();
+        let mut computed = Vec::new();
+        for (name, outliers, changed) in results {
+            for graph in outliers {
+                flagged[graph].entry(name.clone()).or_default().push(metric);
+            }
+            computed.push((name, changed));
+        }
+        insert_computed(graphs, metric, computed);
+    }
+    for (graph, flagged) in graphs.iter_mut().zip(flagged) {
+        for node in &mut graph.nodes {
+            if let Some(metrics) = flagged.get(&node.name) {
+                node.extra_fields.insert("outlier", metrics.join(","));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Add `confirmed_new` and `deaths_new` to every node that was also in the previous
+/// date's graph. Sorts the graphs by date
+pub fn add_deltas(graphs: &mut [Graph], negative: NegativeDeltas) {
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    for i in 1..graphs.len() {
+        let (before, after) = graphs.split_at_mut(i);
+        let previous = before[i - 1]
+            .nodes
+            .iter()
+            .map(|node| (node.name.as_str(), &node.metrics))
+            .collect::<HashMap<&str, &BTreeMap<MetricName, MetricValue>>>();
+
+        for node in &mut after[0].nodes {
+            let previous = match previous.get(node.name.as_str()) {
+                Some(previous) => previous,
+                None => continue,
+            };
+            for &(metric, delta) in DELTAS {
+                let change = match (node.metrics.get(metric), previous.get(metric)) {
+                    (Some(value), Some(previous)) => value.as_i64() - previous.as_i64(),
+                    _ => continue,
+                };
+                let change = match negative {
+                    _ if change >= 0 => change,
+                    NegativeDeltas::Keep => change,
+                    NegativeDeltas::Zero => 0,
+                    NegativeDeltas::Omit => continue,
+                };
+                node.metrics.insert(delta.into(), change.into());
+            }
+        }
+    }
+}
+
+/// Add `confirmed_per_100k` and `deaths_per_100k` to every node with a `population`
+pub fn add_per_capita(graphs: &mut [Graph]) {
+    for node in graphs.iter_mut().flat_map(|graph| &mut graph.nodes) {
+        let population = match node
+            .extra_fields
+            .get("population")
+            .map(|p| p.parse::<i64>())
+        {
+            Some(Ok(population)) if population > 0 => population as f64,
+            _ => continue,
+        };
+        for &(metric, per_capita) in PER_CAPITA {
+            if let Some(&value) = node.metrics.get(metric) {
+                let rate = value.as_f64() * 100_000.0 / population;
+                node.metrics.insert(per_capita.into(), rate.into());
+            }
+        }
+    }
+}
+
+/// Add `cfr_bp`, the case fatality rate in basis points, to every node with at least
+/// `min_cases` confirmed cases
+pub fn add_cfr(graphs: &mut [Graph], min_cases: i64) {
+    for node in graphs.iter_mut().flat_map(|graph| &mut graph.nodes) {
+        let confirmed = node.metrics.get("confirmed").map(|v| v.as_i64());
+        let deaths = node.metrics.get("deaths").map(|v| v.as_i64());
+        if let (Some(confirmed), Some(deaths)) = (confirmed, deaths) {
+            if confirmed >= min_cases.max(1) {
+                node.metrics
+                    .insert("cfr_bp".into(), (deaths * 10_000 / confirmed).into());
+            }
+        }
+    }
+}
+
+/// Add `doubling_days`, how many days ago a node had half its confirmed cases
+pub fn add_doubling_days(graphs: &mut [Graph]) -> Result<()> {
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    let computed = series_by_node(graphs, "confirmed", None)?
+        .into_par_iter()
+        .map(|(name, series)| (name.to_string(), doubling_days(&series)))
+        .collect();
+    insert_computed(graphs, "doubling_days", computed);
+    Ok(())
+}
+
+/// The days since the latest earlier point with at most half of each point's value, by
+/// graph. The points with nothing as low after them are the ones that can be it, and their
+/// values rise towards the latest, so each point is a binary search over them
+fn doubling_days(series: &[Point]) -> Vec<(usize, i64)> {
+    let mut lows = Vec::<&Point>::new();
+    let mut days = Vec::new();
+    for point in series {
+        let half = lows.partition_point(|low| low.value * 2 <= point.value);
+        if point.value > 0 && half > 0 {
+            days.push((point.graph, (point.date - lows[half - 1].date).num_days()));
+        }
+        while lows.last().is_some_and(|low| low.value >= point.value) {
+            lows.pop();
+        }
+        lows.push(point);
+    }
+    days
+}
+
+/// Metrics `--pct-change` compares with their value some days earlier
+const PCT_CHANGE: &[&str] = &["confirmed", "deaths"];
+
+/// Add `confirmed_pct_change_<n>d` and `deaths_pct_change_<n>d` for each window: the
+/// change since the latest value at least `n` days earlier, in basis points of that value.
+/// Left out where the earlier value is zero
+pub fn add_pct_change(graphs: &mut [Graph], windows: &[i64]) -> Result<()> {
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    for &metric in PCT_CHANGE {
+        let series = series_by_node(graphs, metric, None)?;
+        let mut by_window = Vec::new();
+        for &window in windows {
+            let computed = series
+                .par_iter()
+                .map(|(name, series)| {
+                    let values = series
+                        .iter()
+                        .enumerate()
+                        .filter_map(|(j, point)| {
+                            let earlier = series[..j]
+                                .iter()
+                                .rev()
+                                .find(|earlier| (point.date - earlier.date).num_days() >= window)
+                                .filter(|earlier| earlier.value != 0)?;
+                            let change = (point.value - earlier.value) * 10000 / earlier.value;
+                            Some((point.graph, change))
+                        })
+                        .collect();
+                    (name.to_string(), values)
+                })
+                .collect::<Vec<_>>();
+            by_window.push((window, computed));
+        }
+        for (window, computed) in by_window {
+            let name = format!("{}_pct_change_{}d", metric, window);
+            insert_computed(graphs, name, computed);
+        }
+    }
+    Ok(())
+}
+
+/// Add `active_estimated`, confirmed cases less those confirmed `recovery_days` or more
+/// days earlier and less deaths, to nodes whose series reaches that far back
+pub fn add_active(graphs: &mut [Graph], recovery_days: i64) -> Result<()> {
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    let deaths = series_by_node(graphs, "deaths", None)?;
+    let computed = series_by_node(graphs, "confirmed", None)?
+        .into_par_iter()
+        .map(|(name, series)| {
+            let deaths = deaths
+                .get(name)
+                .map(|deaths| {
+                    deaths
+                        .iter()
+                        .map(|point| (point.graph, point.value))
+                        .collect::<HashMap<_, _>>()
+                })
+                .unwrap_or_default();
+            let values = series
+                .iter()
+                .enumerate()
+                .filter_map(|(j, point)| {
+                    let recovered = series[..j]
+                        .iter()
+                        .rev()
+                        .find(|earlier| (point.date - earlier.date).num_days() >= recovery_days)?;
+                    let deaths = deaths.get(&point.graph).copied().unwrap_or(0);
+                    // Revised totals can take the estimate below zero
+                    let active = (point.value - recovered.value - deaths).max(0);
+                    Some((point.graph, active))
+                })
+                .collect();
+            (name.to_string(), values)
+        })
+        .collect();
+    insert_computed(graphs, "active_estimated", computed);
+    Ok(())
+}
+
+/// Days of new cases `--waves` averages over before looking for peaks
+const WAVE_SMOOTHING: i64 = 7;
+
+/// Days either side of a peak it has to be the highest point of
+const PEAK_DISTANCE: i64 = 28;
+
+/// Share of a node's highest smoothed new cases a peak has to reach, so the bumps of a
+/// quiet stretch aren't taken for waves
+const PEAK_PROMINENCE: f64 = 0.1;
+
+/// The highest smoothed value within `PEAK_DISTANCE` days of each point, of the points
+/// visited before it in `order`. The deque holds the points still in the window that
+/// nothing visited since beats, so their values fall from the front, the highest
+fn highest_within(
+    series: &[Point],
+    smoothed: &[f64],
+    order: impl Iterator<Item = usize>,
+) -> Vec<f64> {
+    let mut highest = vec![f64::NEG_INFINITY; series.len()];
+    let mut window = VecDeque::<usize>::new();
+    for j in order {
+        let distance = |i: usize| (series[j].date - series[i].date).num_days().abs();
+        while window.front().is_some_and(|&i| distance(i) > PEAK_DISTANCE) {
+            window.pop_front();
+        }
+        if let Some(&i) = window.front() {
+            highest[j] = smoothed[i];
+        }
+        while window.back().is_some_and(|&i| smoothed[i] <= smoothed[j]) {
+            window.pop_back();
+        }
+        window.push_back(j);
+    }
+    highest
+}
+
+/// Indexes of the peaks of a smoothed series, oldest first
+fn find_peaks(series: &[Point], smoothed: &[f64]) -> Vec<usize> {
+    let highest = smoothed.iter().copied().fold(0.0, f64::max);
+    let before = highest_within(series, smoothed, 0..series.len());
+    let after = highest_within(series, smoothed, (0..series.len()).rev());
+    (0..series.len())
+        .filter(|&j| smoothed[j] > 0.0 && smoothed[j] >= highest * PEAK_PROMINENCE)
+        // Ties go to the earliest day of a plateau
+        .filter(|&j| before[j] < smoothed[j] && after[j] <= smoothed[j])
+        .collect()
+}
+
+/// Annotate every node with the wave of new cases each date falls in: a `wave` extra
+/// field numbering the waves from 1 and a `peak_date` one with the day the wave peaked
+/// on. Peaks are the highest point of the 7 day average within 4 weeks either way, and
+/// the waves split at the lowest point between two peaks. Sorts the graphs by date
+pub fn add_waves(graphs: &mut [Graph]) -> Result<()> {
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    let waves = series_by_node(graphs, "confirmed", None)?
+        .into_par_iter()
+        .map(|(name, series)| {
+            let smoothed = (0..series.len())
+                .map(|j| {
+                    let window = series[..=j]
+                        .iter()
+                        .rev()
+                        .take_while(|earlier| {
+                            (series[j].date - earlier.date).num_days() < WAVE_SMOOTHING
+                        })
+                        .count();
+                    let start = j + 1 - window;
+                    let before = start.checked_sub(1).map_or(0, |i| series[i].value);
+                    ((series[j].value - before).max(0) as f64) / WAVE_SMOOTHING as f64
+                })
+                .collect::<Vec<_>>();
+            let peaks = find_peaks(&series, &smoothed);
+            // A wave runs until the lowest point before the next peak
+            let mut ends = peaks
+                .windows(2)
+                .map(|pair| {
+                    (pair[0]..pair[1])
+                        .min_by(|&a, &b| smoothed[a].total_cmp(&smoothed[b]))
+                        .expect("peaks are apart")
+                })
+                .collect::<Vec<_>>();
+            ends.push(series.len());
+            let mut wave = 0;
+            let values = series
+                .iter()
+                .enumerate()
+                .filter(|_| !peaks.is_empty())
+                .map(|(j, point)| {
+                    while j > ends[wave] {
+                        wave += 1;
+                    }
+                    (point.graph, wave + 1, series[peaks[wave]].date)
+                })
+                .collect::<Vec<_>>();
+            (name.to_string(), values)
+        })
+        .collect::<Vec<_>>();
+
+    let mut by_graph = vec![HashMap::new(); graphs.len()];
+    for (name, values) in waves {
+        for (graph, wave, peak_date) in values {
+            by_graph[graph].insert(name.clone(), (wave, peak_date));
+        }
+    }
+    for (graph, waves) in graphs.iter_mut().zip(by_graph) {
+        for node in &mut graph.nodes {
+            if let Some((wave, peak_date)) = waves.get(&node.name) {
+                node.extra_fields.insert("wave", wave.to_string());
+                node.extra_fields
+                    .insert("peak_date", peak_date.format("%Y-%m-%d").to_string());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Generation interval for `--rt`, as the mean and standard deviation of a gamma
+/// distribution in days
+#[derive(Debug, Clone, Copy)]
+pub struct GenerationInterval {
+    mean: f64,
+    sd: f64,
+}
+
+impl FromStr for GenerationInterval {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            anyhow!(
+                "Invalid generation interval {}, expected MEAN[,SD] in days",
+                s
+            )
+        };
+        let (mean, sd) = match s.split_once(',') {
+            Some((mean, sd)) => (mean, Some(sd)),
+            None => (s, None),
+        };
+        let mean = mean.trim().parse::<f64>().map_err(|_| invalid())?;
+        let sd = match sd {
+            Some(sd) => sd.trim().parse::<f64>().map_err(|_| invalid())?,
+            None => 0.0,
+        };
+        if !(mean > 0.0 && sd >= 0.0) {
+            return Err(invalid());
+        }
+        Ok(GenerationInterval { mean, sd })
+    }
+}
+
+impl GenerationInterval {
+    /// Reproduction number implied by an exponential growth rate per day (Wallinga and
+    /// Lipsitch), `exp(r * mean)` for a fixed interval
+    fn reproduction_number(self, r: f64) -> f64 {
+        if self.sd == 0.0 {
+            return (r * self.mean).exp();
+        }
+        let base = 1.0 + r * self.sd * self.sd / self.mean;
+        if base <= 0.0 {
+            return 0.0;
+        }
+        base.powf(self.mean * self.mean / (self.sd * self.sd))
+    }
+}
+
+/// Least squares slope of `y` over `x`
+fn slope(points: &[(f64, f64)]) -> Option<f64> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let sxx = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum::<f64>();
+    let sxy = points
+        .iter()
+        .map(|p| (p.0 - mean_x) * (p.1 - mean_y))
+        .sum::<f64>();
+    (sxx > 0.0).then(|| sxy / sxx)
+}
+
+/// Add `rt_estimate` to the state nodes: the growth rate of daily new cases, fit as an
+/// exponential over the last `window` days, turned into a reproduction number with the
+/// generation interval. In hundredths since metrics are integers
+pub fn add_rt(graphs: &mut [Graph], window: i64, interval: GenerationInterval) -> Result<()> {
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    let computed = series_by_node(graphs, "confirmed", Some("state"))?
+        .into_par_iter()
+        .map(|(name, series)| {
+            // Average new cases per day since the previous point
+            let incidence = series
+                .windows(2)
+                .map(|pair| {
+                    let days = (pair[1].date - pair[0].date).num_days() as f64;
+                    (pair[1], (pair[1].value - pair[0].value) as f64 / days)
+                })
+                .collect::<Vec<_>>();
+            let values = incidence
+                .iter()
+                .enumerate()
+                .filter_map(|(j, &(point, _))| {
+                    let fit = incidence[..=j]
+                        .iter()
+                        .rev()
+                        .take_while(|(earlier, _)| (point.date - earlier.date).num_days() < window)
+                        .filter(|(_, new)| *new > 0.0)
+                        .map(|(earlier, new)| {
+                            ((earlier.date - point.date).num_days() as f64, new.ln())
+                        })
+                        .collect::<Vec<_>>();
+                    // Too few days with cases for a meaningful fit
+                    if fit.len() < 3 {
+                        return None;
+                    }
+                    let rt = interval.reproduction_number(slope(&fit)?);
+                    Some((point.graph, (rt * 100.0).round() as i64))
+                })
+                .collect();
+            (name.to_string(), values)
+        })
+        .collect();
+    insert_computed(graphs, "rt_estimate", computed);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn series(values: &[f64], gaps: &[usize]) -> (Vec<Point>, Vec<f64>) {
+        let start = NaiveDate::from_ymd_opt(2020, 3, 1).unwrap();
+        let mut date = start;
+        let points = (0..values.len())
+            .map(|i| {
+                date += chrono::Duration::days(1 + gaps[i % gaps.len()] as i64);
+                Point {
+                    graph: i,
+                    date,
+                    value: 0,
+                }
+            })
+            .collect();
+        (points, values.to_vec())
+    }
+
+    /// Every point against every other, the way peaks were first found
+    fn rescan(series: &[Point], smoothed: &[f64]) -> Vec<usize> {
+        let highest = smoothed.iter().copied().fold(0.0, f64::max);
+        (0..series.len())
+            .filter(|&j| smoothed[j] > 0.0 && smoothed[j] >= highest * PEAK_PROMINENCE)
+            .filter(|&j| {
+                (0..series.len()).all(|i| {
+                    let distance = (series[i].date - series[j].date).num_days().abs();
+                    i == j
+                        || distance > PEAK_DISTANCE
+                        || smoothed[i] < smoothed[j]
+                        || (smoothed[i] == smoothed[j] && i > j)
+                })
+            })
+            .collect()
+    }
+
+    fn corrected(policy: Monotonic, values: &[i64]) -> (Vec<i64>, Vec<bool>) {
+        let mut values = values.to_vec();
+        let touched = policy.correct(&mut values);
+        (values, touched)
+    }
+
+    #[test]
+    fn clamp_holds_the_highest_value() {
+        let (values, touched) = corrected(Monotonic::Clamp, &[5, 10, 8, 12, 3]);
+        assert_eq!(values, vec![5, 10, 10, 12, 12]);
+        assert_eq!(touched, vec![false, false, true, false, true]);
+    }
+
+    #[test]
+    fn redistribute_scales_the_days_before() {
+        let (values, touched) = corrected(Monotonic::Redistribute, &[10, 20, 40, 20, 30]);
+        assert_eq!(values, vec![5, 10, 20, 20, 30]);
+        assert_eq!(touched, vec![true, true, true, false, false]);
+    }
+
+    #[test]
+    fn redistribute_clamps_below_zero() {
+        // From zero there's nothing to scale
+        let (values, touched) = corrected(Monotonic::Redistribute, &[0, 0, -4, 3]);
+        assert_eq!(values, vec![0, 0, 0, 3]);
+        assert_eq!(touched, vec![false, false, true, false]);
+        // A negative revision doesn't flip the signs of the days before
+        let (values, _) = corrected(Monotonic::Redistribute, &[4, 8, -2, 1]);
+        assert_eq!(values, vec![0, 0, 0, 1]);
+        let (values, _) = corrected(Monotonic::Redistribute, &[-3, -5, 2]);
+        assert_eq!(values, vec![0, 0, 2]);
+    }
+
+    #[test]
+    fn flag_leaves_the_values() {
+        let (values, touched) = corrected(Monotonic::Flag, &[5, 10, 8, 9, 11]);
+        assert_eq!(values, vec![5, 10, 8, 9, 11]);
+        assert_eq!(touched, vec![false, false, true, true, false]);
+    }
+
+    #[test]
+    fn doubling_days_match_a_rescan() {
+        let mut seed = 6789u64;
+        for gaps in [&[0][..], &[0, 2, 0, 5]] {
+            let mut total = 0;
+            let values = (0..300)
+                .map(|i| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    // Mostly rising, with revisions down now and then
+                    total += ((seed >> 33) % 40) as i64 - if i % 17 == 0 { 100 } else { 5 };
+                    total as f64
+                })
+                .collect::<Vec<_>>();
+            let (mut points, _) = series(&values, gaps);
+            for (point, &value) in points.iter_mut().zip(&values) {
+                point.value = value as i64;
+            }
+            let rescan = (0..points.len())
+                .filter(|&j| points[j].value > 0)
+                .filter_map(|j| {
+                    let half = points[..j]
+                        .iter()
+                        .rev()
+                        .find(|earlier| earlier.value * 2 <= points[j].value)?;
+                    Some((points[j].graph, (points[j].date - half.date).num_days()))
+                })
+                .collect::<Vec<_>>();
+            assert!(rescan.len() > 100);
+            assert_eq!(doubling_days(&points), rescan);
+        }
+    }
+
+    #[test]
+    fn peaks_are_apart_and_prominent() {
+        let mut values = vec![0.0; 100];
+        values[10] = 50.0;
+        values[20] = 40.0;
+        values[60] = 30.0;
+        values[95] = 2.0;
+        let (points, smoothed) = series(&values, &[0]);
+        assert_eq!(find_peaks(&points, &smoothed), vec![10, 60]);
+    }
+
+    #[test]
+    fn plateaus_peak_on_their_first_day() {
+        let (points, smoothed) = series(&[1.0, 5.0, 5.0, 5.0, 2.0], &[0]);
+        assert_eq!(find_peaks(&points, &smoothed), vec![1]);
+    }
+
+    #[test]
+    fn peaks_match_a_rescan() {
+        let mut seed = 12345u64;
+        for gaps in [&[0][..], &[0, 3, 0, 12], &[30]] {
+            let values = (0..300)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    ((seed >> 33) % 20) as f64
+                })
+                .collect::<Vec<_>>();
+            let (points, smoothed) = series(&values, gaps);
+            assert_eq!(find_peaks(&points, &smoothed), rescan(&points, &smoothed));
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Flat `nodes.csv` table for pandas or Excel
+//!
+//! One row per node per date, ordered by date then node. Every metric and extra
+//! field seen on any node gets a column, left empty where a node doesn't have it.
+
+use super::OutputDir;
+use crate::Graph;
+use anyhow::Result;
+use std::collections::BTreeSet;
+
+const FILE_NAME: &str = "nodes.csv";
+
+pub fn write(graphs: &[Graph], output_dir: &OutputDir) -> Result<usize> {
+    let nodes = || graphs.iter().flat_map(|graph| &graph.nodes);
+    let metrics = nodes()
+        .flat_map(|node| node.metrics.keys().map(|metric| metric.as_ref()))
+        .collect::<BTreeSet<_>>();
+    let extra_fields = nodes()
+        .flat_map(|node| node.extra_fields.keys().copied())
+        .collect::<BTreeSet<_>>();
+
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(
+        ["date", "id", "node", "level"]
+            .iter()
+            .chain(&metrics)
+            .chain(&extra_fields),
+    )?;
+
+    let mut graphs = graphs.iter().collect::<Vec<_>>();
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    for graph in graphs {
+        let mut nodes = graph.nodes.iter().collect::<Vec<_>>();
+        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+        for node in nodes {
+            let mut record = vec![
+                graph.timestamp.clone(),
+                node.id.clone(),
+                node.name.clone(),
+                node.level.to_string(),
+            ];
+            record.extend(metrics.iter().map(|metric| {
+                node.metrics
+                    .get(*metric)
+                    .map(|value| value.to_string())
+                    .unwrap_or_default()
+            }));
+            record.extend(
+                extra_fields
+                    .iter()
+                    .map(|field| node.extra_fields.get(field).cloned().unwrap_or_default()),
+            );
+            writer.write_record(&record)?;
+        }
+    }
+    output_dir.write(FILE_NAME, &writer.into_inner()?)?;
+    Ok(1)
+}
@@ -0,0 +1,74 @@
+//! `--graph-deltas`: a `<timestamp>.delta.json` beside the graphs for every date but the
+//! first, with the nodes added, changed and removed since the date before, so frontends
+//! can load the first graph and then apply the deltas in order
+
+use super::OutputDir;
+use crate::{Graph, Node};
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Serialize)]
+struct Delta<'a> {
+    timestamp: &'a str,
+    /// Timestamp of the graph the delta applies to
+    previous: &'a str,
+    added: Vec<&'a Node>,
+    /// The changed nodes in full, as they are on this date
+    changed: Vec<&'a Node>,
+    /// IDs of the nodes that are gone
+    removed: Vec<&'a str>,
+}
+
+fn delta<'a>(previous: &'a Graph, graph: &'a Graph) -> Delta<'a> {
+    let old = previous
+        .nodes
+        .iter()
+        .map(|node| (node.id.as_str(), node))
+        .collect::<HashMap<_, _>>();
+    let new = graph
+        .nodes
+        .iter()
+        .map(|node| (node.id.as_str(), node))
+        .collect::<HashMap<_, _>>();
+    let mut delta = Delta {
+        timestamp: &graph.timestamp,
+        previous: &previous.timestamp,
+        added: Vec::new(),
+        changed: Vec::new(),
+        removed: Vec::new(),
+    };
+    for node in &graph.nodes {
+        match old.get(node.id.as_str()) {
+            None => delta.added.push(node),
+            Some(&old) if old != node => delta.changed.push(node),
+            Some(_) => {}
+        }
+    }
+    delta.removed = previous
+        .nodes
+        .iter()
+        .map(|node| node.id.as_str())
+        .filter(|id| !new.contains_key(id))
+        .collect();
+    delta.added.sort_by(|a, b| a.id.cmp(&b.id));
+    delta.changed.sort_by(|a, b| a.id.cmp(&b.id));
+    delta.removed.sort_unstable();
+    delta
+}
+
+pub fn write(graphs: &[Graph], pretty: bool, output_dir: &OutputDir) -> Result<usize> {
+    let mut graphs = graphs.iter().collect::<Vec<_>>();
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    for pair in graphs.windows(2) {
+        let delta = delta(pair[0], pair[1]);
+        let json = if pretty {
+            serde_json::to_vec_pretty(&delta)?
+        } else {
+            serde_json::to_vec(&delta)?
+        };
+        let name = output_dir.compressed_name(&format!("{}.delta.json", pair[1].timestamp));
+        output_dir.write_file(&name, Some(pair[1]), &json)?;
+    }
+    Ok(graphs.len().saturating_sub(1))
+}
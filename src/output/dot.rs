@@ -0,0 +1,63 @@
+//! One Graphviz DOT file per date, for quick looks at small subsets with `dot -Tsvg`
+//!
+//! Node labels show the node name with its confirmed and deaths counts. Edges between
+//! neighbours are dashed and commuting flows dotted.
+
+use super::OutputDir;
+use crate::{EdgeKind, Graph};
+use anyhow::Result;
+use rayon::prelude::*;
+use std::fmt::Write;
+
+/// Quote a DOT identifier or label
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn to_dot(graph: &Graph) -> String {
+    let mut dot = String::new();
+    // Writing into a String can't fail
+    let _ = writeln!(dot, "digraph {} {{", quote(&graph.timestamp));
+    let _ = writeln!(dot, "  node [shape=box];");
+    for node in &graph.nodes {
+        let metric = |name| node.metrics.get(name).copied().unwrap_or_default();
+        let label = format!(
+            "{}\nconfirmed: {}\ndeaths: {}",
+            node.name,
+            metric("confirmed"),
+            metric("deaths")
+        );
+        let _ = writeln!(
+            dot,
+            "  {} [label={}];",
+            quote(&node.id),
+            quote(&label).replace('\n', "\\n")
+        );
+    }
+    for node in &graph.nodes {
+        for edge in &node.edges {
+            let style = match edge.kind {
+                EdgeKind::Contains => "",
+                EdgeKind::Adjacent => " [style=dashed]",
+                EdgeKind::Commute => " [style=dotted]",
+            };
+            let _ = writeln!(
+                dot,
+                "  {} -> {}{};",
+                quote(&node.id),
+                quote(&edge.target),
+                style
+            );
+        }
+    }
+    let _ = writeln!(dot, "}}");
+    dot
+}
+
+pub fn write(graphs: &[Graph], output_dir: &OutputDir) -> Result<usize> {
+    graphs
+        .par_iter()
+        .map(|graph| output_dir.write_dated(graph, "dot", to_dot(graph).as_bytes()))
+        .collect::<Result<()>>()?;
+    Ok(graphs.len())
+}
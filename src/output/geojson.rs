@@ -0,0 +1,241 @@
+//! One GeoJSON FeatureCollection per date, for Mapbox or Leaflet choropleths
+//!
+//! County geometries come from `--geometries`, a GeoJSON FeatureCollection or a
+//! TopoJSON topology (its `counties` object, or the first one). Features are
+//! matched to county nodes by FIPS code, taken from the feature `id` or a
+//! `GEOID`, `GEO_ID` or `FIPS` property. Each output feature carries the node's
+//! metrics and extra fields as properties. Nodes without a geometry are left out.
+
+use super::OutputDir;
+use crate::source::normalize_fips;
+use crate::Graph;
+use anyhow::{anyhow, Context, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// County geometries keyed by FIPS code
+pub type Geometries = HashMap<String, Value>;
+
+/// FIPS code of a GeoJSON feature or TopoJSON geometry object
+fn feature_fips(feature: &Value) -> Option<String> {
+    let properties = feature.get("properties");
+    let property = |name| properties.and_then(|p| p.get(name));
+    let raw = feature
+        .get("id")
+        .or_else(|| property("GEOID"))
+        .or_else(|| property("GEO_ID"))
+        .or_else(|| property("FIPS"))?;
+    let raw = match raw {
+        Value::String(s) => s.rsplit("US").next().unwrap_or(s).to_string(),
+        Value::Number(n) => n.to_string(),
+        _ => return None,
+    };
+    normalize_fips(&raw)
+}
+
+/// Decode the delta encoded, quantized arcs of a topology into coordinates
+fn decode_arcs(topology: &Value) -> Result<Vec<Vec<[f64; 2]>>> {
+    let transform = match topology.get("transform") {
+        Some(transform) => {
+            let pair = |name| -> Option<[f64; 2]> {
+                let values = transform.get(name)?.as_array()?;
+                Some([values.first()?.as_f64()?, values.get(1)?.as_f64()?])
+            };
+            Some((
+                pair("scale").ok_or_else(|| anyhow!("Invalid TopoJSON transform"))?,
+                pair("translate").ok_or_else(|| anyhow!("Invalid TopoJSON transform"))?,
+            ))
+        }
+        None => None,
+    };
+    let arcs = topology
+        .get("arcs")
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow!("TopoJSON topology has no arcs"))?;
+
+    arcs.iter()
+        .map(|arc| {
+            let (mut x, mut y) = (0.0, 0.0);
+            arc.as_array()
+                .ok_or_else(|| anyhow!("Invalid TopoJSON arc"))?
+                .iter()
+                .map(|position| {
+                    let position = position.as_array().filter(|p| p.len() >= 2);
+                    let coordinate = |i: usize| position.and_then(|p| p[i].as_f64());
+                    let (px, py) = coordinate(0)
+                        .zip(coordinate(1))
+                        .ok_or_else(|| anyhow!("Invalid TopoJSON position"))?;
+                    Ok(match transform {
+                        Some(([sx, sy], [tx, ty])) => {
+                            x += px;
+                            y += py;
+                            [x * sx + tx, y * sy + ty]
+                        }
+                        None => [px, py],
+                    })
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Stitch a ring out of arc indexes, negative ones meaning the reversed arc `!i`
+fn ring(arcs: &[Vec<[f64; 2]>], indexes: &Value) -> Option<Vec<[f64; 2]>> {
+    let mut points: Vec<[f64; 2]> = Vec::new();
+    for index in indexes.as_array()? {
+        let index = index.as_i64()?;
+        let mut arc = arcs
+            .get(if index < 0 { !index } else { index } as usize)?
+            .clone();
+        if index < 0 {
+            arc.reverse();
+        }
+        // Consecutive arcs share their end points
+        let skip = if points.is_empty() { 0 } else { 1 };
+        points.extend(arc.into_iter().skip(skip));
+    }
+    Some(points)
+}
+
+fn polygon(arcs: &[Vec<[f64; 2]>], rings: &Value) -> Option<Value> {
+    let rings = rings
+        .as_array()?
+        .iter()
+        .map(|indexes| ring(arcs, indexes))
+        .collect::<Option<Vec<_>>>()?;
+    Some(json!(rings))
+}
+
+fn topology_geometries(topology: &Value) -> Result<Geometries> {
+    let arcs = decode_arcs(topology)?;
+    let objects = topology
+        .get("objects")
+        .and_then(Value::as_object)
+        .ok_or_else(|| anyhow!("TopoJSON topology has no objects"))?;
+    let counties = objects
+        .get("counties")
+        .or_else(|| objects.values().next())
+        .and_then(|object| object.get("geometries"))
+        .and_then(Value::as_array)
+        .ok_or_else(|| anyhow!("TopoJSON topology has no geometry collection"))?;
+
+    let mut geometries = Geometries::new();
+    for object in counties {
+        let fips = match feature_fips(object) {
+            Some(fips) => fips,
+            None => continue,
+        };
+        let rings = &object["arcs"];
+        let geometry = match object["type"].as_str() {
+            Some("Polygon") => polygon(&arcs, rings)
+                .map(|coordinates| json!({"type": "Polygon", "coordinates": coordinates})),
+            Some("MultiPolygon") => rings
+                .as_array()
+                .and_then(|polygons| {
+                    polygons
+                        .iter()
+                        .map(|rings| polygon(&arcs, rings))
+                        .collect::<Option<Vec<_>>>()
+                })
+                .map(|coordinates| json!({"type": "MultiPolygon", "coordinates": coordinates})),
+            _ => None,
+        };
+        let geometry =
+            geometry.ok_or_else(|| anyhow!("Invalid TopoJSON geometry for county {}", fips))?;
+        geometries.insert(fips, geometry);
+    }
+    Ok(geometries)
+}
+
+/// Load county geometries from a GeoJSON or TopoJSON file
+pub fn load(path: &Path) -> Result<Geometries> {
+    let data =
+        fs::read(path).with_context(|| format!("Failed to read geometries {}", path.display()))?;
+    let mut document: Value =
+        serde_json::from_slice(&data).context("Failed to parse geometries JSON")?;
+
+    match document["type"].as_str() {
+        Some("Topology") => topology_geometries(&document),
+        Some("FeatureCollection") => {
+            let features = match document["features"].take() {
+                Value::Array(features) => features,
+                _ => return Err(anyhow!("GeoJSON FeatureCollection has no features")),
+            };
+            Ok(features
+                .into_iter()
+                .filter_map(|mut feature| {
+                    let fips = feature_fips(&feature)?;
+                    Some((fips, feature["geometry"].take()))
+                })
+                .collect())
+        }
+        _ => Err(anyhow!(
+            "Geometries must be a GeoJSON FeatureCollection or a TopoJSON Topology"
+        )),
+    }
+}
+
+#[derive(Serialize)]
+struct Feature<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    id: &'a str,
+    geometry: &'a Value,
+    properties: Map<String, Value>,
+}
+
+#[derive(Serialize)]
+struct FeatureCollection<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    timestamp: &'a str,
+    features: Vec<Feature<'a>>,
+}
+
+fn feature_collection<'a>(graph: &'a Graph, geometries: &'a Geometries) -> FeatureCollection<'a> {
+    let mut features = graph
+        .nodes
+        .iter()
+        .filter_map(|node| {
+            let fips = node.fips.as_deref()?;
+            let geometry = geometries.get(fips)?;
+            let mut properties = Map::new();
+            properties.insert("id".to_string(), json!(node.id));
+            properties.insert("name".to_string(), json!(node.name));
+            properties.insert("level".to_string(), json!(node.level));
+            for (field, value) in &node.extra_fields {
+                properties.insert(field.to_string(), json!(value));
+            }
+            for (metric, value) in &node.metrics {
+                properties.insert(metric.to_string(), json!(value));
+            }
+            Some(Feature {
+                kind: "Feature",
+                id: fips,
+                geometry,
+                properties,
+            })
+        })
+        .collect::<Vec<_>>();
+    features.sort_by(|a, b| a.id.cmp(b.id));
+    FeatureCollection {
+        kind: "FeatureCollection",
+        timestamp: &graph.timestamp,
+        features,
+    }
+}
+
+pub fn write(graphs: &[Graph], geometries: &Geometries, output_dir: &OutputDir) -> Result<usize> {
+    graphs
+        .par_iter()
+        .map(|graph| {
+            let json = serde_json::to_vec(&feature_collection(graph, geometries))?;
+            output_dir.write_dated(graph, "geojson", &json)
+        })
+        .collect::<Result<()>>()?;
+    Ok(graphs.len())
+}
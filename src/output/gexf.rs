@@ -0,0 +1,187 @@
+//! The whole series as a single dynamic GEXF file, for Gephi's timeline
+//!
+//! Nodes keep their ID across dates. Metrics and extra fields are
+//! dynamic attributes, with consecutive days of the same value merged into one
+//! spell. Nodes and edges only exist on the days they appear in the graphs. Edges are
+//! labelled with their kind and carry their latest weight.
+
+use super::{escape_xml, metric_types, OutputDir};
+use crate::{Graph, Node};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Duration, NaiveDate};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+const FILE_NAME: &str = "covid.gexf";
+
+/// Merge consecutive days with equal values into `(first, last, value)` runs
+fn runs<T: PartialEq>(
+    days: impl IntoIterator<Item = (NaiveDate, T)>,
+) -> Vec<(NaiveDate, NaiveDate, T)> {
+    let mut runs: Vec<(NaiveDate, NaiveDate, T)> = Vec::new();
+    for (day, value) in days {
+        match runs.last_mut() {
+            Some((_, last, run_value))
+                if *last + Duration::days(1) == day && *run_value == value =>
+            {
+                *last = day;
+            }
+            _ => runs.push((day, day, value)),
+        }
+    }
+    runs
+}
+
+/// GEXF intervals are written as `start` and exclusive `endopen`
+fn interval(first: NaiveDate, last: NaiveDate) -> String {
+    format!(
+        r#"start="{}" endopen="{}""#,
+        first,
+        last + Duration::days(1)
+    )
+}
+
+fn spells(xml: &mut String, indent: &str, days: &BTreeSet<NaiveDate>) {
+    let _ = writeln!(xml, "{}<spells>", indent);
+    for (first, last, _) in runs(days.iter().map(|&day| (day, ()))) {
+        let _ = writeln!(xml, "{}  <spell {}/>", indent, interval(first, last));
+    }
+    let _ = writeln!(xml, "{}</spells>", indent);
+}
+
+pub fn write(graphs: &[Graph], output_dir: &OutputDir) -> Result<usize> {
+    let mut series = BTreeMap::<&str, BTreeMap<NaiveDate, &Node>>::new();
+    let mut edges = BTreeMap::<(&str, &str, &str), (BTreeSet<NaiveDate>, Option<f64>)>::new();
+    for graph in graphs {
+        let day = DateTime::parse_from_rfc3339(&graph.timestamp)
+            .with_context(|| format!("Invalid graph timestamp {}", graph.timestamp))?
+            .date_naive();
+        for node in &graph.nodes {
+            series.entry(&node.id).or_default().insert(day, node);
+            for edge in &node.edges {
+                let (days, weight) = edges
+                    .entry((&node.id, &edge.target, edge.kind.as_str()))
+                    .or_default();
+                days.insert(day);
+                *weight = edge.weight.or(*weight);
+            }
+        }
+    }
+    let nodes = || series.values().flat_map(|days| days.values());
+    let metrics = metric_types(nodes().copied());
+    let extra_fields = nodes()
+        .flat_map(|node| node.extra_fields.keys().copied())
+        .collect::<BTreeSet<_>>();
+
+    let mut xml = String::new();
+    // Writing into a String can't fail
+    let _ = writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(xml, r#"<gexf xmlns="http://gexf.net/1.3" version="1.3">"#);
+    let _ = writeln!(
+        xml,
+        r#"  <graph mode="dynamic" defaultedgetype="directed" timeformat="date">"#
+    );
+    let _ = writeln!(xml, r#"    <attributes class="node" mode="static">"#);
+    let _ = writeln!(
+        xml,
+        r#"      <attribute id="level" title="level" type="string"/>"#
+    );
+    let _ = writeln!(xml, "    </attributes>");
+    let _ = writeln!(xml, r#"    <attributes class="node" mode="dynamic">"#);
+    for (metric, &float) in &metrics {
+        let _ = writeln!(
+            xml,
+            r#"      <attribute id="{0}" title="{0}" type="{1}"/>"#,
+            escape_xml(metric),
+            if float { "double" } else { "long" }
+        );
+    }
+    for field in &extra_fields {
+        let _ = writeln!(
+            xml,
+            r#"      <attribute id="{0}" title="{0}" type="string"/>"#,
+            escape_xml(field)
+        );
+    }
+    let _ = writeln!(xml, "    </attributes>");
+
+    let _ = writeln!(xml, "    <nodes>");
+    for (id, days) in &series {
+        let latest = days.values().next_back().expect("series has a day");
+        let label = latest
+            .extra_fields
+            .get("display_name")
+            .unwrap_or(&latest.name);
+        let _ = writeln!(
+            xml,
+            r#"      <node id="{}" label="{}">"#,
+            escape_xml(id),
+            escape_xml(label)
+        );
+        let _ = writeln!(xml, "        <attvalues>");
+        let _ = writeln!(
+            xml,
+            r#"          <attvalue for="level" value="{}"/>"#,
+            latest.level
+        );
+        for metric in metrics.keys() {
+            let values = days
+                .iter()
+                .filter_map(|(&day, node)| node.metrics.get(*metric).map(|value| (day, value)));
+            for (first, last, value) in runs(values) {
+                let _ = writeln!(
+                    xml,
+                    r#"          <attvalue for="{}" value="{}" {}/>"#,
+                    escape_xml(metric),
+                    value,
+                    interval(first, last)
+                );
+            }
+        }
+        for field in &extra_fields {
+            let values = days
+                .iter()
+                .filter_map(|(&day, node)| node.extra_fields.get(field).map(|value| (day, value)));
+            for (first, last, value) in runs(values) {
+                let _ = writeln!(
+                    xml,
+                    r#"          <attvalue for="{}" value="{}" {}/>"#,
+                    escape_xml(field),
+                    escape_xml(value),
+                    interval(first, last)
+                );
+            }
+        }
+        let _ = writeln!(xml, "        </attvalues>");
+        spells(&mut xml, "        ", &days.keys().copied().collect());
+        let _ = writeln!(xml, "      </node>");
+    }
+    let _ = writeln!(xml, "    </nodes>");
+
+    let _ = writeln!(xml, "    <edges>");
+    for (i, ((source, target, kind), (days, weight))) in edges.iter().enumerate() {
+        if !series.contains_key(target) {
+            continue;
+        }
+        let weight = weight
+            .map(|weight| format!(r#" weight="{}""#, weight))
+            .unwrap_or_default();
+        let _ = writeln!(
+            xml,
+            r#"      <edge id="{}" source="{}" target="{}" label="{}"{}>"#,
+            i,
+            escape_xml(source),
+            escape_xml(target),
+            kind,
+            weight
+        );
+        spells(&mut xml, "        ", days);
+        let _ = writeln!(xml, "      </edge>");
+    }
+    let _ = writeln!(xml, "    </edges>");
+    let _ = writeln!(xml, "  </graph>");
+    let _ = writeln!(xml, "</gexf>");
+
+    output_dir.write(FILE_NAME, xml.as_bytes())?;
+    Ok(1)
+}
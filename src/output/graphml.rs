@@ -0,0 +1,132 @@
+//! One GraphML file per date, for Gephi or yEd
+//!
+//! Metrics become `long` node attributes and extra fields `string` ones. Nodes
+//! are identified by their ID with the name as an attribute. Edges are directed,
+//! with `kind` and `weight` attributes.
+
+use super::{escape_xml, metric_types, OutputDir};
+use crate::Graph;
+use anyhow::Result;
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashSet};
+use std::fmt::Write;
+
+fn to_graphml(graph: &Graph) -> String {
+    let metrics = metric_types(graph.nodes.iter());
+    let extra_fields = graph
+        .nodes
+        .iter()
+        .flat_map(|node| node.extra_fields.keys().copied())
+        .collect::<BTreeSet<_>>();
+    let ids = graph
+        .nodes
+        .iter()
+        .map(|node| node.id.as_str())
+        .collect::<HashSet<_>>();
+
+    let mut xml = String::new();
+    // Writing into a String can't fail
+    let _ = writeln!(xml, r#"<?xml version="1.0" encoding="UTF-8"?>"#);
+    let _ = writeln!(
+        xml,
+        r#"<graphml xmlns="http://graphml.graphdrawing.org/xmlns">"#
+    );
+    let _ = writeln!(
+        xml,
+        r#"  <key id="name" for="node" attr.name="name" attr.type="string"/>"#
+    );
+    let _ = writeln!(
+        xml,
+        r#"  <key id="kind" for="edge" attr.name="kind" attr.type="string"/>"#
+    );
+    let _ = writeln!(
+        xml,
+        r#"  <key id="weight" for="edge" attr.name="weight" attr.type="double"/>"#
+    );
+    let _ = writeln!(
+        xml,
+        r#"  <key id="level" for="node" attr.name="level" attr.type="string"/>"#
+    );
+    for (metric, &float) in &metrics {
+        let _ = writeln!(
+            xml,
+            r#"  <key id="{0}" for="node" attr.name="{0}" attr.type="{1}"/>"#,
+            escape_xml(metric),
+            if float { "double" } else { "long" }
+        );
+    }
+    for field in &extra_fields {
+        let _ = writeln!(
+            xml,
+            r#"  <key id="{0}" for="node" attr.name="{0}" attr.type="string"/>"#,
+            escape_xml(field)
+        );
+    }
+    let _ = writeln!(
+        xml,
+        r#"  <graph id="{}" edgedefault="directed">"#,
+        escape_xml(&graph.timestamp)
+    );
+
+    for node in &graph.nodes {
+        let _ = writeln!(xml, r#"    <node id="{}">"#, escape_xml(&node.id));
+        let _ = writeln!(
+            xml,
+            r#"      <data key="name">{}</data>"#,
+            escape_xml(&node.name)
+        );
+        let _ = writeln!(xml, r#"      <data key="level">{}</data>"#, node.level);
+        for (metric, value) in &node.metrics {
+            let _ = writeln!(
+                xml,
+                r#"      <data key="{}">{}</data>"#,
+                escape_xml(metric),
+                value
+            );
+        }
+        for (field, value) in &node.extra_fields {
+            let _ = writeln!(
+                xml,
+                r#"      <data key="{}">{}</data>"#,
+                escape_xml(field),
+                escape_xml(value)
+            );
+        }
+        let _ = writeln!(xml, "    </node>");
+    }
+    for node in &graph.nodes {
+        for edge in node
+            .edges
+            .iter()
+            .filter(|edge| ids.contains(edge.target.as_str()))
+        {
+            let _ = writeln!(
+                xml,
+                r#"    <edge source="{}" target="{}">"#,
+                escape_xml(&node.id),
+                escape_xml(&edge.target)
+            );
+            let _ = writeln!(
+                xml,
+                r#"      <data key="kind">{}</data>"#,
+                edge.kind.as_str()
+            );
+            if let Some(weight) = edge.weight {
+                let _ = writeln!(xml, r#"      <data key="weight">{}</data>"#, weight);
+            }
+            let _ = writeln!(xml, "    </edge>");
+        }
+    }
+
+    let _ = writeln!(xml, "  </graph>");
+    let _ = writeln!(xml, "</graphml>");
+    xml
+}
+
+pub fn write(graphs: &[Graph], output_dir: &OutputDir) -> Result<usize> {
+    graphs
+        .par_iter()
+        .map(|graph| output_dir.write_dated(graph, "graphml", to_graphml(graph).as_bytes()))
+        .collect::<Result<()>>()?;
+    Ok(graphs.len())
+}
@@ -0,0 +1,106 @@
+//! `covid.lp` in InfluxDB line protocol
+//!
+//! A point per node per date in the `covid` measurement, e.g.
+//! `covid,level=county,state=NY,county=Kings confirmed=123i,deaths=4i 1585699200000000000`.
+//! Metrics are integer fields and timestamps are in nanoseconds, Influx's default
+//! precision. States are tagged with their postal abbreviation where there is one,
+//! and counties and ZCTAs with the regions above them.
+//!
+//! ```sh
+//! influx write --bucket covid --file covid.lp
+//! ```
+
+use super::{level_depth, OutputDir};
+use crate::{states, Graph, Node};
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+const FILE_NAME: &str = "covid.lp";
+const MEASUREMENT: &str = "covid";
+
+/// Escape a tag key, tag value or field key
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if matches!(c, ',' | '=' | ' ' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn display_name(node: &Node) -> &str {
+    node.extra_fields
+        .get("display_name")
+        .map(String::as_str)
+        .unwrap_or(&node.name)
+}
+
+pub fn write(graphs: &[Graph], output_dir: &OutputDir) -> Result<usize> {
+    let mut graphs = graphs.iter().collect::<Vec<_>>();
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+
+    let mut out = String::new();
+    for graph in graphs {
+        let timestamp = DateTime::parse_from_rfc3339(&graph.timestamp)?
+            .timestamp_nanos_opt()
+            .ok_or_else(|| anyhow!("{} is out of range", graph.timestamp))?;
+
+        let nodes = graph
+            .nodes
+            .iter()
+            .map(|node| (node.id.as_str(), node))
+            .collect::<HashMap<_, _>>();
+        // Each county and ZCTA is edged from the region directly above it
+        let mut parents = HashMap::new();
+        for node in &graph.nodes {
+            for edge in &node.edges {
+                if let Some(child) = nodes.get(edge.target.as_str()) {
+                    if level_depth(child.level) > level_depth(node.level) {
+                        parents.insert(child.id.as_str(), node);
+                    }
+                }
+            }
+        }
+
+        let mut sorted = graph.nodes.iter().collect::<Vec<_>>();
+        sorted.sort_by(|a, b| a.name.cmp(&b.name));
+        for node in sorted {
+            // A point needs at least one field
+            if node.metrics.is_empty() {
+                continue;
+            }
+
+            let mut tags = vec![("level", node.level.to_string())];
+            let mut region = Some(node);
+            while let Some(current) = region {
+                let tag = match current.level {
+                    "state" => states::abbreviation(&current.name)
+                        .unwrap_or(&current.name)
+                        .to_string(),
+                    _ => display_name(current).to_string(),
+                };
+                tags.push((current.level, tag));
+                region = parents.get(current.id.as_str()).copied();
+            }
+
+            // Writing into a String can't fail
+            let _ = write!(out, "{}", MEASUREMENT);
+            for (key, value) in tags.iter().rev() {
+                let _ = write!(out, ",{}={}", escape(key), escape(value));
+            }
+            for (i, (metric, value)) in node.metrics.iter().enumerate() {
+                let separator = if i == 0 { ' ' } else { ',' };
+                // Integers need their suffix, a bare number is a float
+                let suffix = if value.is_float() { "" } else { "i" };
+                let _ = write!(out, "{}{}={}{}", separator, escape(metric), value, suffix);
+            }
+            let _ = writeln!(out, " {}", timestamp);
+        }
+    }
+    output_dir.write(FILE_NAME, out.as_bytes())?;
+    Ok(1)
+}
@@ -0,0 +1,79 @@
+//! The original output: one JSON file per date, named by its timestamp, or with
+//! `--single-file` one array of all graphs ordered by date. Compact unless `--pretty`
+
+use super::{OutputDir, OutputSink};
+use crate::Graph;
+use anyhow::Result;
+use serde::Serialize;
+
+const SINGLE_FILE_NAME: &str = "graphs.json";
+
+fn to_json<T: Serialize>(value: &T, pretty: bool) -> Result<String> {
+    Ok(if pretty {
+        serde_json::to_string_pretty(value)?
+    } else {
+        serde_json::to_string(value)?
+    })
+}
+
+/// A file per graph, written as it comes
+pub struct JsonFiles<'a> {
+    output_dir: &'a OutputDir<'a>,
+    pretty: bool,
+    num_files: usize,
+}
+
+impl<'a> JsonFiles<'a> {
+    pub fn new(output_dir: &'a OutputDir<'a>, pretty: bool) -> Self {
+        JsonFiles {
+            output_dir,
+            pretty,
+            num_files: 0,
+        }
+    }
+}
+
+impl OutputSink for JsonFiles<'_> {
+    fn write(&mut self, graph: &Graph) -> Result<()> {
+        let json = to_json(graph, self.pretty)?;
+        self.output_dir
+            .write_dated(graph, "json", json.as_bytes())?;
+        self.num_files += 1;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<usize> {
+        Ok(self.num_files)
+    }
+}
+
+/// `graphs.json`, written once every graph is in
+pub struct JsonArray<'a> {
+    output_dir: &'a OutputDir<'a>,
+    pretty: bool,
+    graphs: Vec<Graph>,
+}
+
+impl<'a> JsonArray<'a> {
+    pub fn new(output_dir: &'a OutputDir<'a>, pretty: bool) -> Self {
+        JsonArray {
+            output_dir,
+            pretty,
+            graphs: Vec::new(),
+        }
+    }
+}
+
+impl OutputSink for JsonArray<'_> {
+    fn write(&mut self, graph: &Graph) -> Result<()> {
+        self.graphs.push(graph.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<usize> {
+        self.graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+        let json = to_json(&self.graphs, self.pretty)?;
+        self.output_dir.write(SINGLE_FILE_NAME, json.as_bytes())?;
+        Ok(1)
+    }
+}
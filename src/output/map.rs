@@ -0,0 +1,275 @@
+//! Choropleth maps of confirmed cases per 100,000 people, one per date
+//!
+//! Counties are drawn from `--geometries` (see the GeoJSON output) in an Albers USA
+//! projection, with Alaska and Hawaii moved into insets, and shaded by the
+//! `population` field from `--population`. The seven colour classes are quantiles of
+//! the whole series, so maps of different dates can be compared. Counties without a
+//! geometry are left out, ones without cases or population are grey.
+//!
+//! `png` renders the same SVG with resvg and needs a build with the `png` feature.
+
+use super::geojson::Geometries;
+use super::{escape_xml, OutputDir};
+use crate::Graph;
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use rayon::prelude::*;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+const WIDTH: f64 = 960.0;
+const HEIGHT: f64 = 560.0;
+const COLORS: &[&str] = &[
+    "#ffffb2", "#fed976", "#feb24c", "#fd8d3c", "#fc4e2a", "#e31a1c", "#b10026",
+];
+const NO_DATA_COLOR: &str = "#dddddd";
+// Named fonts first, resvg maps the generic `sans-serif` to Arial only
+const FONT: &str = "Helvetica, Arial, DejaVu Sans, Liberation Sans, sans-serif";
+
+/// An Albers equal-area conic projection, as d3 sets them up for `geoAlbersUsa`
+struct Albers {
+    rotate: f64,
+    center: [f64; 2],
+    parallels: [f64; 2],
+    scale: f64,
+    translate: [f64; 2],
+}
+
+impl Albers {
+    fn raw(&self, lon: f64, lat: f64) -> [f64; 2] {
+        let [phi0, phi1] = self.parallels.map(f64::to_radians);
+        let n = (phi0.sin() + phi1.sin()) / 2.0;
+        let c = 1.0 + phi0.sin() * (2.0 * n - phi0.sin());
+        let r0 = c.sqrt() / n;
+        let r = (c - 2.0 * n * lat.to_radians().sin()).max(0.0).sqrt() / n;
+        let lambda = lon.to_radians() * n;
+        [r * lambda.sin(), r0 - r * lambda.cos()]
+    }
+
+    fn project(&self, lon: f64, lat: f64) -> [f64; 2] {
+        let [cx, cy] = self.raw(self.center[0], self.center[1]);
+        let [x, y] = self.raw(lon + self.rotate, lat);
+        [
+            self.translate[0] + self.scale * (x - cx),
+            self.translate[1] - self.scale * (y - cy),
+        ]
+    }
+}
+
+/// Albers USA: the lower 48 with Alaska and Hawaii insets, picked by state FIPS
+fn projection(fips: &str) -> Albers {
+    let (k, [tx, ty]) = (1070.0, [WIDTH / 2.0, 290.0]);
+    match &fips[..2] {
+        "02" => Albers {
+            rotate: 154.0,
+            center: [-2.0, 58.5],
+            parallels: [55.0, 65.0],
+            scale: 0.35 * k,
+            translate: [tx - 0.307 * k, ty + 0.201 * k],
+        },
+        "15" => Albers {
+            rotate: 157.0,
+            center: [-3.0, 19.9],
+            parallels: [8.0, 18.0],
+            scale: k,
+            translate: [tx - 0.205 * k, ty + 0.212 * k],
+        },
+        _ => Albers {
+            rotate: 96.0,
+            center: [-0.6, 38.7],
+            parallels: [29.5, 45.5],
+            scale: k,
+            translate: [tx, ty],
+        },
+    }
+}
+
+/// SVG path data for a GeoJSON Polygon or MultiPolygon
+fn path_data(fips: &str, geometry: &Value) -> Option<String> {
+    let polygons = match geometry["type"].as_str()? {
+        "Polygon" => vec![&geometry["coordinates"]],
+        "MultiPolygon" => geometry["coordinates"].as_array()?.iter().collect(),
+        _ => return None,
+    };
+    let projection = projection(fips);
+    let mut data = String::new();
+    for rings in polygons {
+        for ring in rings.as_array()? {
+            for (i, position) in ring.as_array()?.iter().enumerate() {
+                let lon = position.get(0)?.as_f64()?;
+                let lat = position.get(1)?.as_f64()?;
+                let [x, y] = projection.project(lon, lat);
+                let command = if i == 0 { 'M' } else { 'L' };
+                // Writing into a String can't fail
+                let _ = write!(data, "{}{:.1} {:.1}", command, x, y);
+            }
+            data.push('Z');
+        }
+    }
+    Some(data)
+}
+
+/// Confirmed cases per 100,000 people of each county node with a population, by FIPS
+fn per_capita(graph: &Graph) -> HashMap<&str, f64> {
+    graph
+        .nodes
+        .iter()
+        .filter_map(|node| {
+            let fips = node.fips.as_deref()?;
+            let population = node.extra_fields.get("population")?.parse::<f64>().ok()?;
+            let confirmed = node.metrics.get("confirmed")?.as_f64();
+            (population > 0.0).then(|| (fips, confirmed * 1e5 / population))
+        })
+        .collect()
+}
+
+/// Upper bounds of all but the last colour class, rounded to two significant digits.
+/// Fewer than seven classes when the quantiles round to the same value
+fn breaks(mut values: Vec<f64>) -> Vec<f64> {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mut breaks = (1..COLORS.len())
+        .map(|i| {
+            let value = values[i * (values.len() - 1) / COLORS.len()];
+            let magnitude = 10f64.powf(value.abs().log10().floor() - 1.0);
+            if value > 0.0 {
+                (value / magnitude).round() * magnitude
+            } else {
+                0.0
+            }
+        })
+        .collect::<Vec<_>>();
+    breaks.dedup();
+    breaks
+}
+
+fn color(breaks: &[f64], value: Option<f64>) -> &'static str {
+    match value {
+        Some(value) => COLORS[breaks.iter().take_while(|&&b| value >= b).count()],
+        None => NO_DATA_COLOR,
+    }
+}
+
+fn to_svg(graph: &Graph, paths: &HashMap<&str, String>, breaks: &[f64]) -> Result<String> {
+    let date = DateTime::parse_from_rfc3339(&graph.timestamp)?.format("%Y-%m-%d");
+    let values = per_capita(graph);
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" font-family=\"{f}\">",
+        w = WIDTH,
+        h = HEIGHT,
+        f = FONT
+    );
+    let _ = writeln!(svg, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>");
+    let _ = writeln!(
+        svg,
+        "<text x=\"20\" y=\"36\" font-size=\"22\">Confirmed cases per 100,000 people, {}</text>",
+        date
+    );
+
+    let mut counties = paths.iter().collect::<Vec<_>>();
+    counties.sort_by(|a, b| a.0.cmp(b.0));
+    let _ = writeln!(svg, "<g stroke=\"white\" stroke-width=\"0.3\">");
+    for (fips, data) in counties {
+        let _ = writeln!(
+            svg,
+            "<path id=\"c{}\" fill=\"{}\" d=\"{}\"/>",
+            fips,
+            color(breaks, values.get(fips).copied()),
+            data
+        );
+    }
+    let _ = writeln!(svg, "</g>");
+
+    let mut labels = Vec::new();
+    for (i, to) in breaks.iter().enumerate() {
+        labels.push(match i.checked_sub(1).map(|j| breaks[j]) {
+            Some(from) => format!("{} – {}", from, to),
+            None => format!("under {}", to),
+        });
+    }
+    labels.extend(breaks.last().map(|last| format!("{} and over", last)));
+    labels.push("no data".to_string());
+    let _ = writeln!(svg, "<g font-size=\"12\">");
+    let colors = COLORS[..=breaks.len()].iter().chain([&NO_DATA_COLOR]);
+    for (i, (label, color)) in labels.iter().zip(colors).enumerate() {
+        let y = 380.0 + 20.0 * i as f64;
+        let _ = writeln!(
+            svg,
+            "<rect x=\"830\" y=\"{}\" width=\"14\" height=\"14\" fill=\"{}\"/><text x=\"850\" y=\"{}\">{}</text>",
+            y,
+            color,
+            y + 11.0,
+            escape_xml(label)
+        );
+    }
+    let _ = writeln!(svg, "</g>\n</svg>");
+    Ok(svg)
+}
+
+#[cfg(feature = "png")]
+fn to_png(svg: &str) -> Result<Vec<u8>> {
+    use resvg::{tiny_skia, usvg};
+    use std::sync::Arc;
+
+    let mut options = usvg::Options::default();
+    Arc::make_mut(&mut options.fontdb).load_system_fonts();
+    let tree = usvg::Tree::from_str(svg, &options)?;
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height())
+        .ok_or_else(|| anyhow!("Invalid map size"))?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    Ok(pixmap.encode_png()?)
+}
+
+pub fn write_svg(
+    graphs: &[Graph],
+    geometries: &Geometries,
+    output_dir: &OutputDir,
+) -> Result<usize> {
+    write(graphs, geometries, output_dir, "svg", |svg| {
+        Ok(svg.into_bytes())
+    })
+}
+
+#[cfg(feature = "png")]
+pub fn write_png(
+    graphs: &[Graph],
+    geometries: &Geometries,
+    output_dir: &OutputDir,
+) -> Result<usize> {
+    write(graphs, geometries, output_dir, "png", |svg| to_png(&svg))
+}
+
+fn write(
+    graphs: &[Graph],
+    geometries: &Geometries,
+    output_dir: &OutputDir,
+    format: &str,
+    encode: impl Fn(String) -> Result<Vec<u8>> + Sync,
+) -> Result<usize> {
+    let paths = geometries
+        .par_iter()
+        .filter_map(|(fips, geometry)| Some((fips.as_str(), path_data(fips, geometry)?)))
+        .collect::<HashMap<_, _>>();
+    let values = graphs
+        .iter()
+        .flat_map(|graph| per_capita(graph).into_values())
+        .collect::<Vec<_>>();
+    if values.is_empty() {
+        return Err(anyhow!(
+            "Maps need the county populations from --population"
+        ));
+    }
+    let breaks = breaks(values);
+
+    graphs
+        .par_iter()
+        .map(|graph| {
+            let data = encode(to_svg(graph, &paths, &breaks)?)?;
+            output_dir.write_dated(graph, format, &data)
+        })
+        .collect::<Result<()>>()?;
+    Ok(graphs.len())
+}
@@ -0,0 +1,1048 @@
+//! Writers for the built graphs, selected with `--output-format`, or `--output`
+//! for targets that aren't a directory
+
+use crate::error::{self, CovidDataError};
+use crate::progress::Progress;
+use crate::{states, Graph, Node};
+use anyhow::{anyhow, Context, Result};
+use chrono::DateTime;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Mutex;
+use structopt::StructOpt;
+
+pub mod csv;
+pub mod delta;
+pub mod dot;
+pub mod geojson;
+pub mod gexf;
+pub mod graphml;
+pub mod influx;
+pub mod json;
+pub mod map;
+pub mod msgpack;
+pub mod ndjson;
+pub mod neo4j;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod postgres;
+pub mod protobuf;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod vega;
+
+#[derive(Debug, Clone, Copy)]
+pub enum OutputFormat {
+    /// One JSON graph per date, compact unless `--pretty`
+    Json,
+    /// All graphs in one file, one JSON graph per line
+    Ndjson,
+    /// The JSON graphs encoded as MessagePack, one file per date
+    Msgpack,
+    /// Protobuf messages per `proto/covid_graph.proto`, one file per date
+    Protobuf,
+    /// A single flat table of every node on every date
+    Csv,
+    /// One GraphML file per date
+    Graphml,
+    /// One Graphviz DOT file per date
+    Dot,
+    /// One GeoJSON FeatureCollection of the counties per date
+    Geojson,
+    /// One SVG choropleth of confirmed cases per capita per date
+    Svg,
+    /// The SVG choropleths rendered to PNG
+    #[cfg(feature = "png")]
+    Png,
+    /// The whole series as one dynamic GEXF graph
+    Gexf,
+    /// Node and relationship CSVs for `neo4j-admin database import`
+    Neo4j,
+    /// InfluxDB line protocol, a line per node per date
+    Influx,
+    /// Tab-separated tables for Postgres `COPY`, with their DDL
+    Postgres,
+    /// The CSV table as one Parquet file, a row group per date
+    #[cfg(feature = "parquet")]
+    Parquet,
+}
+
+/// Names accepted by `--output-format`
+pub fn names() -> Vec<&'static str> {
+    vec![
+        "json",
+        "ndjson",
+        "msgpack",
+        "protobuf",
+        "csv",
+        "graphml",
+        "dot",
+        "geojson",
+        "svg",
+        #[cfg(feature = "png")]
+        "png",
+        "gexf",
+        "neo4j",
+        "influx",
+        "postgres",
+        #[cfg(feature = "parquet")]
+        "parquet",
+    ]
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(OutputFormat::Json),
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "msgpack" => Ok(OutputFormat::Msgpack),
+            "protobuf" => Ok(OutputFormat::Protobuf),
+            "csv" => Ok(OutputFormat::Csv),
+            "graphml" => Ok(OutputFormat::Graphml),
+            "dot" => Ok(OutputFormat::Dot),
+            "geojson" => Ok(OutputFormat::Geojson),
+            "svg" => Ok(OutputFormat::Svg),
+            #[cfg(feature = "png")]
+            "png" => Ok(OutputFormat::Png),
+            "gexf" => Ok(OutputFormat::Gexf),
+            "neo4j" => Ok(OutputFormat::Neo4j),
+            "influx" => Ok(OutputFormat::Influx),
+            "postgres" => Ok(OutputFormat::Postgres),
+            #[cfg(feature = "parquet")]
+            "parquet" => Ok(OutputFormat::Parquet),
+            _ => Err(anyhow!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+/// How far down the country > state > county > ZCTA hierarchy a node level is. Metro
+/// areas and groupings of states aren't part of it, so their members keep the parent
+/// they have in it
+fn level_depth(level: &str) -> usize {
+    match level {
+        "country" => 0,
+        "state" => 1,
+        "county" => 2,
+        _ => 3,
+    }
+}
+
+/// The nodes' metrics, each with whether any node has it as a float, for the formats
+/// that declare a type per metric
+fn metric_types<'a>(nodes: impl Iterator<Item = &'a Node>) -> BTreeMap<&'a str, bool> {
+    let mut metrics = BTreeMap::new();
+    for node in nodes {
+        for (metric, value) in &node.metrics {
+            *metrics.entry(metric.as_ref()).or_default() |= value.is_float();
+        }
+    }
+    metrics
+}
+
+/// Escape text for use in XML attributes and element content
+pub fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Debug, StructOpt)]
+pub struct OutputOpt {
+    /// How to write the graphs. A file per date: `json`, `msgpack`, `protobuf`, `graphml`,
+    /// `dot`, `geojson` or `svg` and `png` maps. The whole series at once: `csv` or `parquet`
+    /// tables, `ndjson` with a graph per line, a dynamic `gexf` graph, `neo4j` import CSVs,
+    /// `influx` line protocol or `postgres` COPY tables. `parquet` and `png` need a build
+    /// with the feature of the same name
+    #[structopt(long, default_value = "json", possible_values = &names())]
+    pub output_format: OutputFormat,
+
+    /// Write all dates into one file, e.g. a `graphs.json` array, instead of a file per date.
+    /// Applies to `json`, `msgpack` and `protobuf`
+    #[structopt(long)]
+    pub single_file: bool,
+
+    /// Write compact JSON, about half the size of `--pretty` and faster. It's the default,
+    /// so the flag only spells it out: an explicit alias kept for scripts that pass it
+    #[structopt(long, conflicts_with = "pretty")]
+    pub compact: bool,
+
+    /// Indent the JSON output for reading by hand
+    #[structopt(long)]
+    pub pretty: bool,
+
+    /// Compress every output file: `gzip` or `zstd`, optionally with a level like `zstd:19`
+    #[structopt(long, value_name = "gzip|zstd[:level]")]
+    pub compress: Option<Compress>,
+
+    /// County geometries for `--output-format geojson` and the maps, a GeoJSON or TopoJSON
+    /// file with features identified by FIPS code
+    #[structopt(long, parse(from_os_str))]
+    pub geometries: Option<PathBuf>,
+
+    /// Split the output into a subdirectory per state, e.g. `New York/<timestamp>.json`,
+    /// each with only that state's subgraph
+    #[structopt(long, possible_values = &["state"])]
+    pub partition_by: Option<Partition>,
+
+    /// Name the per-date files after a template instead of the bare timestamp, e.g.
+    /// `{date}.json`. Placeholders: `{date}` (YYYY-MM-DD), `{timestamp}` (RFC 3339),
+    /// `{format}` (the file extension, e.g. `json`) and `{compression}` (`.gz`, `.zst`
+    /// or nothing)
+    #[structopt(long)]
+    pub filename_template: Option<FilenameTemplate>,
+
+    /// Replace existing output files, the default
+    #[structopt(long, conflicts_with_all = &["skip-existing", "error-if-exists"])]
+    pub force: bool,
+
+    /// Leave existing output files alone and only write the missing ones
+    #[structopt(long, conflicts_with = "error-if-exists")]
+    pub skip_existing: bool,
+
+    /// Fail instead of replacing an existing output file
+    #[structopt(long)]
+    pub error_if_exists: bool,
+
+    /// Also write a `manifest.json` listing every output file with its date, node count,
+    /// size and SHA-256, so sync jobs can tell partial or changed outputs apart
+    #[structopt(long)]
+    pub manifest: bool,
+
+    /// Also write a Vega-Lite spec charting a node's confirmed cases and deaths over
+    /// time, by name or ID, e.g. `--chart "New York - Kings"` or `--chart 36047`. Can be
+    /// given more than once
+    #[structopt(long, value_name = "NODE", number_of_values = 1)]
+    pub chart: Vec<String>,
+
+    /// Also write each node's edge targets as an `edges_directed` list, the format from
+    /// before edges had kinds and weights. Applies to `json`, `ndjson` and `msgpack`
+    #[structopt(long)]
+    pub legacy_edges: bool,
+
+    /// Also write a `<timestamp>.delta.json` for every date after the first, with the nodes
+    /// added, changed and removed since the previous date, for loading incrementally
+    #[structopt(long)]
+    pub graph_deltas: bool,
+
+    /// Name states and territories in full, the default, or by postal abbreviation, e.g.
+    /// `NY` and `NY - Kings`. Either way every input's spelling comes out the same, so with
+    /// `full` a county an input gives as `NY - Kings` is `New York - Kings`, and so is its
+    /// ID when it has no FIPS
+    #[structopt(long, default_value = "full", possible_values = &["full", "abbrev"])]
+    pub states_as: StatesAs,
+}
+
+impl OutputOpt {
+    fn output_dir<'a>(
+        &'a self,
+        dir: &'a Path,
+        manifest: &'a Manifest<'a>,
+        progress: &'a Progress,
+    ) -> OutputDir<'a> {
+        OutputDir {
+            dir,
+            progress,
+            compress: self.compress,
+            template: self.filename_template.as_ref(),
+            existing: self.existing(),
+            manifest: (self.manifest || manifest.incremental.is_some()).then_some(manifest),
+        }
+    }
+
+    /// The `--geometries` of the formats drawn from them, loaded once for all the writes
+    fn load_geometries(&self) -> Result<Option<geojson::Geometries>> {
+        let drawn = matches!(
+            self.output_format,
+            OutputFormat::Geojson | OutputFormat::Svg
+        );
+        #[cfg(feature = "png")]
+        let drawn = drawn || matches!(self.output_format, OutputFormat::Png);
+        if !drawn {
+            return Ok(None);
+        }
+        let path = self
+            .geometries
+            .as_ref()
+            .ok_or_else(|| anyhow!("The geojson, svg and png formats need --geometries"))?;
+        geojson::load(path).map(Some)
+    }
+
+    fn existing(&self) -> Existing {
+        if self.force {
+            Existing::Overwrite
+        } else if self.skip_existing {
+            Existing::Skip
+        } else if self.error_if_exists {
+            Existing::Error
+        } else {
+            Existing::Overwrite
+        }
+    }
+}
+
+/// What to do about output files that already exist
+#[derive(Debug, Clone, Copy)]
+enum Existing {
+    Overwrite,
+    Skip,
+    Error,
+}
+
+/// Whether to go ahead and write `path`, given what to do if it exists
+fn check_existing(path: &Path, existing: Existing) -> Result<bool> {
+    match existing {
+        Existing::Overwrite => Ok(true),
+        _ if !path.exists() => Ok(true),
+        Existing::Skip => Ok(false),
+        Existing::Error => Err(CovidDataError::Output {
+            message: format!(
+                "{} already exists, pass --force to replace it",
+                path.display()
+            ),
+            kind: io::ErrorKind::AlreadyExists,
+        }
+        .into()),
+    }
+}
+
+const PLACEHOLDERS: &[&str] = &["date", "timestamp", "format", "compression"];
+
+#[derive(Debug, Clone)]
+pub struct FilenameTemplate(String);
+
+impl FromStr for FilenameTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut rest = s;
+        let mut dated = false;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| anyhow!("Unclosed {{ in filename template {}", s))?;
+            let placeholder = &rest[start + 1..start + end];
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(anyhow!(
+                    "Unknown placeholder {{{}}} in filename template, expected one of {}",
+                    placeholder,
+                    PLACEHOLDERS.join(", ")
+                ));
+            }
+            dated |= placeholder == "date" || placeholder == "timestamp";
+            rest = &rest[start + end + 1..];
+        }
+        // Otherwise every date would overwrite the same file
+        if !dated {
+            return Err(anyhow!(
+                "Filename template {} needs a {{date}} or {{timestamp}}",
+                s
+            ));
+        }
+        Ok(FilenameTemplate(s.to_string()))
+    }
+}
+
+impl FilenameTemplate {
+    fn render(&self, timestamp: &str, format: &str, compress: Option<Compress>) -> Result<String> {
+        let date = DateTime::parse_from_rfc3339(timestamp)?
+            .format("%Y-%m-%d")
+            .to_string();
+        let compression = compress
+            .map(|compress| format!(".{}", compress.extension()))
+            .unwrap_or_default();
+        Ok(self
+            .0
+            .replace("{date}", &date)
+            .replace("{timestamp}", timestamp)
+            .replace("{format}", format)
+            .replace("{compression}", &compression))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Partition {
+    State,
+}
+
+impl FromStr for Partition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "state" => Ok(Partition::State),
+            _ => Err(anyhow!("Unknown partition {}, expected state", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatesAs {
+    Full,
+    Abbrev,
+}
+
+impl FromStr for StatesAs {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "full" => Ok(StatesAs::Full),
+            "abbrev" => Ok(StatesAs::Abbrev),
+            _ => Err(anyhow!(
+                "Unknown state naming {}, expected full or abbrev",
+                s
+            )),
+        }
+    }
+}
+
+/// Rename states to their abbreviation, and the counties and ZCTAs named after them. Nodes
+/// whose ID falls back on their name get the new name as ID, and so do edges to them
+fn abbreviate_states(graphs: &mut [Graph]) {
+    for graph in graphs {
+        let mut ids = HashMap::new();
+        for node in &mut graph.nodes {
+            let abbreviated = match node.level {
+                "state" => states::abbreviation(&node.name).map(String::from),
+                "county" | "zcta" => node.name.split_once(" - ").and_then(|(state, rest)| {
+                    states::abbreviation(state).map(|abbrev| format!("{} - {}", abbrev, rest))
+                }),
+                _ => None,
+            };
+            if let Some(name) = abbreviated {
+                if node.id == node.name {
+                    ids.insert(node.id.clone(), name.clone());
+                    node.id = name.clone();
+                }
+                node.name = name;
+            }
+        }
+        if ids.is_empty() {
+            continue;
+        }
+        for edge in graph.nodes.iter_mut().flat_map(|node| &mut node.edges) {
+            if let Some(id) = ids.get(&edge.target) {
+                edge.target = id.clone();
+            }
+        }
+    }
+}
+
+/// Split each graph into the subgraphs of its states, keyed by state name. Counties and
+/// ZCTAs go with the state above them, `--national`, `--cbsa` and `--hierarchy` nodes are
+/// left out and edges leaving a state's subgraph are dropped
+fn partition_by_state(graphs: Vec<Graph>) -> BTreeMap<String, Vec<Graph>> {
+    let mut partitions = BTreeMap::<String, Vec<Graph>>::new();
+    for graph in graphs {
+        let nodes = graph
+            .nodes
+            .iter()
+            .map(|node| (node.id.as_str(), node))
+            .collect::<HashMap<_, _>>();
+        let mut parents = HashMap::new();
+        for node in &graph.nodes {
+            for edge in &node.edges {
+                match nodes.get(edge.target.as_str()) {
+                    Some(child) if level_depth(child.level) > level_depth(node.level) => {
+                        parents.insert(child.id.as_str(), node.id.as_str());
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let states = graph
+            .nodes
+            .iter()
+            .filter_map(|node| {
+                let mut id = node.id.as_str();
+                while nodes[id].level != "state" {
+                    id = parents.get(id)?;
+                }
+                Some((node.id.clone(), nodes[id].name.clone()))
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut subgraphs = BTreeMap::<&str, Vec<Node>>::new();
+        for mut node in graph.nodes {
+            let state = match states.get(&node.id) {
+                Some(state) => state,
+                None => continue,
+            };
+            node.edges
+                .retain(|edge| states.get(&edge.target) == Some(state));
+            subgraphs.entry(state).or_default().push(node);
+        }
+        for (state, nodes) in subgraphs {
+            partitions
+                .entry(state.to_string())
+                .or_default()
+                .push(Graph {
+                    timestamp: graph.timestamp.clone(),
+                    nodes,
+                    meta: graph.meta.clone(),
+                });
+        }
+    }
+    partitions
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Compress {
+    Gzip(u32),
+    Zstd(i32),
+}
+
+impl FromStr for Compress {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (algorithm, level) = match s.split_once(':') {
+            Some((algorithm, level)) => (algorithm, Some(level)),
+            None => (s, None),
+        };
+        let invalid_level = || anyhow!("Invalid compression level in {}", s);
+        match algorithm {
+            "gzip" => {
+                let level = level.map_or(Ok(6), |l| l.parse().map_err(|_| invalid_level()))?;
+                if level > 9 {
+                    return Err(invalid_level());
+                }
+                Ok(Compress::Gzip(level))
+            }
+            "zstd" => {
+                let level = level.map_or(Ok(zstd::DEFAULT_COMPRESSION_LEVEL), |l| {
+                    l.parse().map_err(|_| invalid_level())
+                })?;
+                if !zstd::compression_level_range().contains(&level) {
+                    return Err(invalid_level());
+                }
+                Ok(Compress::Zstd(level))
+            }
+            _ => Err(anyhow!(
+                "Unknown compression: {}, expected gzip or zstd",
+                algorithm
+            )),
+        }
+    }
+}
+
+impl Compress {
+    fn extension(self) -> &'static str {
+        match self {
+            Compress::Gzip(_) => "gz",
+            Compress::Zstd(_) => "zst",
+        }
+    }
+
+    fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compress::Gzip(level) => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::new(level));
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            Compress::Zstd(level) => Ok(zstd::encode_all(data, level)?),
+        }
+    }
+}
+
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ManifestEntry {
+    /// Relative to the output directory
+    pub file: String,
+    /// Which graph the file holds, for formats with a file per date
+    pub timestamp: Option<String>,
+    pub nodes: Option<usize>,
+    pub bytes: usize,
+    pub sha256: String,
+    /// With `--incremental`, the fingerprint of what the graph was built from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_sha256: Option<String>,
+}
+
+/// The entries of the manifest in `dir`, none if there isn't one
+pub fn read_manifest(dir: &Path) -> Result<Option<Vec<ManifestEntry>>> {
+    let path = dir.join(MANIFEST_FILE_NAME);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let entries = serde_json::from_slice(&data)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+    Ok(Some(entries))
+}
+
+/// What `--incremental` writes besides the graphs
+pub struct Incremental<'a> {
+    /// Fingerprints of the graphs' dates, by timestamp
+    pub fingerprints: &'a HashMap<String, String>,
+    /// Manifest entries of the files left as they were
+    pub kept: Vec<ManifestEntry>,
+}
+
+/// Files written so far, for `--manifest`
+struct Manifest<'a> {
+    root: &'a Path,
+    entries: Mutex<Vec<ManifestEntry>>,
+    incremental: Option<Incremental<'a>>,
+}
+
+impl<'a> Manifest<'a> {
+    fn new(root: &'a Path, incremental: Option<Incremental<'a>>) -> Self {
+        Manifest {
+            root,
+            entries: Mutex::default(),
+            incremental,
+        }
+    }
+
+    fn add(&self, path: &Path, graph: Option<&Graph>, data: &[u8]) {
+        let file = path
+            .strip_prefix(self.root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned();
+        let entry =
+            ManifestEntry {
+                file,
+                timestamp: graph.map(|graph| graph.timestamp.clone()),
+                nodes: graph.map(|graph| graph.nodes.len()),
+                bytes: data.len(),
+                sha256: format!("{:x}", Sha256::digest(data)),
+                input_sha256: graph.zip(self.incremental.as_ref()).and_then(
+                    |(graph, incremental)| incremental.fingerprints.get(&graph.timestamp).cloned(),
+                ),
+            };
+        self.entries.lock().unwrap().push(entry);
+    }
+
+    fn write(self) -> Result<()> {
+        let mut entries = self.entries.into_inner().unwrap();
+        entries.extend(
+            self.incremental
+                .into_iter()
+                .flat_map(|incremental| incremental.kept),
+        );
+        entries.sort_by(|a, b| a.file.cmp(&b.file));
+        let path = self.root.join(MANIFEST_FILE_NAME);
+        fs::write(&path, serde_json::to_string_pretty(&entries)?).map_err(|err| {
+            CovidDataError::output(format_args!("Failed to write {}", path.display()), err).into()
+        })
+    }
+}
+
+/// Output directory the writers put their files in
+pub struct OutputDir<'a> {
+    dir: &'a Path,
+    compress: Option<Compress>,
+    template: Option<&'a FilenameTemplate>,
+    existing: Existing,
+    manifest: Option<&'a Manifest<'a>>,
+    /// Counts the files written
+    progress: &'a Progress,
+}
+
+impl OutputDir<'_> {
+    /// Write a finished file, compressed and with the compression's extension if asked to
+    fn write(&self, name: &str, data: &[u8]) -> Result<()> {
+        self.write_file(&self.compressed_name(name), None, data)
+    }
+
+    /// Write the file for one date, `<timestamp>.<format>` unless there's a `--filename-template`
+    fn write_dated(&self, graph: &Graph, format: &str, data: &[u8]) -> Result<()> {
+        let name = match self.template {
+            Some(template) => template.render(&graph.timestamp, format, self.compress)?,
+            // The original output: uncompressed JSON files are named by the bare timestamp
+            None if format == "json" && self.compress.is_none() => graph.timestamp.clone(),
+            None => self.compressed_name(&format!("{}.{}", graph.timestamp, format)),
+        };
+        self.write_file(&name, Some(graph), data)
+    }
+
+    fn compressed_name(&self, name: &str) -> String {
+        match self.compress {
+            Some(compress) => format!("{}.{}", name, compress.extension()),
+            None => name.to_string(),
+        }
+    }
+
+    fn write_file(&self, name: &str, graph: Option<&Graph>, data: &[u8]) -> Result<()> {
+        let path = self.dir.join(name);
+        if !check_existing(&path, self.existing)? {
+            return self.record(&path, graph);
+        }
+        let data = match self.compress {
+            Some(compress) => compress.compress(data)?,
+            None => data.to_vec(),
+        };
+        fs::write(&path, &data).map_err(|err| {
+            CovidDataError::output(format_args!("Failed to write {}", path.display()), err)
+        })?;
+        if let Some(manifest) = self.manifest {
+            manifest.add(&path, graph, &data);
+        }
+        self.progress.inc();
+        Ok(())
+    }
+
+    /// Path for a file the writer creates itself, or `None` to skip it because it exists.
+    /// Either way it should be passed to `record` once done
+    fn claim(&self, name: &str) -> Result<Option<PathBuf>> {
+        let path = self.dir.join(name);
+        Ok(check_existing(&path, self.existing)?.then_some(path))
+    }
+
+    /// Add a file already on disk to the manifest
+    fn record(&self, path: &Path, graph: Option<&Graph>) -> Result<()> {
+        if let Some(manifest) = self.manifest {
+            let data = fs::read(path).map_err(|err| {
+                CovidDataError::output(format_args!("Failed to read {}", path.display()), err)
+            })?;
+            manifest.add(path, graph, &data);
+        }
+        self.progress.inc();
+        Ok(())
+    }
+}
+
+/// Where the graphs go
+#[derive(Debug)]
+pub enum OutputTarget {
+    /// Files in `--output-format` in a directory
+    Dir(PathBuf),
+    /// `sqlite:path.db`, a database with tables for nodes, metrics and edges
+    #[cfg(feature = "sqlite")]
+    Sqlite(PathBuf),
+}
+
+impl FromStr for OutputTarget {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.strip_prefix("sqlite:") {
+            #[cfg(feature = "sqlite")]
+            Some(path) => Ok(OutputTarget::Sqlite(path.into())),
+            #[cfg(not(feature = "sqlite"))]
+            Some(_) => Err(anyhow!(
+                "sqlite output needs a build with the sqlite feature"
+            )),
+            None => Ok(OutputTarget::Dir(s.into())),
+        }
+    }
+}
+
+impl fmt::Display for OutputTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OutputTarget::Dir(dir) => write!(f, "{}", dir.display()),
+            #[cfg(feature = "sqlite")]
+            OutputTarget::Sqlite(path) => write!(f, "sqlite:{}", path.display()),
+        }
+    }
+}
+
+/// Where the graphs go one at a time. The `json` files, the `--single-file` array,
+/// `parquet` and `sqlite:` targets are written through one, picked by the flags, and
+/// anything else can stand in for them. Sinks that need the whole series, like the
+/// array or parquet's schema, keep the graphs until `finish`
+pub trait OutputSink {
+    fn write(&mut self, graph: &Graph) -> Result<()>;
+
+    /// Write whatever was kept back once all graphs are in. Returns the number of files
+    /// written
+    fn finish(&mut self) -> Result<usize>;
+}
+
+/// Write the graphs to a sink in date order
+pub fn write_to(sink: &mut dyn OutputSink, graphs: &mut [Graph]) -> error::Result<usize> {
+    Ok(write_sink(sink, graphs)?)
+}
+
+fn write_sink(sink: &mut dyn OutputSink, graphs: &mut [Graph]) -> Result<usize> {
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    for graph in graphs.iter() {
+        sink.write(graph)?;
+    }
+    sink.finish()
+}
+
+/// Write the graphs to the target, returns the number of files written
+pub fn write(graphs: Vec<Graph>, target: &OutputTarget, opt: &OutputOpt) -> error::Result<usize> {
+    Ok(write_target(graphs, target, opt)?)
+}
+
+fn write_target(mut graphs: Vec<Graph>, target: &OutputTarget, opt: &OutputOpt) -> Result<usize> {
+    if opt.states_as == StatesAs::Abbrev {
+        abbreviate_states(&mut graphs);
+    }
+    match target {
+        OutputTarget::Dir(dir) => write_dir(graphs, dir, opt, None),
+        #[cfg(feature = "sqlite")]
+        OutputTarget::Sqlite(_)
+            if opt.partition_by.is_some()
+                || opt.manifest
+                || !opt.chart.is_empty()
+                || opt.graph_deltas =>
+        {
+            Err(anyhow!(
+                "--partition-by, --manifest, --chart and --graph-deltas only apply to output \
+                 directories"
+            ))
+        }
+        #[cfg(feature = "sqlite")]
+        OutputTarget::Sqlite(path) if !check_existing(path, opt.existing())? => Ok(0),
+        #[cfg(feature = "sqlite")]
+        OutputTarget::Sqlite(path) => {
+            write_sink(&mut self::sqlite::SqliteSink::create(path)?, &mut graphs)
+        }
+    }
+}
+
+/// Write the graphs of the dates `--incremental` rebuilds, with a manifest of them and the
+/// files kept from before. Returns the number of files written
+pub fn write_incremental(
+    mut graphs: Vec<Graph>,
+    dir: &Path,
+    opt: &OutputOpt,
+    incremental: Incremental,
+) -> error::Result<usize> {
+    if opt.states_as == StatesAs::Abbrev {
+        abbreviate_states(&mut graphs);
+    }
+    Ok(write_dir(graphs, dir, opt, Some(incremental))?)
+}
+
+/// The directory `flag` writes to a date at a time, for formats with a file per date and
+/// without `--chart` and `--graph-deltas`, which need the whole series
+pub fn per_date_dir<'a>(
+    target: &'a OutputTarget,
+    opt: &OutputOpt,
+    flag: &str,
+) -> error::Result<&'a Path> {
+    match target {
+        OutputTarget::Dir(dir) => Ok(check_per_date(opt, flag).map(|()| dir.as_path())?),
+        #[cfg(feature = "sqlite")]
+        OutputTarget::Sqlite(_) => {
+            Err(anyhow!("{} only writes to output directories", flag).into())
+        }
+    }
+}
+
+fn check_per_date(opt: &OutputOpt, flag: &str) -> Result<()> {
+    if !check_dir_opt(opt)? {
+        return Err(anyhow!(
+            "{} only writes the formats with a file per date",
+            flag
+        ));
+    }
+    if !opt.chart.is_empty() || opt.graph_deltas {
+        return Err(anyhow!(
+            "--chart and --graph-deltas need all dates, they don't go with {}",
+            flag
+        ));
+    }
+    Ok(())
+}
+
+/// Check the output options go together. Returns whether the format has a file per date
+fn check_dir_opt(opt: &OutputOpt) -> Result<bool> {
+    let per_date = matches!(
+        opt.output_format,
+        OutputFormat::Json | OutputFormat::Msgpack | OutputFormat::Protobuf
+    );
+    if opt.single_file && !per_date {
+        return Err(anyhow!(
+            "--single-file only applies to json, msgpack and protobuf"
+        ));
+    }
+    let dated = matches!(
+        opt.output_format,
+        OutputFormat::Graphml | OutputFormat::Dot | OutputFormat::Geojson | OutputFormat::Svg
+    ) || (per_date && !opt.single_file);
+    #[cfg(feature = "png")]
+    let dated = dated || matches!(opt.output_format, OutputFormat::Png);
+    if opt.filename_template.is_some() && !dated {
+        return Err(anyhow!(
+            "--filename-template only applies to formats with a file per date"
+        ));
+    }
+    #[cfg(feature = "parquet")]
+    if opt.compress.is_some() && matches!(opt.output_format, OutputFormat::Parquet) {
+        return Err(anyhow!(
+            "--compress doesn't apply to parquet, which is compressed already"
+        ));
+    }
+    Ok(dated)
+}
+
+fn write_dir(
+    graphs: Vec<Graph>,
+    dir: &Path,
+    opt: &OutputOpt,
+    incremental: Option<Incremental>,
+) -> Result<usize> {
+    check_dir_opt(opt)?;
+    let geometries = opt.load_geometries()?;
+    let manifest = Manifest::new(dir, incremental);
+    let progress = Progress::new("write", "files", None);
+    let output_dir = opt.output_dir(dir, &manifest, &progress);
+    for node in &opt.chart {
+        vega::write(&graphs, node, &output_dir)?;
+    }
+    let num_files = match opt.partition_by {
+        Some(Partition::State) => {
+            let mut num_files = 0;
+            for (state, graphs) in partition_by_state(graphs) {
+                let dir = dir.join(state);
+                create_dir(&dir)?;
+                let output_dir = opt.output_dir(&dir, &manifest, &progress);
+                num_files += write_format(graphs, &output_dir, opt, geometries.as_ref())?;
+            }
+            num_files
+        }
+        None => write_format(graphs, &output_dir, opt, geometries.as_ref())?,
+    };
+    if opt.manifest || manifest.incremental.is_some() {
+        manifest.write()?;
+    }
+    Ok(num_files + opt.chart.len())
+}
+
+/// Writes graphs to a directory a date at a time, as `build --stream` finishes them. Only
+/// for the formats with a file per date, and without `--chart` and `--graph-deltas`, which
+/// need the whole series
+pub struct DirStream<'a> {
+    dir: &'a Path,
+    opt: &'a OutputOpt,
+    manifest: Manifest<'a>,
+    progress: Progress,
+    geometries: Option<geojson::Geometries>,
+    num_files: usize,
+}
+
+impl<'a> DirStream<'a> {
+    pub fn new(target: &'a OutputTarget, opt: &'a OutputOpt) -> error::Result<Self> {
+        let dir = per_date_dir(target, opt, "--stream")?;
+        Ok(DirStream {
+            dir,
+            opt,
+            manifest: Manifest::new(dir, None),
+            progress: Progress::new("write", "files", None),
+            geometries: opt.load_geometries()?,
+            num_files: 0,
+        })
+    }
+
+    pub fn write(&mut self, mut graph: Graph) -> error::Result<()> {
+        if self.opt.states_as == StatesAs::Abbrev {
+            abbreviate_states(std::slice::from_mut(&mut graph));
+        }
+        let graphs = vec![graph];
+        self.num_files += match self.opt.partition_by {
+            Some(Partition::State) => {
+                let mut num_files = 0;
+                for (state, graphs) in partition_by_state(graphs) {
+                    let dir = self.dir.join(state);
+                    create_dir(&dir)?;
+                    let output_dir = self.opt.output_dir(&dir, &self.manifest, &self.progress);
+                    num_files +=
+                        write_format(graphs, &output_dir, self.opt, self.geometries.as_ref())?;
+                }
+                num_files
+            }
+            None => {
+                let output_dir = self
+                    .opt
+                    .output_dir(self.dir, &self.manifest, &self.progress);
+                write_format(graphs, &output_dir, self.opt, self.geometries.as_ref())?
+            }
+        };
+        Ok(())
+    }
+
+    /// Write the manifest, once all dates are in. Returns the number of files written
+    pub fn finish(self) -> error::Result<usize> {
+        if self.opt.manifest {
+            self.manifest.write()?;
+        }
+        Ok(self.num_files)
+    }
+}
+
+fn create_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir).map_err(|err| {
+        CovidDataError::output(format_args!("Failed to create {}", dir.display()), err).into()
+    })
+}
+
+/// Write in the `--output-format`, with the geometries `load_geometries` gave
+fn write_format(
+    mut graphs: Vec<Graph>,
+    output_dir: &OutputDir,
+    opt: &OutputOpt,
+    geometries: Option<&geojson::Geometries>,
+) -> Result<usize> {
+    let geometries = || geometries.ok_or_else(|| anyhow!("No --geometries loaded"));
+    if opt.legacy_edges {
+        for node in graphs.iter_mut().flat_map(|graph| &mut graph.nodes) {
+            node.edges_directed = Some(node.edges.iter().map(|edge| edge.target.clone()).collect());
+        }
+    }
+    let num_deltas = if opt.graph_deltas {
+        delta::write(&graphs, opt.pretty, output_dir)?
+    } else {
+        0
+    };
+    let num_files = match opt.output_format {
+        OutputFormat::Json if opt.single_file => write_sink(
+            &mut json::JsonArray::new(output_dir, opt.pretty),
+            &mut graphs,
+        ),
+        OutputFormat::Json => write_sink(
+            &mut json::JsonFiles::new(output_dir, opt.pretty),
+            &mut graphs,
+        ),
+        OutputFormat::Ndjson => ndjson::write(graphs, output_dir),
+        OutputFormat::Msgpack if opt.single_file => msgpack::write_single(graphs, output_dir),
+        OutputFormat::Msgpack => msgpack::write(&graphs, output_dir),
+        OutputFormat::Protobuf if opt.single_file => protobuf::write_single(graphs, output_dir),
+        OutputFormat::Protobuf => protobuf::write(&graphs, output_dir),
+        OutputFormat::Csv => self::csv::write(&graphs, output_dir),
+        OutputFormat::Graphml => graphml::write(&graphs, output_dir),
+        OutputFormat::Dot => dot::write(&graphs, output_dir),
+        OutputFormat::Geojson => geojson::write(&graphs, geometries()?, output_dir),
+        OutputFormat::Svg => map::write_svg(&graphs, geometries()?, output_dir),
+        #[cfg(feature = "png")]
+        OutputFormat::Png => map::write_png(&graphs, geometries()?, output_dir),
+        OutputFormat::Gexf => gexf::write(&graphs, output_dir),
+        OutputFormat::Neo4j => neo4j::write(&graphs, output_dir),
+        OutputFormat::Influx => influx::write(&graphs, output_dir),
+        OutputFormat::Postgres => postgres::write(&graphs, output_dir),
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => write_sink(
+            &mut self::parquet::ParquetSink::new(output_dir),
+            &mut graphs,
+        ),
+    }?;
+    Ok(num_files + num_deltas)
+}
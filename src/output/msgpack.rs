@@ -0,0 +1,24 @@
+//! MessagePack encoding of the JSON output, one `.msgpack` file per date or a single
+//! `graphs.msgpack` array with `--single-file`. Structs are encoded as maps with
+//! their field names, the same shape as the JSON.
+
+use super::OutputDir;
+use crate::Graph;
+use anyhow::Result;
+use rayon::prelude::*;
+
+const SINGLE_FILE_NAME: &str = "graphs.msgpack";
+
+pub fn write(graphs: &[Graph], output_dir: &OutputDir) -> Result<usize> {
+    graphs
+        .par_iter()
+        .map(|graph| output_dir.write_dated(graph, "msgpack", &rmp_serde::to_vec_named(graph)?))
+        .collect::<Result<()>>()?;
+    Ok(graphs.len())
+}
+
+pub fn write_single(mut graphs: Vec<Graph>, output_dir: &OutputDir) -> Result<usize> {
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    output_dir.write(SINGLE_FILE_NAME, &rmp_serde::to_vec_named(&graphs)?)?;
+    Ok(1)
+}
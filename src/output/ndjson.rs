@@ -0,0 +1,18 @@
+//! All graphs in one `graphs.ndjson`, one compact JSON graph per line ordered by date
+
+use super::OutputDir;
+use crate::Graph;
+use anyhow::Result;
+
+const FILE_NAME: &str = "graphs.ndjson";
+
+pub fn write(mut graphs: Vec<Graph>, output_dir: &OutputDir) -> Result<usize> {
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    let mut data = Vec::new();
+    for graph in &graphs {
+        serde_json::to_writer(&mut data, graph)?;
+        data.push(b'\n');
+    }
+    output_dir.write(FILE_NAME, &data)?;
+    Ok(1)
+}
@@ -0,0 +1,125 @@
+//! `nodes.csv` and `relationships.csv` in `neo4j-admin database import` format
+//!
+//! Every node on every date is its own Neo4j node, with the ID `<timestamp>|<node id>`,
+//! the labels `Region` plus its level (`State`, `County`, `Zcta`) and the date as a
+//! `datetime` property. Edges become relationships named after their kind, `CONTAINS`,
+//! `ADJACENT` or `COMMUTE`, with the edge weight as a `weight` property. `NEXT` links
+//! a region to itself on the following date, so the date dimension can be walked in
+//! Cypher.
+//!
+//! ```sh
+//! neo4j-admin database import full --nodes=nodes.csv --relationships=relationships.csv
+//! ```
+
+use super::{metric_types, OutputDir};
+use crate::{Graph, Node};
+use anyhow::Result;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+const NODES_FILE_NAME: &str = "nodes.csv";
+const RELATIONSHIPS_FILE_NAME: &str = "relationships.csv";
+
+fn id(timestamp: &str, node: &str) -> String {
+    format!("{}|{}", timestamp, node)
+}
+
+fn label(level: &str) -> String {
+    let mut chars = level.chars();
+    let capitalized = chars
+        .next()
+        .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+        .unwrap_or_default();
+    format!("Region;{}", capitalized)
+}
+
+pub fn write(graphs: &[Graph], output_dir: &OutputDir) -> Result<usize> {
+    let nodes = || graphs.iter().flat_map(|graph| &graph.nodes);
+    let metrics = metric_types(nodes());
+    let extra_fields = nodes()
+        .flat_map(|node| node.extra_fields.keys().copied())
+        .collect::<BTreeSet<_>>();
+
+    let mut graphs = graphs.iter().collect::<Vec<_>>();
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+
+    let mut node_writer = csv::Writer::from_writer(Vec::new());
+    let mut header = vec![
+        "id:ID".to_string(),
+        "name".to_string(),
+        "date:datetime".to_string(),
+        "level".to_string(),
+    ];
+    header.extend(
+        metrics.iter().map(|(metric, &float)| {
+            format!("{}:{}", metric, if float { "double" } else { "long" })
+        }),
+    );
+    header.extend(extra_fields.iter().map(|field| field.to_string()));
+    header.push(":LABEL".to_string());
+    node_writer.write_record(&header)?;
+
+    let mut relationship_writer = csv::Writer::from_writer(Vec::new());
+    relationship_writer.write_record([":START_ID", ":END_ID", ":TYPE", "weight:double"])?;
+
+    // Date each region was last seen on, for the NEXT relationships
+    let mut previous = HashMap::<&str, &str>::new();
+    for graph in graphs {
+        let ids = graph
+            .nodes
+            .iter()
+            .map(|node| node.id.as_str())
+            .collect::<HashSet<_>>();
+        let mut nodes = graph.nodes.iter().collect::<Vec<&Node>>();
+        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+
+        for node in nodes {
+            let mut record = vec![
+                id(&graph.timestamp, &node.id),
+                node.name.clone(),
+                graph.timestamp.clone(),
+                node.level.to_string(),
+            ];
+            record.extend(metrics.keys().map(|metric| {
+                node.metrics
+                    .get(*metric)
+                    .map(|value| value.to_string())
+                    .unwrap_or_default()
+            }));
+            record.extend(
+                extra_fields
+                    .iter()
+                    .map(|field| node.extra_fields.get(field).cloned().unwrap_or_default()),
+            );
+            record.push(label(node.level));
+            node_writer.write_record(&record)?;
+
+            // Import fails on relationships to missing nodes
+            for edge in node
+                .edges
+                .iter()
+                .filter(|edge| ids.contains(edge.target.as_str()))
+            {
+                relationship_writer.write_record([
+                    id(&graph.timestamp, &node.id),
+                    id(&graph.timestamp, &edge.target),
+                    edge.kind.as_str().to_uppercase(),
+                    edge.weight
+                        .map(|weight| weight.to_string())
+                        .unwrap_or_default(),
+                ])?;
+            }
+            if let Some(last_seen) = previous.insert(&node.id, &graph.timestamp) {
+                relationship_writer.write_record([
+                    &id(last_seen, &node.id),
+                    &id(&graph.timestamp, &node.id),
+                    "NEXT",
+                    "",
+                ])?;
+            }
+        }
+    }
+
+    output_dir.write(NODES_FILE_NAME, &node_writer.into_inner()?)?;
+    output_dir.write(RELATIONSHIPS_FILE_NAME, &relationship_writer.into_inner()?)?;
+    Ok(2)
+}
@@ -0,0 +1,211 @@
+//! The whole series as a single `nodes.parquet` for DuckDB or Spark
+//!
+//! Same columns as the CSV table, with `date` as a UTC timestamp. Every date is
+//! its own row group, so readers filtering on date can skip the other dates.
+
+use super::{metric_types, OutputDir, OutputSink};
+use crate::error::CovidDataError;
+use crate::Graph;
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use parquet::basic::{
+    Compression, LogicalType, Repetition, TimeUnit, Type as PhysicalType, ZstdLevel,
+};
+use parquet::data_type::{ByteArray, ByteArrayType, DataType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::{SerializedFileWriter, SerializedRowGroupWriter};
+use parquet::schema::types::{Type, TypePtr};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::sync::Arc;
+
+const FILE_NAME: &str = "nodes.parquet";
+
+fn column(
+    name: &str,
+    physical: PhysicalType,
+    repetition: Repetition,
+    logical: LogicalType,
+) -> Result<TypePtr> {
+    Ok(Arc::new(
+        Type::primitive_type_builder(name, physical)
+            .with_repetition(repetition)
+            .with_logical_type(Some(logical))
+            .build()?,
+    ))
+}
+
+fn write_column<T: DataType>(
+    row_group: &mut SerializedRowGroupWriter<'_, File>,
+    values: &[T::T],
+    def_levels: Option<&[i16]>,
+) -> Result<()> {
+    let mut column = row_group
+        .next_column()?
+        .context("Wrote more columns than the schema has")?;
+    column.typed::<T>().write_batch(values, def_levels, None)?;
+    column.close()?;
+    Ok(())
+}
+
+/// Split optional values into the present values and their definition levels
+fn optional<V>(values: impl Iterator<Item = Option<V>>) -> (Vec<V>, Vec<i16>) {
+    let mut present = Vec::new();
+    let mut def_levels = Vec::new();
+    for value in values {
+        def_levels.push(value.is_some() as i16);
+        present.extend(value);
+    }
+    (present, def_levels)
+}
+
+fn write(graphs: &[Graph], output_dir: &OutputDir) -> Result<usize> {
+    let nodes = || graphs.iter().flat_map(|graph| &graph.nodes);
+    let metrics = metric_types(nodes());
+    let extra_fields = nodes()
+        .flat_map(|node| node.extra_fields.keys().copied())
+        .collect::<BTreeSet<_>>();
+
+    let timestamp = LogicalType::Timestamp {
+        is_adjusted_to_u_t_c: true,
+        unit: TimeUnit::MILLIS(Default::default()),
+    };
+    let mut fields = vec![
+        column("date", PhysicalType::INT64, Repetition::REQUIRED, timestamp)?,
+        column(
+            "id",
+            PhysicalType::BYTE_ARRAY,
+            Repetition::REQUIRED,
+            LogicalType::String,
+        )?,
+        column(
+            "node",
+            PhysicalType::BYTE_ARRAY,
+            Repetition::REQUIRED,
+            LogicalType::String,
+        )?,
+        column(
+            "level",
+            PhysicalType::BYTE_ARRAY,
+            Repetition::REQUIRED,
+            LogicalType::String,
+        )?,
+    ];
+    for (metric, &float) in &metrics {
+        fields.push(if float {
+            Arc::new(
+                Type::primitive_type_builder(metric, PhysicalType::DOUBLE)
+                    .with_repetition(Repetition::OPTIONAL)
+                    .build()?,
+            )
+        } else {
+            let integer = LogicalType::Integer {
+                bit_width: 64,
+                is_signed: true,
+            };
+            column(metric, PhysicalType::INT64, Repetition::OPTIONAL, integer)?
+        });
+    }
+    for field in &extra_fields {
+        fields.push(column(
+            field,
+            PhysicalType::BYTE_ARRAY,
+            Repetition::OPTIONAL,
+            LogicalType::String,
+        )?);
+    }
+    let schema = Type::group_type_builder("nodes")
+        .with_fields(fields)
+        .build()?;
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .build();
+
+    let path = match output_dir.claim(FILE_NAME)? {
+        Some(path) => path,
+        None => {
+            output_dir.record(&output_dir.dir.join(FILE_NAME), None)?;
+            return Ok(0);
+        }
+    };
+    let file = File::create(&path).map_err(|err| {
+        CovidDataError::output(format_args!("Failed to create {}", path.display()), err)
+    })?;
+    let mut writer = SerializedFileWriter::new(file, Arc::new(schema), Arc::new(properties))?;
+
+    let mut graphs = graphs.iter().collect::<Vec<_>>();
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    for graph in graphs {
+        let millis = DateTime::parse_from_rfc3339(&graph.timestamp)?.timestamp_millis();
+        let mut nodes = graph.nodes.iter().collect::<Vec<_>>();
+        nodes.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut row_group = writer.next_row_group()?;
+        write_column::<Int64Type>(&mut row_group, &vec![millis; nodes.len()], None)?;
+        let ids = nodes
+            .iter()
+            .map(|node| ByteArray::from(node.id.as_str()))
+            .collect::<Vec<_>>();
+        write_column::<ByteArrayType>(&mut row_group, &ids, None)?;
+        let names = nodes
+            .iter()
+            .map(|node| ByteArray::from(node.name.as_str()))
+            .collect::<Vec<_>>();
+        write_column::<ByteArrayType>(&mut row_group, &names, None)?;
+        let levels = nodes
+            .iter()
+            .map(|node| ByteArray::from(node.level))
+            .collect::<Vec<_>>();
+        write_column::<ByteArrayType>(&mut row_group, &levels, None)?;
+
+        for (metric, &float) in &metrics {
+            let values = nodes.iter().map(|node| node.metrics.get(*metric));
+            if float {
+                let (values, def_levels) = optional(values.map(|v| v.map(|v| v.as_f64())));
+                write_column::<DoubleType>(&mut row_group, &values, Some(&def_levels))?;
+            } else {
+                let (values, def_levels) = optional(values.map(|v| v.map(|v| v.as_i64())));
+                write_column::<Int64Type>(&mut row_group, &values, Some(&def_levels))?;
+            }
+        }
+        for field in &extra_fields {
+            let (values, def_levels) = optional(nodes.iter().map(|node| {
+                node.extra_fields
+                    .get(field)
+                    .map(|value| ByteArray::from(value.as_str()))
+            }));
+            write_column::<ByteArrayType>(&mut row_group, &values, Some(&def_levels))?;
+        }
+        row_group.close()?;
+    }
+    writer.close()?;
+    output_dir.record(&path, None)?;
+    Ok(1)
+}
+
+/// Keeps the graphs until `finish`, since the columns are every metric and extra field
+/// of the series
+pub struct ParquetSink<'a> {
+    output_dir: &'a OutputDir<'a>,
+    graphs: Vec<Graph>,
+}
+
+impl<'a> ParquetSink<'a> {
+    pub fn new(output_dir: &'a OutputDir<'a>) -> Self {
+        ParquetSink {
+            output_dir,
+            graphs: Vec::new(),
+        }
+    }
+}
+
+impl OutputSink for ParquetSink<'_> {
+    fn write(&mut self, graph: &Graph) -> Result<()> {
+        self.graphs.push(graph.clone());
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<usize> {
+        write(&self.graphs, self.output_dir)
+    }
+}
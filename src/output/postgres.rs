@@ -0,0 +1,172 @@
+//! Tab-separated tables for Postgres `COPY`, with the DDL to load them into
+//!
+//! `schema.sql` creates the same tables as the SQLite output with `date` as a
+//! `timestamptz`, and turns them into hypertables when TimescaleDB is installed.
+//! `load.sql` then copies in the data files from the current directory:
+//!
+//! ```sh
+//! psql -f schema.sql -f load.sql
+//! ```
+//!
+//! The SQL files are never compressed. With `--compress` the data files are and
+//! `load.sql` reads them through `gzip -dc` or `zstd -dc` on the client.
+
+use super::{Compress, OutputDir};
+use crate::error::CovidDataError;
+use crate::Graph;
+use anyhow::Result;
+use std::fmt::Write;
+use std::fs;
+
+const SCHEMA_FILE_NAME: &str = "schema.sql";
+const LOAD_FILE_NAME: &str = "load.sql";
+
+const SCHEMA: &str = "\
+CREATE TABLE nodes (
+    date TIMESTAMPTZ NOT NULL,
+    node TEXT NOT NULL,
+    name TEXT NOT NULL,
+    level TEXT NOT NULL,
+    PRIMARY KEY (date, node)
+);
+CREATE TABLE metrics (
+    date TIMESTAMPTZ NOT NULL,
+    node TEXT NOT NULL,
+    metric TEXT NOT NULL,
+    value NUMERIC NOT NULL
+);
+CREATE TABLE extra_fields (
+    date TIMESTAMPTZ NOT NULL,
+    node TEXT NOT NULL,
+    field TEXT NOT NULL,
+    value TEXT NOT NULL
+);
+CREATE TABLE edges (
+    date TIMESTAMPTZ NOT NULL,
+    source TEXT NOT NULL,
+    target TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    weight DOUBLE PRECISION
+);
+CREATE INDEX metrics_node_date ON metrics (node, date);
+CREATE INDEX extra_fields_node_date ON extra_fields (node, date);
+CREATE INDEX edges_source_date ON edges (source, date);
+
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM pg_extension WHERE extname = 'timescaledb') THEN
+        PERFORM create_hypertable('nodes', 'date');
+        PERFORM create_hypertable('metrics', 'date');
+        PERFORM create_hypertable('extra_fields', 'date');
+        PERFORM create_hypertable('edges', 'date');
+    END IF;
+END
+$$;
+";
+
+/// Escape a value for `COPY`'s text format
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// A table being written, a row per line with tab-separated columns
+#[derive(Default)]
+struct Table(String);
+
+impl Table {
+    fn row(&mut self, columns: &[&str]) {
+        self.nullable_row(&columns.iter().copied().map(Some).collect::<Vec<_>>());
+    }
+
+    /// A row where `None` columns are written as `\N`, `COPY`'s NULL
+    fn nullable_row(&mut self, columns: &[Option<&str>]) {
+        for (i, column) in columns.iter().enumerate() {
+            if i > 0 {
+                self.0.push('\t');
+            }
+            match column {
+                Some(column) => self.0.push_str(&escape(column)),
+                None => self.0.push_str("\\N"),
+            }
+        }
+        self.0.push('\n');
+    }
+}
+
+pub fn write(graphs: &[Graph], output_dir: &OutputDir) -> Result<usize> {
+    let mut nodes = Table::default();
+    let mut metrics = Table::default();
+    let mut extra_fields = Table::default();
+    let mut edges = Table::default();
+    for graph in graphs {
+        let date = graph.timestamp.as_str();
+        for node in &graph.nodes {
+            nodes.row(&[date, &node.id, &node.name, node.level]);
+            for (metric, value) in &node.metrics {
+                metrics.row(&[date, &node.id, metric, &value.to_string()]);
+            }
+            for (field, value) in &node.extra_fields {
+                extra_fields.row(&[date, &node.id, field, value]);
+            }
+            for edge in &node.edges {
+                let weight = edge.weight.map(|weight| weight.to_string());
+                edges.nullable_row(&[
+                    Some(date),
+                    Some(&node.id),
+                    Some(&edge.target),
+                    Some(edge.kind.as_str()),
+                    weight.as_deref(),
+                ]);
+            }
+        }
+    }
+
+    let mut load = String::new();
+    for (table, data) in [
+        ("nodes", nodes),
+        ("metrics", metrics),
+        ("extra_fields", extra_fields),
+        ("edges", edges),
+    ] {
+        let name = format!("{}.tsv", table);
+        output_dir.write(&name, data.0.as_bytes())?;
+        // Writing into a String can't fail
+        let _ = match output_dir.compress {
+            Some(compress) => {
+                let program = match compress {
+                    Compress::Gzip(_) => "gzip",
+                    Compress::Zstd(_) => "zstd",
+                };
+                writeln!(
+                    load,
+                    "\\copy {} FROM PROGRAM '{} -dc {}.{}'",
+                    table,
+                    program,
+                    name,
+                    compress.extension()
+                )
+            }
+            None => writeln!(load, "\\copy {} FROM '{}'", table, name),
+        };
+    }
+
+    for (name, data) in [(SCHEMA_FILE_NAME, SCHEMA), (LOAD_FILE_NAME, load.as_str())] {
+        if let Some(path) = output_dir.claim(name)? {
+            fs::write(&path, data).map_err(|err| {
+                CovidDataError::output(format_args!("Failed to write {}", path.display()), err)
+            })?;
+        }
+        output_dir.record(&output_dir.dir.join(name), None)?;
+    }
+    Ok(6)
+}
@@ -0,0 +1,125 @@
+//! Protobuf encoding of the graphs, one `.pb` file per date or a single `graphs.pb`
+//! with `--single-file`. The schema is `proto/covid_graph.proto`, which the messages
+//! below have to be kept in sync with.
+
+use super::OutputDir;
+use crate::{Graph, MetricValue, Node};
+use anyhow::Result;
+use prost::Message;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+
+const SINGLE_FILE_NAME: &str = "graphs.pb";
+
+#[derive(Clone, PartialEq, Message)]
+struct GraphsMessage {
+    #[prost(message, repeated, tag = "1")]
+    graphs: Vec<GraphMessage>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct GraphMessage {
+    #[prost(string, tag = "1")]
+    timestamp: String,
+    #[prost(message, repeated, tag = "2")]
+    nodes: Vec<NodeMessage>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct NodeMessage {
+    #[prost(string, tag = "1")]
+    name: String,
+    #[prost(string, tag = "2")]
+    level: String,
+    #[prost(btree_map = "string, int64", tag = "3")]
+    metrics: BTreeMap<String, i64>,
+    #[prost(string, repeated, tag = "4")]
+    edges_directed: Vec<String>,
+    #[prost(btree_map = "string, string", tag = "5")]
+    extra_fields: BTreeMap<String, String>,
+    #[prost(string, tag = "6")]
+    id: String,
+    #[prost(message, repeated, tag = "7")]
+    edges: Vec<EdgeMessage>,
+    #[prost(btree_map = "string, double", tag = "8")]
+    float_metrics: BTreeMap<String, f64>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct EdgeMessage {
+    #[prost(string, tag = "1")]
+    target: String,
+    #[prost(string, tag = "2")]
+    kind: String,
+    #[prost(double, optional, tag = "3")]
+    weight: Option<f64>,
+}
+
+impl From<&Node> for NodeMessage {
+    fn from(node: &Node) -> Self {
+        NodeMessage {
+            name: node.name.clone(),
+            level: node.level.to_string(),
+            metrics: node
+                .metrics
+                .iter()
+                .filter_map(|(metric, value)| match value {
+                    MetricValue::Int(n) => Some((metric.to_string(), *n)),
+                    MetricValue::Float(_) => None,
+                })
+                .collect(),
+            float_metrics: node
+                .metrics
+                .iter()
+                .filter_map(|(metric, value)| match value {
+                    MetricValue::Float(x) => Some((metric.to_string(), *x)),
+                    MetricValue::Int(_) => None,
+                })
+                .collect(),
+            edges_directed: node.edges.iter().map(|edge| edge.target.clone()).collect(),
+            extra_fields: node
+                .extra_fields
+                .iter()
+                .map(|(field, value)| (field.to_string(), value.clone()))
+                .collect(),
+            id: node.id.clone(),
+            edges: node
+                .edges
+                .iter()
+                .map(|edge| EdgeMessage {
+                    target: edge.target.clone(),
+                    kind: edge.kind.as_str().to_string(),
+                    weight: edge.weight,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl From<&Graph> for GraphMessage {
+    fn from(graph: &Graph) -> Self {
+        GraphMessage {
+            timestamp: graph.timestamp.clone(),
+            nodes: graph.nodes.iter().map(Into::into).collect(),
+        }
+    }
+}
+
+pub fn write(graphs: &[Graph], output_dir: &OutputDir) -> Result<usize> {
+    graphs
+        .par_iter()
+        .map(|graph| {
+            output_dir.write_dated(graph, "pb", &GraphMessage::from(graph).encode_to_vec())
+        })
+        .collect::<Result<()>>()?;
+    Ok(graphs.len())
+}
+
+pub fn write_single(mut graphs: Vec<Graph>, output_dir: &OutputDir) -> Result<usize> {
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    let message = GraphsMessage {
+        graphs: graphs.iter().map(Into::into).collect(),
+    };
+    output_dir.write(SINGLE_FILE_NAME, &message.encode_to_vec())?;
+    Ok(1)
+}
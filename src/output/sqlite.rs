@@ -0,0 +1,126 @@
+//! The whole series as a single SQLite database
+//!
+//! ```sql
+//! nodes (date, node, name, level)
+//! metrics (date, node, metric, value)
+//! extra_fields (date, node, field, value)
+//! edges (date, source, target, kind, weight)
+//! ```
+//!
+//! `date` is the graph's RFC 3339 timestamp and nodes are referred to by ID. All tables
+//! are indexed on (date, node), `edges` on (date, source). An existing database at the
+//! path is replaced.
+
+use super::OutputSink;
+use crate::{Graph, MetricValue};
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use std::fs;
+use std::path::Path;
+
+const SCHEMA: &str = "
+    CREATE TABLE nodes (
+        date TEXT NOT NULL,
+        node TEXT NOT NULL,
+        name TEXT NOT NULL,
+        level TEXT NOT NULL,
+        PRIMARY KEY (date, node)
+    );
+    CREATE TABLE metrics (
+        date TEXT NOT NULL,
+        node TEXT NOT NULL,
+        metric TEXT NOT NULL,
+        value NUMERIC NOT NULL
+    );
+    CREATE TABLE extra_fields (
+        date TEXT NOT NULL,
+        node TEXT NOT NULL,
+        field TEXT NOT NULL,
+        value TEXT NOT NULL
+    );
+    CREATE TABLE edges (
+        date TEXT NOT NULL,
+        source TEXT NOT NULL,
+        target TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        weight REAL
+    );
+";
+
+// Created after the inserts, which is faster than maintaining them row by row
+const INDEXES: &str = "
+    CREATE INDEX metrics_date_node ON metrics (date, node);
+    CREATE INDEX extra_fields_date_node ON extra_fields (date, node);
+    CREATE INDEX edges_date_source ON edges (date, source);
+";
+
+/// Inserts each graph as it comes, in one transaction committed by `finish`
+pub struct SqliteSink {
+    connection: Connection,
+}
+
+impl SqliteSink {
+    /// Replace any database at the path with an empty one
+    pub fn create(path: &Path) -> Result<Self> {
+        if path.exists() {
+            fs::remove_file(path)
+                .with_context(|| format!("Failed to replace database {}", path.display()))?;
+        }
+        let connection = Connection::open(path)
+            .with_context(|| format!("Failed to create database {}", path.display()))?;
+        connection.execute_batch(SCHEMA)?;
+        connection.execute_batch("BEGIN")?;
+        Ok(SqliteSink { connection })
+    }
+}
+
+impl OutputSink for SqliteSink {
+    fn write(&mut self, graph: &Graph) -> Result<()> {
+        let mut insert_node = self
+            .connection
+            .prepare_cached("INSERT INTO nodes VALUES (?1, ?2, ?3, ?4)")?;
+        let mut insert_metric = self
+            .connection
+            .prepare_cached("INSERT INTO metrics VALUES (?1, ?2, ?3, ?4)")?;
+        let mut insert_field = self
+            .connection
+            .prepare_cached("INSERT INTO extra_fields VALUES (?1, ?2, ?3, ?4)")?;
+        let mut insert_edge = self
+            .connection
+            .prepare_cached("INSERT INTO edges VALUES (?1, ?2, ?3, ?4, ?5)")?;
+
+        let date = &graph.timestamp;
+        for node in &graph.nodes {
+            insert_node.execute(params![date, node.id, node.name, node.level])?;
+            for (metric, value) in &node.metrics {
+                match value {
+                    MetricValue::Int(n) => {
+                        insert_metric.execute(params![date, node.id, metric, n])?
+                    }
+                    MetricValue::Float(x) => {
+                        insert_metric.execute(params![date, node.id, metric, x])?
+                    }
+                };
+            }
+            for (field, value) in &node.extra_fields {
+                insert_field.execute(params![date, node.id, field, value])?;
+            }
+            for edge in &node.edges {
+                insert_edge.execute(params![
+                    date,
+                    node.id,
+                    edge.target,
+                    edge.kind.as_str(),
+                    edge.weight
+                ])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<usize> {
+        self.connection.execute_batch("COMMIT")?;
+        self.connection.execute_batch(INDEXES)?;
+        Ok(1)
+    }
+}
@@ -0,0 +1,52 @@
+//! Vega-Lite specs for `--chart`, a line chart of confirmed cases and deaths over time
+//! for one node, with the data inlined so the spec renders on its own in vega-embed or
+//! Observable
+
+use super::OutputDir;
+use crate::Graph;
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use serde_json::json;
+
+const SCHEMA: &str = "https://vega.github.io/schema/vega-lite/v5.json";
+const METRICS: &[&str] = &["confirmed", "deaths"];
+
+pub fn write(graphs: &[Graph], node: &str, output_dir: &OutputDir) -> Result<()> {
+    let mut graphs = graphs.iter().collect::<Vec<_>>();
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+
+    let mut values = Vec::new();
+    for graph in graphs {
+        let date = DateTime::parse_from_rfc3339(&graph.timestamp)?
+            .format("%Y-%m-%d")
+            .to_string();
+        if let Some(found) = graph.nodes.iter().find(|n| n.name == node || n.id == node) {
+            for metric in METRICS {
+                if let Some(value) = found.metrics.get(*metric) {
+                    values.push(json!({ "date": date, "metric": metric, "value": value }));
+                }
+            }
+        }
+    }
+    if values.is_empty() {
+        return Err(anyhow!("--chart {}: no node with that name or ID", node));
+    }
+
+    let spec = json!({
+        "$schema": SCHEMA,
+        "title": node,
+        "width": 600,
+        "height": 300,
+        "data": { "values": values },
+        "mark": { "type": "line", "point": true, "tooltip": true },
+        "encoding": {
+            "x": { "field": "date", "type": "temporal", "title": "Date" },
+            "y": { "field": "value", "type": "quantitative", "title": null },
+            "color": { "field": "metric", "type": "nominal", "title": null }
+        }
+    });
+    output_dir.write(
+        &format!("{}.vl.json", node),
+        serde_json::to_string_pretty(&spec)?.as_bytes(),
+    )
+}
@@ -0,0 +1,93 @@
+//! Progress bars on stderr for the long stages of a build: parsing the inputs, grouping
+//! the entries and writing the files
+//!
+//! Off unless `enable`d, which the binary does when stderr is a terminal and there's no
+//! `--quiet`, so embedders and piped logs never see them
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// How often a bar is redrawn at most
+const REDRAW: Duration = Duration::from_millis(100);
+
+const WIDTH: u64 = 30;
+
+pub fn enable(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// A bar counting to `total`, or a bare count without one. Cleared when dropped
+pub struct Progress {
+    label: &'static str,
+    unit: &'static str,
+    total: Option<u64>,
+    done: AtomicU64,
+    /// When it was last drawn, none until it has been
+    drawn: Mutex<Option<Instant>>,
+}
+
+impl Progress {
+    pub fn new(label: &'static str, unit: &'static str, total: Option<u64>) -> Self {
+        Progress {
+            label,
+            unit,
+            total,
+            done: AtomicU64::new(0),
+            drawn: Mutex::new(None),
+        }
+    }
+
+    pub fn inc(&self) {
+        if ENABLED.load(Ordering::Relaxed) {
+            self.draw(self.done.fetch_add(1, Ordering::Relaxed) + 1);
+        }
+    }
+
+    /// Count to `done` at once, for stages too hot to count one at a time
+    pub fn set(&self, done: u64) {
+        if ENABLED.load(Ordering::Relaxed) {
+            self.done.store(done, Ordering::Relaxed);
+            self.draw(done);
+        }
+    }
+
+    fn draw(&self, done: u64) {
+        // Another thread drawing is as good as this one doing it
+        let mut drawn = match self.drawn.try_lock() {
+            Ok(drawn) => drawn,
+            Err(_) => return,
+        };
+        if drawn.is_some_and(|at| at.elapsed() < REDRAW) && Some(done) != self.total {
+            return;
+        }
+        *drawn = Some(Instant::now());
+        let line = match self.total {
+            Some(total) => {
+                let filled = (done * WIDTH / total.max(1)).min(WIDTH) as usize;
+                format!(
+                    "\r{} [{:<width$}] {}/{} {}",
+                    self.label,
+                    "=".repeat(filled),
+                    done,
+                    total,
+                    self.unit,
+                    width = WIDTH as usize
+                )
+            }
+            None => format!("\r{} {} {}", self.label, done, self.unit),
+        };
+        let _ = io::stderr().lock().write_all(line.as_bytes());
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if self.drawn.get_mut().is_ok_and(|drawn| drawn.is_some()) {
+            let _ = io::stderr().lock().write_all(b"\r\x1b[2K");
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! `query` subcommand: nodes' metrics from a run's JSON output, printed as CSV or JSON
+
+use crate::log;
+use crate::meta::Meta;
+use crate::MetricValue;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct QueryOpt {
+    /// Output directory, or single `graphs.json` / `graphs.ndjson` file, of a run
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Only this date (YYYY-MM-DD)
+    #[structopt(long, conflicts_with_all = &["from", "to"])]
+    date: Option<NaiveDate>,
+
+    /// Only dates on or after this one
+    #[structopt(long)]
+    from: Option<NaiveDate>,
+
+    /// Only dates on or before this one
+    #[structopt(long)]
+    to: Option<NaiveDate>,
+
+    /// Node by ID or name, e.g. `36047` or `"New York - Kings"`. Can be given more than
+    /// once, every node by default
+    #[structopt(long, number_of_values = 1)]
+    node: Vec<String>,
+
+    /// Only nodes of this level, e.g. `state`
+    #[structopt(long)]
+    level: Option<String>,
+
+    /// Metric to print. Can be given more than once, every metric of the nodes found by
+    /// default
+    #[structopt(long, number_of_values = 1)]
+    metric: Vec<String>,
+
+    /// Print a JSON array instead of CSV
+    #[structopt(long)]
+    json: bool,
+}
+
+/// A graph as the JSON output has it, for reading it back
+#[derive(Serialize, Deserialize)]
+pub struct Graph {
+    pub timestamp: String,
+    pub nodes: Vec<Node>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Meta>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Node {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub level: String,
+    #[serde(default)]
+    pub metrics: BTreeMap<String, MetricValue>,
+    #[serde(default)]
+    pub edges: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub extra_fields: BTreeMap<String, String>,
+}
+
+impl Graph {
+    pub fn date(&self) -> Result<NaiveDate> {
+        Ok(DateTime::parse_from_rfc3339(&self.timestamp)?.date_naive())
+    }
+}
+
+impl Node {
+    /// Whether the node is the one asked for by ID or name
+    pub fn is(&self, id_or_name: &str) -> bool {
+        self.id == id_or_name || self.name == id_or_name
+    }
+}
+
+/// The graphs of a run's JSON output, ordered by date
+pub fn load(path: &Path) -> Result<Vec<Graph>> {
+    let mut graphs = crate::diff::read_graphs::<Graph>(path)?;
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    Ok(graphs)
+}
+
+#[derive(Serialize)]
+struct Row<'a> {
+    timestamp: &'a str,
+    id: &'a str,
+    name: &'a str,
+    level: &'a str,
+    metrics: BTreeMap<&'a str, MetricValue>,
+}
+
+pub fn run(opt: QueryOpt) -> Result<()> {
+    let QueryOpt {
+        input,
+        date,
+        from,
+        to,
+        node,
+        level,
+        metric,
+        json,
+    } = opt;
+    let (from, to) = match date {
+        Some(date) => (Some(date), Some(date)),
+        None => (from, to),
+    };
+
+    let graphs = log::stage("load", || {
+        let graphs = load(&input)?;
+        tracing::info!(dates = graphs.len());
+        Ok(graphs)
+    })?;
+    let mut rows = Vec::new();
+    for graph in &graphs {
+        let day = graph.date()?;
+        if from.is_some_and(|from| day < from) || to.is_some_and(|to| day > to) {
+            continue;
+        }
+        let nodes = graph.nodes.iter().filter(|n| {
+            (node.is_empty() || node.iter().any(|wanted| n.is(wanted)))
+                && level.as_ref().is_none_or(|level| &n.level == level)
+        });
+        for n in nodes {
+            rows.push(Row {
+                timestamp: &graph.timestamp,
+                id: &n.id,
+                name: &n.name,
+                level: &n.level,
+                metrics: n
+                    .metrics
+                    .iter()
+                    .filter(|(name, _)| metric.is_empty() || metric.contains(name))
+                    .map(|(name, &value)| (name.as_str(), value))
+                    .collect(),
+            });
+        }
+    }
+    if rows.is_empty() {
+        return Err(anyhow!("No nodes match in {}", input.display()));
+    }
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&rows)?);
+        return Ok(());
+    }
+    let columns = match metric.is_empty() {
+        true => rows
+            .iter()
+            .flat_map(|row| row.metrics.keys().map(|metric| metric.as_ref()))
+            .collect::<BTreeSet<_>>(),
+        false => metric.iter().map(String::as_str).collect(),
+    };
+    let mut writer = csv::Writer::from_writer(io::stdout());
+    writer.write_record(
+        ["date", "id", "node", "level"]
+            .iter()
+            .copied()
+            .chain(columns.iter().copied()),
+    )?;
+    for row in &rows {
+        let mut record = vec![
+            row.timestamp.to_string(),
+            row.id.to_string(),
+            row.name.to_string(),
+            row.level.to_string(),
+        ];
+        record.extend(columns.iter().map(|column| {
+            row.metrics
+                .get(column)
+                .map(|value| value.to_string())
+                .unwrap_or_default()
+        }));
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
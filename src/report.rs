@@ -0,0 +1,222 @@
+//! Static HTML summary of a run for `--report`: national and state totals, the top
+//! counties and the national trend, all as of the latest date
+
+use crate::output::escape_xml;
+use crate::{Graph, Node};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Duration, NaiveDate};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+
+const TOP_COUNTIES: usize = 20;
+const TREND_DATES: usize = 14;
+
+const STYLE: &str = "
+    body { font-family: sans-serif; margin: 2em; color: #222; }
+    table { border-collapse: collapse; margin-bottom: 2em; }
+    th, td { padding: 0.3em 0.8em; border-bottom: 1px solid #ddd; }
+    th { text-align: left; background: #f4f4f4; }
+    td.n { text-align: right; font-variant-numeric: tabular-nums; }
+";
+
+/// Node metrics on one date, by node name
+struct Day<'a> {
+    date: NaiveDate,
+    nodes: HashMap<&'a str, &'a Node>,
+}
+
+impl<'a> Day<'a> {
+    fn new(graph: &'a Graph) -> Result<Self> {
+        let date = DateTime::parse_from_rfc3339(&graph.timestamp)?.date_naive();
+        let nodes = graph
+            .nodes
+            .iter()
+            .map(|node| (node.name.as_str(), node))
+            .collect();
+        Ok(Day { date, nodes })
+    }
+
+    fn metric(&self, name: &str, metric: &str) -> Option<i64> {
+        Some(self.nodes.get(name)?.metrics.get(metric)?.as_i64())
+    }
+
+    /// National total of a metric, summed over the states
+    fn total(&self, metric: &str) -> i64 {
+        self.nodes
+            .values()
+            .filter(|node| node.level == "state")
+            .filter_map(|node| node.metrics.get(metric))
+            .map(|value| value.as_i64())
+            .sum()
+    }
+}
+
+fn number(value: Option<i64>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn change(value: Option<i64>) -> String {
+    match value {
+        Some(value) if value > 0 => format!("+{}", value),
+        value => number(value),
+    }
+}
+
+fn table(html: &mut String, header: &[&str], rows: &[Vec<String>]) {
+    // Writing into a String can't fail
+    let _ = write!(html, "<table>\n<tr>");
+    for column in header {
+        let _ = write!(html, "<th>{}</th>", escape_xml(column));
+    }
+    let _ = writeln!(html, "</tr>");
+    for row in rows {
+        let _ = write!(html, "<tr>");
+        for (i, cell) in row.iter().enumerate() {
+            let class = if i == 0 { "" } else { " class=\"n\"" };
+            let _ = write!(html, "<td{}>{}</td>", class, escape_xml(cell));
+        }
+        let _ = writeln!(html, "</tr>");
+    }
+    let _ = writeln!(html, "</table>");
+}
+
+pub fn write(graphs: &[Graph], path: &Path) -> Result<()> {
+    let mut graphs = graphs.iter().collect::<Vec<_>>();
+    graphs.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    let days = graphs
+        .into_iter()
+        .map(Day::new)
+        .collect::<Result<Vec<_>>>()?;
+    let latest = days
+        .last()
+        .ok_or_else(|| anyhow!("No graphs to report on"))?;
+    let days_before = |n| {
+        let date = latest.date - Duration::days(n);
+        days.iter().find(|day| day.date == date)
+    };
+    let previous = days_before(1);
+    let week_ago = days_before(7);
+    // Change of a node's metric since an earlier day
+    let since = |day: Option<&Day>, name: &str, metric: &str| {
+        Some(latest.metric(name, metric)? - day?.metric(name, metric)?)
+    };
+
+    let mut html = String::new();
+    let title = format!("COVID-19 summary for {}", latest.date);
+    let _ = writeln!(
+        html,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">"
+    );
+    let _ = writeln!(html, "<title>{}</title>", title);
+    let _ = writeln!(html, "<style>{}</style>\n</head>\n<body>", STYLE);
+    let _ = writeln!(html, "<h1>{}</h1>", title);
+    let _ = writeln!(
+        html,
+        "<p>{} dates from {} to {}, {} nodes on the latest date.</p>",
+        days.len(),
+        days[0].date,
+        latest.date,
+        latest.nodes.len()
+    );
+
+    let _ = writeln!(html, "<h2>National totals</h2>");
+    let total_change =
+        |day: Option<&Day>, metric: &str| day.map(|day| latest.total(metric) - day.total(metric));
+    table(
+        &mut html,
+        &["", "Total", "Change on the day", "Change over 7 days"],
+        &["confirmed", "deaths"].map(|metric| {
+            vec![
+                metric.to_string(),
+                latest.total(metric).to_string(),
+                change(total_change(previous, metric)),
+                change(total_change(week_ago, metric)),
+            ]
+        }),
+    );
+
+    let by_confirmed = |level: &str| {
+        let mut nodes = latest
+            .nodes
+            .values()
+            .filter(|node| node.level == level)
+            .copied()
+            .collect::<Vec<_>>();
+        nodes.sort_by_key(|node| {
+            (
+                std::cmp::Reverse(node.metrics.get("confirmed").map(|v| v.as_i64())),
+                node.name.as_str(),
+            )
+        });
+        nodes
+    };
+    let rows = |nodes: &[&Node]| {
+        nodes
+            .iter()
+            .map(|node| {
+                let name = node.name.as_str();
+                vec![
+                    name.to_string(),
+                    number(latest.metric(name, "confirmed")),
+                    change(since(previous, name, "confirmed")),
+                    change(since(week_ago, name, "confirmed")),
+                    number(latest.metric(name, "deaths")),
+                    change(since(previous, name, "deaths")),
+                ]
+            })
+            .collect::<Vec<_>>()
+    };
+    let header = [
+        "",
+        "Confirmed",
+        "New on the day",
+        "New over 7 days",
+        "Deaths",
+        "New on the day",
+    ];
+
+    let _ = writeln!(html, "<h2>States</h2>");
+    table(&mut html, &header, &rows(&by_confirmed("state")));
+
+    let _ = writeln!(
+        html,
+        "<h2>Top {} counties by confirmed cases</h2>",
+        TOP_COUNTIES
+    );
+    let counties = by_confirmed("county");
+    table(
+        &mut html,
+        &header,
+        &rows(&counties[..counties.len().min(TOP_COUNTIES)]),
+    );
+
+    let _ = writeln!(html, "<h2>National trend</h2>");
+    let start = days.len().saturating_sub(TREND_DATES);
+    let trend = (start..days.len())
+        .rev()
+        .map(|i| {
+            let day = &days[i];
+            let new = |metric| {
+                i.checked_sub(1)
+                    .map(|j| day.total(metric) - days[j].total(metric))
+            };
+            vec![
+                day.date.to_string(),
+                day.total("confirmed").to_string(),
+                change(new("confirmed")),
+                day.total("deaths").to_string(),
+                change(new("deaths")),
+            ]
+        })
+        .collect::<Vec<_>>();
+    table(
+        &mut html,
+        &["Date", "Confirmed", "New", "Deaths", "New"],
+        &trend,
+    );
+
+    let _ = writeln!(html, "</body>\n</html>");
+    fs::write(path, html).with_context(|| format!("Failed to write {}", path.display()))
+}
@@ -0,0 +1,205 @@
+//! `schema` subcommand: the JSON Schema of a graph as the JSON output writes it
+//!
+//! Written by hand next to the types in `graph` and `meta`, so a field added there needs
+//! adding here too, which the tests check against a built graph. A `graphs.json` file is an
+//! array of these, and `graphs.ndjson` has one per line
+
+use crate::meta::SCHEMA_VERSION;
+use anyhow::Result;
+use serde_json::{json, Value};
+
+/// Every level a node can have
+const LEVELS: &[&str] = &[
+    "country",
+    "state",
+    "county",
+    "zcta",
+    "metro",
+    "hhs_region",
+    "census_region",
+    "census_division",
+];
+
+pub fn graph() -> Value {
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "Graph",
+        "description": format!(
+            "The nodes of one date and the edges between them, schema version {}",
+            SCHEMA_VERSION
+        ),
+        "type": "object",
+        "required": ["timestamp", "nodes"],
+        "properties": {
+            "timestamp": { "type": "string", "format": "date-time" },
+            "nodes": { "type": "array", "items": { "$ref": "#/$defs/node" } },
+            "meta": { "$ref": "#/$defs/meta" }
+        },
+        "$defs": {
+            "node": {
+                "type": "object",
+                "required": ["id", "name", "level", "metrics", "edges", "extra_fields"],
+                "properties": {
+                    "id": {
+                        "description": "FIPS code for states and counties, `zcta:` and \
+                                        `cbsa:` prefixed codes, else the name",
+                        "type": "string"
+                    },
+                    "name": { "type": "string" },
+                    "level": { "enum": LEVELS },
+                    "metrics": {
+                        "description": "Counts are integers, rates like the per capita \
+                                        metrics are numbers",
+                        "type": "object",
+                        "additionalProperties": { "type": "number" }
+                    },
+                    "edges": { "type": "array", "items": { "$ref": "#/$defs/edge" } },
+                    "edges_directed": {
+                        "description": "Only with `--legacy-edges`: the edges' targets",
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "uniqueItems": true
+                    },
+                    "extra_fields": {
+                        "type": "object",
+                        "additionalProperties": { "type": "string" }
+                    }
+                }
+            },
+            "edge": {
+                "type": "object",
+                "required": ["target", "kind"],
+                "properties": {
+                    "target": { "description": "ID of the node pointed to", "type": "string" },
+                    "kind": { "enum": ["contains", "adjacent", "commute"] },
+                    "weight": { "type": "number" }
+                }
+            },
+            "meta": {
+                "type": "object",
+                "required": ["schema_version", "generated_at", "tool_version", "inputs"],
+                "properties": {
+                    "schema_version": { "type": "integer", "minimum": 1 },
+                    "generated_at": { "type": "string", "format": "date-time" },
+                    "tool_version": { "type": "string" },
+                    "inputs": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "required": ["source", "path"],
+                            "properties": {
+                                "source": { "type": "string" },
+                                "path": { "type": "string" },
+                                "sha256": { "type": "string", "pattern": "^[0-9a-f]{64}$" }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+pub fn run() -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&graph())?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meta::{Input, Meta};
+    use crate::source::RawEntry;
+    use crate::{Graph, GraphBuilder};
+    use std::collections::BTreeSet;
+
+    fn built() -> Graph {
+        let entry = RawEntry {
+            date: 1585699200000,
+            county: "Kings".to_string(),
+            state: "New York".to_string(),
+            values: 5,
+            entry_type: "Confirmed".to_string(),
+            fips: Some("36047".to_string()),
+            zcta: None,
+            date_only: true,
+        };
+        GraphBuilder::new()
+            .add_entry(entry)
+            .build()
+            .unwrap()
+            .remove(0)
+    }
+
+    /// A built graph with every optional field set
+    fn with_everything() -> Graph {
+        let mut graph = built();
+        for node in &mut graph.nodes {
+            node.edges_directed = Some(node.edges.iter().map(|e| e.target.clone()).collect());
+            for edge in &mut node.edges {
+                edge.weight = Some(1.0);
+            }
+        }
+        graph.meta = Some(Meta {
+            inputs: vec![Input {
+                source: "knowi".to_string(),
+                path: "covid_county.json".to_string(),
+                sha256: Some("0".repeat(64)),
+            }],
+            ..Meta::new(Vec::new()).unwrap()
+        });
+        graph
+    }
+
+    /// The keys of each object in `value` against its schema's properties: all of them with
+    /// `everything`, else only the required ones
+    fn compare(path: &str, value: &Value, schema: &Value, everything: bool) {
+        let schema = match schema["$ref"].as_str() {
+            Some(reference) => graph()["$defs"][reference.trim_start_matches("#/$defs/")].clone(),
+            None => schema.clone(),
+        };
+        match value {
+            Value::Object(object) if schema["properties"].is_object() => {
+                let keys = object.keys().map(String::as_str).collect::<BTreeSet<_>>();
+                let expected = match everything {
+                    true => schema["properties"]
+                        .as_object()
+                        .unwrap()
+                        .keys()
+                        .map(String::as_str)
+                        .collect::<BTreeSet<_>>(),
+                    false => schema["required"]
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .map(|key| key.as_str().unwrap())
+                        .collect(),
+                };
+                assert_eq!(keys, expected, "keys of {}", path);
+                for (key, value) in object {
+                    let path = format!("{}.{}", path, key);
+                    compare(&path, value, &schema["properties"][key], everything);
+                }
+            }
+            Value::Array(items) if schema["items"].is_object() => {
+                for (i, item) in items.iter().enumerate() {
+                    let path = format!("{}[{}]", path, i);
+                    compare(&path, item, &schema["items"], everything);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn every_field_is_in_the_schema() {
+        let graph = serde_json::to_value(with_everything()).unwrap();
+        compare("graph", &graph, &super::graph(), true);
+    }
+
+    #[test]
+    fn the_fields_always_there_are_required() {
+        let graph = serde_json::to_value(built()).unwrap();
+        compare("graph", &graph, &super::graph(), false);
+    }
+}
@@ -0,0 +1,190 @@
+//! `serve` subcommand: a run's JSON output over HTTP, a request per connection, each on a
+//! thread of its own
+//!
+//! - `GET /dates`: the dates there are graphs for
+//! - `GET /graphs/<YYYY-MM-DD>`: the graph of a date
+//! - `GET /nodes/<id or name>`: a node on every date it's in, as `{timestamp, node}`s
+
+use crate::log;
+use crate::query::{self, Graph};
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use serde::Serialize;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct ServeOpt {
+    /// Output directory, or single `graphs.json` / `graphs.ndjson` file, of a run
+    #[structopt(parse(from_os_str))]
+    input: PathBuf,
+
+    /// Address to listen on
+    #[structopt(long, default_value = "127.0.0.1:8080")]
+    addr: String,
+}
+
+struct Response {
+    status: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json<T: Serialize>(value: &T) -> Result<Self> {
+        Ok(Response {
+            status: "200 OK",
+            body: serde_json::to_string(value)?,
+        })
+    }
+
+    fn error(status: &'static str, message: &str) -> Self {
+        Response {
+            status,
+            body: serde_json::json!({ "error": message }).to_string(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Dated<'a> {
+    timestamp: &'a str,
+    node: &'a query::Node,
+}
+
+/// `%XX` escapes in a request path, as in `/nodes/New%20York`
+fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = path.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn route(graphs: &[Graph], method: &str, path: &str) -> Result<Response> {
+    if method != "GET" {
+        return Ok(Response::error(
+            "405 Method Not Allowed",
+            "Only GET is served",
+        ));
+    }
+    let path = match percent_decode(path.split('?').next().unwrap_or_default()) {
+        Some(path) => path,
+        None => return Ok(Response::error("400 Bad Request", "Bad escape in path")),
+    };
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["dates"] => {
+            let dates = graphs.iter().map(Graph::date).collect::<Result<Vec<_>>>()?;
+            Response::json(&dates)
+        }
+        ["graphs", date] => {
+            let date = match date.parse::<NaiveDate>() {
+                Ok(date) => date,
+                Err(_) => return Ok(Response::error("400 Bad Request", "Dates are YYYY-MM-DD")),
+            };
+            for graph in graphs {
+                if graph.date()? == date {
+                    return Response::json(graph);
+                }
+            }
+            Ok(Response::error("404 Not Found", "No graph for that date"))
+        }
+        ["nodes", id_or_name] => {
+            let series: Vec<_> = graphs
+                .iter()
+                .flat_map(|graph| {
+                    graph
+                        .nodes
+                        .iter()
+                        .filter(|node| node.is(id_or_name))
+                        .map(move |node| Dated {
+                            timestamp: &graph.timestamp,
+                            node,
+                        })
+                })
+                .collect();
+            if series.is_empty() {
+                return Ok(Response::error("404 Not Found", "No such node"));
+            }
+            Response::json(&series)
+        }
+        _ => Ok(Response::error("404 Not Found", "No such route")),
+    }
+}
+
+/// How long a client can take to send its request or read the response, so one that
+/// stalls doesn't hold on to a thread
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn handle(graphs: &[Graph], stream: TcpStream) -> Result<(String, String, &'static str)> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // The headers aren't used, but are read so the client isn't cut off mid-request
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(anyhow!("Bad request line {:?}", request_line.trim_end())),
+    };
+    let response = route(graphs, &method, &path)?;
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        response.status,
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()?;
+    Ok((method, path, response.status))
+}
+
+pub fn run(opt: ServeOpt) -> Result<()> {
+    let graphs = log::stage("load", || {
+        let graphs = query::load(&opt.input)?;
+        tracing::info!(dates = graphs.len());
+        Ok(graphs)
+    })?;
+    let listener = TcpListener::bind(&opt.addr)?;
+    log::stage("listen", || {
+        tracing::info!(addr = %listener.local_addr()?);
+        Ok(())
+    })?;
+    let graphs = &graphs;
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            // A client going away mid-request, or timing out, is logged, not fatal
+            scope.spawn(move || {
+                let _ = log::stage("request", || {
+                    let (method, path, status) = handle(graphs, stream?)?;
+                    tracing::info!(method = method);
+                    tracing::info!(path = path);
+                    tracing::info!(status = status);
+                    Ok(())
+                });
+            });
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,104 @@
+//! JHU CSSE daily reports (`csse_covid_19_daily_reports`)
+//!
+//! One CSV per day named `MM-DD-YYYY.csv`. Only the US county level rows
+//! (the ones with an `Admin2` column) are used, older files without it are skipped.
+
+use super::{normalize_fips, DataSource, Entries, RawEntry};
+use anyhow::{Context, Result};
+use chrono::{NaiveDate, NaiveTime};
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize)]
+struct DailyReportRow {
+    #[serde(rename = "FIPS", default)]
+    fips: Option<String>,
+    #[serde(rename = "Admin2", default)]
+    admin2: Option<String>,
+    #[serde(rename = "Province_State", default)]
+    province_state: Option<String>,
+    #[serde(rename = "Country_Region", default)]
+    country_region: Option<String>,
+    #[serde(rename = "Confirmed", default)]
+    confirmed: Option<i64>,
+    #[serde(rename = "Deaths", default)]
+    deaths: Option<i64>,
+}
+
+/// Parse the report date out of a `MM-DD-YYYY.csv` file name
+fn report_date(path: &Path) -> Option<NaiveDate> {
+    let stem = path.file_stem()?.to_str()?;
+    NaiveDate::parse_from_str(stem, "%m-%d-%Y").ok()
+}
+
+fn read_report(path: &Path, date: NaiveDate) -> Result<Vec<RawEntry>> {
+    let millis = date.and_time(NaiveTime::MIN).and_utc().timestamp_millis();
+    let mut entries = Vec::new();
+
+    let mut reader = csv::Reader::from_path(path)?;
+    for row in reader.deserialize::<DailyReportRow>() {
+        let row = row?;
+        let (county, state) = match (row.admin2, row.province_state) {
+            (Some(county), Some(state)) if !county.is_empty() => (county, state),
+            _ => continue,
+        };
+        if row.country_region.as_deref() != Some("US") {
+            continue;
+        }
+        let fips = row.fips.as_deref().and_then(normalize_fips);
+
+        for (entry_type, value) in &[("Confirmed", row.confirmed), ("Deaths", row.deaths)] {
+            if let Some(value) = value {
+                entries.push(RawEntry {
+                    date: millis,
+                    county: county.clone(),
+                    state: state.clone(),
+                    values: *value,
+                    entry_type: entry_type.to_string(),
+                    fips: fips.clone(),
+                    zcta: None,
+                    date_only: true,
+                });
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Read every daily report in `dir` into raw entries
+fn read_daily_reports(dir: &Path) -> Result<Vec<RawEntry>> {
+    let mut reports = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read JHU reports dir {}", dir.display()))?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<Result<Vec<PathBuf>>>()?;
+    reports.sort();
+
+    let entries = reports
+        .par_iter()
+        .filter_map(|path| report_date(path).map(|date| (path, date)))
+        .map(|(path, date)| {
+            read_report(path, date)
+                .with_context(|| format!("Failed to parse JHU report {}", path.display()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(entries.into_iter().flatten().collect())
+}
+
+pub struct JhuSource {
+    dir: PathBuf,
+}
+
+impl JhuSource {
+    pub fn new(dir: PathBuf) -> Self {
+        JhuSource { dir }
+    }
+}
+
+impl DataSource for JhuSource {
+    fn entries(&self) -> Result<Entries<'_>> {
+        let entries = read_daily_reports(&self.dir)?;
+        Ok(Box::new(entries.into_iter().map(Ok)))
+    }
+}
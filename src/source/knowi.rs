@@ -0,0 +1,208 @@
+//! Knowi API exports (https://www.knowi.com/coronavirus-dashboards/covid-19-api/)
+
+use super::{open_input, read_input, Compression, DataSource, Entries, RawEntry};
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy)]
+pub enum InputFormat {
+    Json,
+    /// Newline-delimited JSON, one entry per line
+    Ndjson,
+    Csv,
+}
+
+impl InputFormat {
+    /// Guess the format from the file extension, defaulting to JSON.
+    /// A trailing compression extension (`.gz`, `.zst`) is skipped.
+    pub fn from_path(path: &Path) -> Self {
+        let path = match Compression::from_path(path) {
+            Compression::None => path.to_path_buf(),
+            _ => path.with_extension(""),
+        };
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => InputFormat::Csv,
+            Some(ext)
+                if ext.eq_ignore_ascii_case("ndjson") || ext.eq_ignore_ascii_case("jsonl") =>
+            {
+                InputFormat::Ndjson
+            }
+            _ => InputFormat::Json,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(InputFormat::Json),
+            "ndjson" => Ok(InputFormat::Ndjson),
+            "csv" => Ok(InputFormat::Csv),
+            _ => Err(anyhow!("Unknown input format: {}", s)),
+        }
+    }
+}
+
+/// Granularity of the Knowi entity the export was taken from
+#[derive(Debug, Clone, Copy)]
+pub enum Entity {
+    /// "Raw County level Data"
+    County,
+    /// "Raw State level Data", rows have no `County`
+    State,
+    /// Global country data. Countries take the place of states in the graph
+    Country,
+}
+
+impl FromStr for Entity {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "county" => Ok(Entity::County),
+            "state" => Ok(Entity::State),
+            "country" => Ok(Entity::Country),
+            _ => Err(anyhow!("Unknown Knowi entity: {}", s)),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct StateRow {
+    #[serde(rename(deserialize = "Date"))]
+    date: i64,
+    #[serde(rename(deserialize = "State"))]
+    state: String,
+    values: i64,
+    #[serde(rename(deserialize = "Type"))]
+    entry_type: String,
+}
+
+impl From<StateRow> for RawEntry {
+    fn from(row: StateRow) -> Self {
+        RawEntry {
+            date: row.date,
+            county: String::new(),
+            state: row.state,
+            values: row.values,
+            entry_type: row.entry_type,
+            fips: None,
+            zcta: None,
+            date_only: false,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct CountryRow {
+    #[serde(rename(deserialize = "Date"))]
+    date: i64,
+    #[serde(rename(deserialize = "Country"))]
+    country: String,
+    values: i64,
+    #[serde(rename(deserialize = "Type"))]
+    entry_type: String,
+}
+
+impl From<CountryRow> for RawEntry {
+    fn from(row: CountryRow) -> Self {
+        RawEntry {
+            date: row.date,
+            county: String::new(),
+            state: row.country,
+            values: row.values,
+            entry_type: row.entry_type,
+            fips: None,
+            zcta: None,
+            date_only: false,
+        }
+    }
+}
+
+/// Parse rows of type `T` out of an in-memory export.
+/// With the `simd-json` feature the buffer is parsed in place and left garbled
+pub fn parse_entries<T>(raw_data: &mut [u8], format: InputFormat) -> Result<Vec<RawEntry>>
+where
+    T: DeserializeOwned + Into<RawEntry>,
+{
+    let rows = match format {
+        #[cfg(feature = "simd-json")]
+        InputFormat::Json => {
+            simd_json::serde::from_slice::<Vec<T>>(raw_data).context("Failed to parse JSON")?
+        }
+        #[cfg(not(feature = "simd-json"))]
+        InputFormat::Json => {
+            serde_json::from_slice::<Vec<T>>(raw_data).context("Failed to parse JSON")?
+        }
+        InputFormat::Ndjson => serde_json::Deserializer::from_slice(raw_data)
+            .into_iter::<T>()
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to parse NDJSON")?,
+        InputFormat::Csv => csv::Reader::from_reader(&raw_data[..])
+            .deserialize::<T>()
+            .collect::<Result<Vec<_>, _>>()
+            .context("Failed to parse CSV")?,
+    };
+    Ok(rows.into_iter().map(Into::into).collect())
+}
+
+/// Parse newline-delimited JSON record by record, without buffering the whole input
+fn stream_ndjson<T>(reader: impl Read + Send) -> impl Iterator<Item = Result<RawEntry>> + Send
+where
+    T: DeserializeOwned + Into<RawEntry> + Send,
+{
+    serde_json::Deserializer::from_reader(reader)
+        .into_iter::<T>()
+        .map(|row| Ok(row.context("Failed to parse NDJSON record")?.into()))
+}
+
+pub struct KnowiSource {
+    input: PathBuf,
+    format: InputFormat,
+    entity: Entity,
+    mmap: bool,
+}
+
+impl KnowiSource {
+    /// The format is detected from the file extension if not given
+    pub fn new(input: PathBuf, format: Option<InputFormat>, entity: Entity, mmap: bool) -> Self {
+        let format = format.unwrap_or_else(|| InputFormat::from_path(&input));
+        KnowiSource {
+            input,
+            format,
+            entity,
+            mmap,
+        }
+    }
+
+    fn read<T>(&self) -> Result<Entries<'_>>
+    where
+        T: DeserializeOwned + Into<RawEntry> + Send + 'static,
+    {
+        if let InputFormat::Ndjson = self.format {
+            // Stream records straight into the fold so memory use doesn't grow with the input
+            let reader = open_input(&self.input)?;
+            return Ok(Box::new(stream_ndjson::<T>(reader)));
+        }
+
+        let mut raw_data = read_input(&self.input, self.mmap)?;
+        let entries = parse_entries::<T>(&mut raw_data, self.format)?;
+        Ok(Box::new(entries.into_iter().map(Ok)))
+    }
+}
+
+impl DataSource for KnowiSource {
+    fn entries(&self) -> Result<Entries<'_>> {
+        match self.entity {
+            Entity::County => self.read::<RawEntry>(),
+            Entity::State => self.read::<StateRow>(),
+            Entity::Country => self.read::<CountryRow>(),
+        }
+    }
+}
@@ -0,0 +1,345 @@
+//! Upstream feeds, each behind the `DataSource` trait
+//!
+//! Adding a feed means implementing `DataSource` and registering a constructor
+//! in `REGISTRY`, the grouping and graph building stay untouched.
+
+use crate::progress::Progress;
+use anyhow::{anyhow, Context, Result};
+use rayon::prelude::*;
+use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, Read};
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use structopt::StructOpt;
+
+pub mod jhu;
+pub mod knowi;
+pub mod nyt;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+pub mod xlsx;
+
+pub use knowi::{Entity, InputFormat};
+
+/// One observation from an upstream feed. Field names follow the Knowi export,
+/// other adapters map their columns onto it. `county` is empty for records that
+/// only exist at the state level.
+#[derive(Deserialize, Clone, PartialEq, Eq, Hash)]
+pub struct RawEntry {
+    #[serde(rename(deserialize = "Date"))]
+    pub date: i64,
+    #[serde(rename(deserialize = "County"))]
+    pub county: String,
+    #[serde(rename(deserialize = "State"))]
+    pub state: String,
+
+    pub values: i64,
+
+    #[serde(rename(deserialize = "Type"))]
+    pub entry_type: String,
+
+    /// 5 digit county FIPS code, when the feed has one
+    #[serde(rename(deserialize = "FIPS"), default)]
+    pub fips: Option<String>,
+
+    /// ZIP Code Tabulation Area, for feeds with ZIP level counts below the county
+    #[serde(rename(deserialize = "ZCTA"), default)]
+    pub zcta: Option<String>,
+
+    /// The feed has a date but no time of day, so `--timezone` leaves it on its date
+    #[serde(skip)]
+    pub date_only: bool,
+}
+
+/// Normalize FIPS codes that may come through as numbers ("1001", "36047.0")
+/// to the zero padded 5 digit form
+pub fn normalize_fips(raw: &str) -> Option<String> {
+    let code = raw.trim().parse::<f64>().ok()?;
+    if code <= 0.0 || code.fract() != 0.0 {
+        return None;
+    }
+    Some(format!("{:05}", code as u32))
+}
+
+pub type Entries<'a> = Box<dyn Iterator<Item = Result<RawEntry>> + Send + 'a>;
+
+pub trait DataSource: Send + Sync {
+    /// Open the source and iterate over its entries.
+    /// Sources that can't be parsed incrementally may read everything up front.
+    fn entries(&self) -> Result<Entries<'_>>;
+}
+
+#[derive(Debug, StructOpt)]
+pub struct SourceOpt {
+    /// Upstream feed the input comes from. For `jhu` the input is the daily reports directory.
+    /// `parquet` and `sqlite` are only available when built with the feature of the same name
+    #[structopt(long, default_value = "knowi", possible_values = &names())]
+    pub source: String,
+
+    /// Format of a Knowi input file. Detected from the file extension if omitted
+    #[structopt(long, possible_values = &["json", "ndjson", "csv"])]
+    pub format: Option<InputFormat>,
+
+    /// Knowi entity the input was exported from
+    #[structopt(long, default_value = "county", possible_values = &["county", "state", "country"])]
+    pub entity: Entity,
+
+    /// Read input files into memory instead of memory-mapping them, e.g. on network filesystems
+    #[structopt(long)]
+    pub no_mmap: bool,
+
+    /// SQL query selecting the entries for `--source sqlite`, with columns named as in the Knowi export
+    #[structopt(long)]
+    pub query: Option<String>,
+
+    /// Sheet to read for `--source xlsx`, the first one by default
+    #[structopt(long)]
+    pub sheet: Option<String>,
+
+    /// Spreadsheet column holding an entry field for `--source xlsx`, e.g. `date=Report Date`.
+    /// Fields are date, county, state, type, values, fips and zcta
+    #[structopt(long = "column", value_name = "FIELD=HEADER", number_of_values = 1)]
+    pub columns: Vec<xlsx::ColumnMapping>,
+}
+
+type Constructor = fn(PathBuf, &SourceOpt) -> Box<dyn DataSource>;
+
+const REGISTRY: &[(&str, Constructor)] = &[
+    ("knowi", |input, opt| {
+        Box::new(knowi::KnowiSource::new(
+            input,
+            opt.format,
+            opt.entity,
+            !opt.no_mmap,
+        ))
+    }),
+    ("jhu", |input, _| Box::new(jhu::JhuSource::new(input))),
+    ("nyt", |input, _| Box::new(nyt::NytSource::new(input))),
+    ("csv", |input, opt| {
+        Box::new(knowi::KnowiSource::new(
+            input,
+            Some(InputFormat::Csv),
+            opt.entity,
+            !opt.no_mmap,
+        ))
+    }),
+    #[cfg(feature = "parquet")]
+    ("parquet", |input, _| {
+        Box::new(self::parquet::ParquetSource::new(input))
+    }),
+    #[cfg(feature = "sqlite")]
+    ("sqlite", |input, opt| {
+        Box::new(sqlite::SqliteSource::new(input, opt.query.clone()))
+    }),
+    ("xlsx", |input, opt| {
+        Box::new(xlsx::XlsxSource::new(
+            input,
+            opt.sheet.clone(),
+            opt.columns.clone(),
+        ))
+    }),
+];
+
+/// Names accepted by `--source`
+pub fn names() -> Vec<&'static str> {
+    REGISTRY.iter().map(|(name, _)| *name).collect()
+}
+
+pub fn create(input: PathBuf, opt: &SourceOpt) -> Result<Box<dyn DataSource>> {
+    let (_, constructor) = REGISTRY
+        .iter()
+        .find(|(name, _)| *name == opt.source)
+        .ok_or_else(|| anyhow!("Unknown source: {}", opt.source))?;
+    if opt.query.is_some() && opt.source != "sqlite" {
+        return Err(anyhow!("--query only applies to --source sqlite"));
+    }
+    if (opt.sheet.is_some() || !opt.columns.is_empty()) && opt.source != "xlsx" {
+        return Err(anyhow!("--sheet and --column only apply to --source xlsx"));
+    }
+    Ok(constructor(input, opt))
+}
+
+/// Sources whose input is a directory rather than a single file
+const DIRECTORY_SOURCES: &[&str] = &["jhu"];
+
+/// Files directly inside `dir`, skipping hidden files, sorted by name
+pub fn dir_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("Failed to read input dir {}", dir.display()))?
+    {
+        let entry = entry?;
+        let hidden = entry.file_name().to_string_lossy().starts_with('.');
+        if !hidden && entry.file_type()?.is_file() {
+            files.push(entry.path());
+        }
+    }
+    if files.is_empty() {
+        return Err(anyhow!("Input dir {} has no files", dir.display()));
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Expand any glob patterns among the input paths, and directories into the files
+/// they contain unless the source reads whole directories. Other paths are kept as is
+pub fn expand_inputs(paths: Vec<PathBuf>, opt: &SourceOpt) -> Result<Vec<PathBuf>> {
+    let mut expanded = Vec::new();
+    for path in paths {
+        let pattern = path.to_string_lossy();
+        if !pattern.contains(&['*', '?', '['][..]) {
+            if path.is_dir() && !DIRECTORY_SOURCES.contains(&opt.source.as_str()) {
+                expanded.append(&mut dir_files(&path)?);
+            } else {
+                expanded.push(path);
+            }
+            continue;
+        }
+
+        let mut matches = glob::glob(&pattern)
+            .with_context(|| format!("Invalid glob pattern {}", pattern))?
+            .collect::<Result<Vec<_>, _>>()?;
+        if matches.is_empty() {
+            return Err(anyhow!("No input files match {}", pattern));
+        }
+        matches.sort();
+        expanded.append(&mut matches);
+    }
+    Ok(expanded)
+}
+
+/// Chain the entries of all sources. Sources are opened in parallel, so inputs that
+/// are parsed up front are parsed concurrently. With more than one source, records that
+/// are identical to one already seen are dropped so overlapping dumps aren't double counted.
+/// Only a 64 bit hash of each record is kept for that, not the record
+pub fn merge(sources: &[Box<dyn DataSource>]) -> Result<Entries<'_>> {
+    let progress = Progress::new("parse", "inputs", Some(sources.len() as u64));
+    let entries = sources
+        .par_iter()
+        .map(|source| {
+            let entries = source.entries();
+            progress.inc();
+            entries
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .flatten();
+    if sources.len() == 1 {
+        return Ok(Box::new(entries));
+    }
+
+    let mut seen = HashSet::new();
+    Ok(Box::new(entries.filter(move |entry| match entry {
+        Ok(entry) => {
+            let mut hasher = DefaultHasher::new();
+            entry.hash(&mut hasher);
+            seen.insert(hasher.finish())
+        }
+        Err(_) => true,
+    })))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    const GZIP_MAGIC: &'static [u8] = &[0x1f, 0x8b];
+    const ZSTD_MAGIC: &'static [u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    fn from_magic(header: &[u8]) -> Self {
+        if header.starts_with(Self::GZIP_MAGIC) {
+            Compression::Gzip
+        } else if header.starts_with(Self::ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+}
+
+/// Whole input held in memory, either mapped or read
+pub enum InputData {
+    /// Private copy-on-write mapping, so in-place parsers can write to it
+    Mapped(memmap2::MmapMut),
+    Read(Vec<u8>),
+}
+
+impl Deref for InputData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            InputData::Mapped(map) => map,
+            InputData::Read(data) => data,
+        }
+    }
+}
+
+impl DerefMut for InputData {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        match self {
+            InputData::Mapped(map) => map,
+            InputData::Read(data) => data,
+        }
+    }
+}
+
+/// Load the whole input. Uncompressed files are memory-mapped when `mmap` is set,
+/// which avoids holding a second copy of multi-GB inputs while parsing.
+pub fn read_input(path: &Path, mmap: bool) -> Result<InputData> {
+    if mmap && path != Path::new("-") {
+        let file = fs::File::open(path)
+            .with_context(|| format!("Failed to open input file {}", path.display()))?;
+        // Safety: the mapping is private, so writes never reach the file. Truncating
+        // the file underneath us would fault, which is no worse than a failed read.
+        let map = unsafe { memmap2::MmapOptions::new().map_copy(&file) }
+            .with_context(|| format!("Failed to map input file {}", path.display()))?;
+        if Compression::from_magic(&map) == Compression::None {
+            return Ok(InputData::Mapped(map));
+        }
+    }
+
+    let mut data = Vec::new();
+    open_input(path)?
+        .read_to_end(&mut data)
+        .context("Failed to read raw covid data")?;
+    Ok(InputData::Read(data))
+}
+
+/// Open the input for reading, treating `-` as stdin.
+/// Gzip and zstd streams are detected by their magic bytes and decompressed transparently.
+pub fn open_input(path: &Path) -> Result<Box<dyn Read + Send>> {
+    let source: Box<dyn Read + Send> = if path == Path::new("-") {
+        Box::new(io::stdin())
+    } else {
+        let file = fs::File::open(path)
+            .with_context(|| format!("Failed to open input file {}", path.display()))?;
+        Box::new(file)
+    };
+
+    let mut source = BufReader::new(source);
+    let compression = Compression::from_magic(source.fill_buf()?);
+    Ok(match compression {
+        Compression::None => Box::new(source),
+        Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(source)),
+        Compression::Zstd => Box::new(zstd::Decoder::with_buffer(source)?),
+    })
+}
@@ -0,0 +1,71 @@
+//! New York Times `us-counties.csv` (https://github.com/nytimes/covid-19-data)
+//!
+//! Each row carries the cumulative `cases` and `deaths` for one county on one day,
+//! while Knowi emits a separate row per entry type. Rows are split into one
+//! `Confirmed` and one `Deaths` entry so both feeds group the same way.
+
+use super::{normalize_fips, open_input, DataSource, Entries, RawEntry};
+use anyhow::{Context, Result};
+use chrono::{NaiveDate, NaiveTime};
+use serde::Deserialize;
+use std::io::Read;
+use std::path::PathBuf;
+
+#[derive(Deserialize)]
+struct CountyRow {
+    date: NaiveDate,
+    county: String,
+    state: String,
+    fips: Option<String>,
+    cases: Option<i64>,
+    deaths: Option<i64>,
+}
+
+fn read_counties(reader: impl Read) -> Result<Vec<RawEntry>> {
+    let mut entries = Vec::new();
+    for (i, row) in csv::Reader::from_reader(reader)
+        .deserialize::<CountyRow>()
+        .enumerate()
+    {
+        let row = row.with_context(|| format!("Failed to parse NYT row {}", i + 1))?;
+        let millis = row
+            .date
+            .and_time(NaiveTime::MIN)
+            .and_utc()
+            .timestamp_millis();
+        let fips = row.fips.as_deref().and_then(normalize_fips);
+
+        for (entry_type, value) in &[("Confirmed", row.cases), ("Deaths", row.deaths)] {
+            if let Some(value) = value {
+                entries.push(RawEntry {
+                    date: millis,
+                    county: row.county.clone(),
+                    state: row.state.clone(),
+                    values: *value,
+                    entry_type: entry_type.to_string(),
+                    fips: fips.clone(),
+                    zcta: None,
+                    date_only: true,
+                });
+            }
+        }
+    }
+    Ok(entries)
+}
+
+pub struct NytSource {
+    input: PathBuf,
+}
+
+impl NytSource {
+    pub fn new(input: PathBuf) -> Self {
+        NytSource { input }
+    }
+}
+
+impl DataSource for NytSource {
+    fn entries(&self) -> Result<Entries<'_>> {
+        let entries = read_counties(open_input(&self.input)?)?;
+        Ok(Box::new(entries.into_iter().map(Ok)))
+    }
+}
@@ -0,0 +1,117 @@
+//! Parquet copies of the raw Knowi feed, as kept in the data lake
+//!
+//! Columns are matched by name with the same mapping as the JSON export
+//! (`Date`, `County`, `State`, `values`, `Type` and optionally `FIPS` and `ZCTA`).
+//! `Date` may be stored as epoch milliseconds or as a Parquet date/timestamp.
+
+use super::{normalize_fips, DataSource, Entries, RawEntry};
+use anyhow::{anyhow, Context, Result};
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::{Field, Row};
+use std::fs::File;
+use std::path::PathBuf;
+
+const MILLIS_PER_DAY: i64 = 24 * 60 * 60 * 1000;
+
+fn field_i64(name: &str, field: &Field) -> Result<i64> {
+    match field {
+        Field::Byte(v) => Ok(i64::from(*v)),
+        Field::Short(v) => Ok(i64::from(*v)),
+        Field::Int(v) => Ok(i64::from(*v)),
+        Field::Long(v) => Ok(*v),
+        Field::UInt(v) => Ok(i64::from(*v)),
+        Field::Double(v) if v.fract() == 0.0 => Ok(*v as i64),
+        _ => Err(anyhow!("Column {} is not an integer: {}", name, field)),
+    }
+}
+
+fn field_date(field: &Field) -> Result<i64> {
+    match field {
+        Field::Date(days) => Ok(i64::from(*days) * MILLIS_PER_DAY),
+        Field::TimestampMillis(millis) => Ok(*millis),
+        Field::TimestampMicros(micros) => Ok(micros / 1000),
+        _ => field_i64("Date", field),
+    }
+}
+
+fn field_string(name: &str, field: &Field) -> Result<String> {
+    match field {
+        Field::Str(v) => Ok(v.clone()),
+        Field::Null => Ok(String::new()),
+        _ => Err(anyhow!("Column {} is not a string: {}", name, field)),
+    }
+}
+
+fn entry_from_row(row: &Row) -> Result<RawEntry> {
+    let (mut date, mut values, mut entry_type) = (None, None, None);
+    let mut entry = RawEntry {
+        date: 0,
+        county: String::new(),
+        state: String::new(),
+        values: 0,
+        entry_type: String::new(),
+        fips: None,
+        zcta: None,
+        date_only: false,
+    };
+    for (name, field) in row.get_column_iter() {
+        match name.as_str() {
+            "Date" => {
+                date = Some(field_date(field)?);
+                entry.date_only = matches!(field, Field::Date(_));
+            }
+            "County" => entry.county = field_string(name, field)?,
+            "State" => entry.state = field_string(name, field)?,
+            "values" => values = Some(field_i64(name, field)?),
+            "Type" => entry_type = Some(field_string(name, field)?),
+            "FIPS" => {
+                entry.fips = match field {
+                    Field::Null => None,
+                    Field::Str(v) => normalize_fips(v),
+                    _ => normalize_fips(&field_i64(name, field)?.to_string()),
+                }
+            }
+            "ZCTA" => {
+                entry.zcta = match field {
+                    Field::Null => None,
+                    Field::Str(v) => Some(v.clone()),
+                    _ => Some(format!("{:05}", field_i64(name, field)?)),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    entry.date = date.ok_or_else(|| anyhow!("Missing column Date"))?;
+    entry.values = values.ok_or_else(|| anyhow!("Missing column values"))?;
+    entry.entry_type = entry_type.ok_or_else(|| anyhow!("Missing column Type"))?;
+    Ok(entry)
+}
+
+pub struct ParquetSource {
+    input: PathBuf,
+}
+
+impl ParquetSource {
+    pub fn new(input: PathBuf) -> Self {
+        ParquetSource { input }
+    }
+}
+
+impl DataSource for ParquetSource {
+    fn entries(&self) -> Result<Entries<'_>> {
+        let file = File::open(&self.input)
+            .with_context(|| format!("Failed to open input file {}", self.input.display()))?;
+        let reader = SerializedFileReader::new(file).context("Failed to read Parquet footer")?;
+        let rows = reader
+            .get_row_iter(None)
+            .context("Failed to read Parquet rows")?
+            .enumerate()
+            .map(|(i, row)| {
+                let row = row.with_context(|| format!("Failed to read Parquet row {}", i + 1))?;
+                entry_from_row(&row).with_context(|| format!("Bad Parquet row {}", i + 1))
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::new(rows.into_iter()))
+    }
+}
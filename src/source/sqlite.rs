@@ -0,0 +1,77 @@
+//! Entries pulled from a SQLite database with a user supplied `--query`
+//!
+//! The query's result columns are matched by name with the same mapping as the
+//! JSON export (`Date`, `County`, `State`, `values`, `Type` and optionally `FIPS` and `ZCTA`),
+//! so `SELECT ... AS "Date"` can adapt other schemas.
+
+use super::{normalize_fips, DataSource, Entries, RawEntry};
+use anyhow::{anyhow, Context, Result};
+use rusqlite::types::Value;
+use rusqlite::{Connection, OpenFlags, Row};
+use std::path::PathBuf;
+
+pub struct SqliteSource {
+    input: PathBuf,
+    query: Option<String>,
+}
+
+impl SqliteSource {
+    pub fn new(input: PathBuf, query: Option<String>) -> Self {
+        SqliteSource { input, query }
+    }
+}
+
+fn entry_from_row(row: &Row, has_fips: bool, has_zcta: bool) -> rusqlite::Result<RawEntry> {
+    let fips = if has_fips {
+        match row.get::<_, Value>("FIPS")? {
+            Value::Integer(code) => normalize_fips(&code.to_string()),
+            Value::Real(code) => normalize_fips(&code.to_string()),
+            Value::Text(code) => normalize_fips(&code),
+            _ => None,
+        }
+    } else {
+        None
+    };
+    let zcta = if has_zcta {
+        match row.get::<_, Value>("ZCTA")? {
+            Value::Integer(code) => Some(format!("{:05}", code)),
+            Value::Text(code) => Some(code),
+            _ => None,
+        }
+    } else {
+        None
+    };
+    Ok(RawEntry {
+        date: row.get("Date")?,
+        county: row.get::<_, Option<String>>("County")?.unwrap_or_default(),
+        state: row.get("State")?,
+        values: row.get("values")?,
+        entry_type: row.get("Type")?,
+        fips,
+        zcta,
+        date_only: false,
+    })
+}
+
+impl DataSource for SqliteSource {
+    fn entries(&self) -> Result<Entries<'_>> {
+        let query = self
+            .query
+            .as_deref()
+            .ok_or_else(|| anyhow!("--source sqlite needs a --query"))?;
+        let connection = Connection::open_with_flags(&self.input, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("Failed to open database {}", self.input.display()))?;
+        let mut statement = connection
+            .prepare(query)
+            .context("Failed to prepare query")?;
+        let has_fips = statement.column_names().contains(&"FIPS");
+        let has_zcta = statement.column_names().contains(&"ZCTA");
+        let entries = statement
+            .query_map([], |row| entry_from_row(row, has_fips, has_zcta))
+            .context("Failed to run query")?
+            .enumerate()
+            .map(|(i, entry)| entry.with_context(|| format!("Bad result row {}", i + 1)))
+            .collect::<Vec<_>>();
+        Ok(Box::new(entries.into_iter()))
+    }
+}
@@ -0,0 +1,177 @@
+//! Spreadsheets published by health departments (.xlsx, .xls, .ods)
+//!
+//! Every sheet has its own headers, so the column holding each entry field is
+//! configurable with `--column FIELD=HEADER`. Unmapped fields default to the
+//! Knowi column names.
+
+use super::{normalize_fips, DataSource, Entries, RawEntry};
+use anyhow::{anyhow, Context, Result};
+use calamine::{open_workbook_auto, Data, DataType, Reader};
+use chrono::{NaiveDate, NaiveTime};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Date,
+    County,
+    State,
+    Type,
+    Values,
+    Fips,
+    Zcta,
+}
+
+const FIELDS: &[(&str, Field, &str)] = &[
+    ("date", Field::Date, "Date"),
+    ("county", Field::County, "County"),
+    ("state", Field::State, "State"),
+    ("type", Field::Type, "Type"),
+    ("values", Field::Values, "values"),
+    ("fips", Field::Fips, "FIPS"),
+    ("zcta", Field::Zcta, "ZCTA"),
+];
+
+/// `FIELD=HEADER`, the sheet column to read an entry field from
+#[derive(Debug, Clone)]
+pub struct ColumnMapping {
+    field: Field,
+    header: String,
+}
+
+impl FromStr for ColumnMapping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (field, header) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected FIELD=HEADER, got {}", s))?;
+        let (_, field, _) = FIELDS
+            .iter()
+            .find(|(name, _, _)| *name == field)
+            .ok_or_else(|| {
+                anyhow!(
+                    "Unknown field {}, expected one of date, county, state, type, values, fips, zcta",
+                    field
+                )
+            })?;
+        Ok(ColumnMapping {
+            field: *field,
+            header: header.to_string(),
+        })
+    }
+}
+
+/// Spreadsheet dates may be real date cells or text in either common layout
+fn cell_millis(cell: &Data) -> Option<i64> {
+    let date = cell.as_date().or_else(|| {
+        let text = cell.get_string()?.trim();
+        NaiveDate::parse_from_str(text, "%Y-%m-%d")
+            .or_else(|_| NaiveDate::parse_from_str(text, "%m/%d/%Y"))
+            .ok()
+    })?;
+    Some(date.and_time(NaiveTime::MIN).and_utc().timestamp_millis())
+}
+
+fn cell_string(cell: &Data) -> String {
+    match cell {
+        Data::Empty => String::new(),
+        Data::Float(v) if v.fract() == 0.0 => (*v as i64).to_string(),
+        _ => cell.to_string().trim().to_string(),
+    }
+}
+
+pub struct XlsxSource {
+    input: PathBuf,
+    sheet: Option<String>,
+    columns: Vec<ColumnMapping>,
+}
+
+impl XlsxSource {
+    /// Reads the first sheet unless `sheet` is given
+    pub fn new(input: PathBuf, sheet: Option<String>, columns: Vec<ColumnMapping>) -> Self {
+        XlsxSource {
+            input,
+            sheet,
+            columns,
+        }
+    }
+
+    fn header(&self, field: Field) -> &str {
+        self.columns
+            .iter()
+            .rev()
+            .find(|column| column.field == field)
+            .map(|column| column.header.as_str())
+            .or_else(|| {
+                FIELDS
+                    .iter()
+                    .find(|(_, f, _)| *f == field)
+                    .map(|(_, _, header)| *header)
+            })
+            .unwrap()
+    }
+}
+
+impl DataSource for XlsxSource {
+    fn entries(&self) -> Result<Entries<'_>> {
+        let mut workbook = open_workbook_auto(&self.input)
+            .with_context(|| format!("Failed to open spreadsheet {}", self.input.display()))?;
+        let sheet = match &self.sheet {
+            Some(sheet) => sheet.clone(),
+            None => workbook
+                .sheet_names()
+                .first()
+                .cloned()
+                .ok_or_else(|| anyhow!("Spreadsheet has no sheets"))?,
+        };
+        let range = workbook
+            .worksheet_range(&sheet)
+            .with_context(|| format!("Failed to read sheet {}", sheet))?;
+
+        let mut rows = range.rows();
+        let headers = rows
+            .next()
+            .ok_or_else(|| anyhow!("Sheet {} is empty", sheet))?
+            .iter()
+            .map(cell_string)
+            .collect::<Vec<_>>();
+        let column = |field| {
+            let header = self.header(field);
+            headers
+                .iter()
+                .position(|h| h == header)
+                .ok_or_else(|| anyhow!("Sheet {} has no column {}", sheet, header))
+        };
+        let date = column(Field::Date)?;
+        let county = column(Field::County)?;
+        let state = column(Field::State)?;
+        let entry_type = column(Field::Type)?;
+        let values = column(Field::Values)?;
+        let fips = column(Field::Fips).ok();
+        let zcta = column(Field::Zcta).ok();
+
+        let mut entries = Vec::new();
+        // header is row 1
+        for (i, row) in rows.enumerate().map(|(i, row)| (i + 2, row)) {
+            if row.iter().all(|cell| cell.is_empty()) {
+                continue;
+            }
+            let cell = |index: usize| row.get(index).unwrap_or(&Data::Empty);
+            entries.push(Ok(RawEntry {
+                date: cell_millis(cell(date))
+                    .ok_or_else(|| anyhow!("Row {}: invalid date {}", i, cell(date)))?,
+                county: cell_string(cell(county)),
+                state: cell_string(cell(state)),
+                values: cell(values)
+                    .as_i64()
+                    .ok_or_else(|| anyhow!("Row {}: invalid value {}", i, cell(values)))?,
+                entry_type: cell_string(cell(entry_type)),
+                fips: fips.and_then(|index| normalize_fips(&cell_string(cell(index)))),
+                zcta: zcta.map(|index| cell_string(cell(index))),
+                date_only: true,
+            }));
+        }
+        Ok(Box::new(entries.into_iter()))
+    }
+}
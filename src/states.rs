@@ -0,0 +1,273 @@
+//! US state and territory names, and the regions they're grouped into
+
+use anyhow::anyhow;
+use std::str::FromStr;
+
+/// Postal abbreviation and full name, states first then DC and territories
+pub const STATES: &[(&str, &str)] = &[
+    ("AL", "Alabama"),
+    ("AK", "Alaska"),
+    ("AZ", "Arizona"),
+    ("AR", "Arkansas"),
+    ("CA", "California"),
+    ("CO", "Colorado"),
+    ("CT", "Connecticut"),
+    ("DE", "Delaware"),
+    ("FL", "Florida"),
+    ("GA", "Georgia"),
+    ("HI", "Hawaii"),
+    ("ID", "Idaho"),
+    ("IL", "Illinois"),
+    ("IN", "Indiana"),
+    ("IA", "Iowa"),
+    ("KS", "Kansas"),
+    ("KY", "Kentucky"),
+    ("LA", "Louisiana"),
+    ("ME", "Maine"),
+    ("MD", "Maryland"),
+    ("MA", "Massachusetts"),
+    ("MI", "Michigan"),
+    ("MN", "Minnesota"),
+    ("MS", "Mississippi"),
+    ("MO", "Missouri"),
+    ("MT", "Montana"),
+    ("NE", "Nebraska"),
+    ("NV", "Nevada"),
+    ("NH", "New Hampshire"),
+    ("NJ", "New Jersey"),
+    ("NM", "New Mexico"),
+    ("NY", "New York"),
+    ("NC", "North Carolina"),
+    ("ND", "North Dakota"),
+    ("OH", "Ohio"),
+    ("OK", "Oklahoma"),
+    ("OR", "Oregon"),
+    ("PA", "Pennsylvania"),
+    ("RI", "Rhode Island"),
+    ("SC", "South Carolina"),
+    ("SD", "South Dakota"),
+    ("TN", "Tennessee"),
+    ("TX", "Texas"),
+    ("UT", "Utah"),
+    ("VT", "Vermont"),
+    ("VA", "Virginia"),
+    ("WA", "Washington"),
+    ("WV", "West Virginia"),
+    ("WI", "Wisconsin"),
+    ("WY", "Wyoming"),
+    ("DC", "District of Columbia"),
+    ("PR", "Puerto Rico"),
+    ("GU", "Guam"),
+    ("VI", "Virgin Islands"),
+    ("MP", "Northern Mariana Islands"),
+    ("AS", "American Samoa"),
+];
+
+/// Postal abbreviation for a full name
+pub fn abbreviation(name: &str) -> Option<&'static str> {
+    STATES
+        .iter()
+        .find(|(_, n)| n.eq_ignore_ascii_case(name))
+        .map(|(abbrev, _)| *abbrev)
+}
+
+/// FIPS code by postal abbreviation
+const FIPS: &[(&str, &str)] = &[
+    ("AL", "01"),
+    ("AK", "02"),
+    ("AZ", "04"),
+    ("AR", "05"),
+    ("CA", "06"),
+    ("CO", "08"),
+    ("CT", "09"),
+    ("DE", "10"),
+    ("DC", "11"),
+    ("FL", "12"),
+    ("GA", "13"),
+    ("HI", "15"),
+    ("ID", "16"),
+    ("IL", "17"),
+    ("IN", "18"),
+    ("IA", "19"),
+    ("KS", "20"),
+    ("KY", "21"),
+    ("LA", "22"),
+    ("ME", "23"),
+    ("MD", "24"),
+    ("MA", "25"),
+    ("MI", "26"),
+    ("MN", "27"),
+    ("MS", "28"),
+    ("MO", "29"),
+    ("MT", "30"),
+    ("NE", "31"),
+    ("NV", "32"),
+    ("NH", "33"),
+    ("NJ", "34"),
+    ("NM", "35"),
+    ("NY", "36"),
+    ("NC", "37"),
+    ("ND", "38"),
+    ("OH", "39"),
+    ("OK", "40"),
+    ("OR", "41"),
+    ("PA", "42"),
+    ("RI", "44"),
+    ("SC", "45"),
+    ("SD", "46"),
+    ("TN", "47"),
+    ("TX", "48"),
+    ("UT", "49"),
+    ("VT", "50"),
+    ("VA", "51"),
+    ("WA", "53"),
+    ("WV", "54"),
+    ("WI", "55"),
+    ("WY", "56"),
+    ("AS", "60"),
+    ("GU", "66"),
+    ("MP", "69"),
+    ("PR", "72"),
+    ("VI", "78"),
+];
+
+/// Two digit FIPS code for a full name
+pub fn fips(name: &str) -> Option<&'static str> {
+    let abbrev = abbreviation(name)?;
+    FIPS.iter()
+        .find(|(a, _)| *a == abbrev)
+        .map(|(_, code)| *code)
+}
+
+/// Full name for a postal abbreviation
+pub fn full_name(abbrev: &str) -> Option<&'static str> {
+    STATES
+        .iter()
+        .find(|(a, _)| a.eq_ignore_ascii_case(abbrev))
+        .map(|(_, name)| *name)
+}
+
+/// Other names the feeds give territories, with their abbreviations
+const ALIASES: &[(&str, &str)] = &[
+    ("U.S. Virgin Islands", "VI"),
+    ("US Virgin Islands", "VI"),
+    ("United States Virgin Islands", "VI"),
+    ("Commonwealth of the Northern Mariana Islands", "MP"),
+    ("Northern Marianas", "MP"),
+    ("Commonwealth of Puerto Rico", "PR"),
+    ("Washington, D.C.", "DC"),
+];
+
+/// Full name for a state or territory given by abbreviation, name or another name it
+/// goes by, so every input spells it the same
+pub fn normalize(state: &str) -> Option<&'static str> {
+    let state = state.trim();
+    full_name(state)
+        .or_else(|| abbreviation(state).and_then(full_name))
+        .or_else(|| {
+            ALIASES
+                .iter()
+                .find(|(alias, _)| alias.eq_ignore_ascii_case(state))
+                .and_then(|(_, abbrev)| full_name(abbrev))
+        })
+}
+
+/// States of each HHS region, by number
+const HHS_REGIONS: &[(u8, &[&str])] = &[
+    (1, &["CT", "ME", "MA", "NH", "RI", "VT"]),
+    (2, &["NJ", "NY", "PR", "VI"]),
+    (3, &["DE", "DC", "MD", "PA", "VA", "WV"]),
+    (4, &["AL", "FL", "GA", "KY", "MS", "NC", "SC", "TN"]),
+    (5, &["IL", "IN", "MI", "MN", "OH", "WI"]),
+    (6, &["AR", "LA", "NM", "OK", "TX"]),
+    (7, &["IA", "KS", "MO", "NE"]),
+    (8, &["CO", "MT", "ND", "SD", "UT", "WY"]),
+    (9, &["AZ", "CA", "HI", "NV", "AS", "GU", "MP"]),
+    (10, &["AK", "ID", "OR", "WA"]),
+];
+
+/// Census region, division and the division's states. Territories are in neither
+const CENSUS_DIVISIONS: &[(&str, &str, &[&str])] = &[
+    (
+        "Northeast",
+        "New England",
+        &["CT", "ME", "MA", "NH", "RI", "VT"],
+    ),
+    ("Northeast", "Middle Atlantic", &["NJ", "NY", "PA"]),
+    (
+        "Midwest",
+        "East North Central",
+        &["IL", "IN", "MI", "OH", "WI"],
+    ),
+    (
+        "Midwest",
+        "West North Central",
+        &["IA", "KS", "MN", "MO", "NE", "ND", "SD"],
+    ),
+    (
+        "South",
+        "South Atlantic",
+        &["DE", "DC", "FL", "GA", "MD", "NC", "SC", "VA", "WV"],
+    ),
+    ("South", "East South Central", &["AL", "KY", "MS", "TN"]),
+    ("South", "West South Central", &["AR", "LA", "OK", "TX"]),
+    (
+        "West",
+        "Mountain",
+        &["AZ", "CO", "ID", "MT", "NV", "NM", "UT", "WY"],
+    ),
+    ("West", "Pacific", &["AK", "CA", "HI", "OR", "WA"]),
+];
+
+/// Groupings of states `--hierarchy` can add nodes for
+#[derive(Debug, Clone, Copy)]
+pub enum Grouping {
+    HhsRegion,
+    CensusRegion,
+    CensusDivision,
+}
+
+impl FromStr for Grouping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hhs-region" => Ok(Grouping::HhsRegion),
+            "census-region" => Ok(Grouping::CensusRegion),
+            "census-division" => Ok(Grouping::CensusDivision),
+            _ => Err(anyhow!(
+                "Unknown grouping {}, expected hhs-region, census-region or census-division",
+                s
+            )),
+        }
+    }
+}
+
+impl Grouping {
+    /// Level of the grouping's nodes
+    pub fn level(self) -> &'static str {
+        match self {
+            Grouping::HhsRegion => "hhs_region",
+            Grouping::CensusRegion => "census_region",
+            Grouping::CensusDivision => "census_division",
+        }
+    }
+
+    /// Name of the group a state, by full name, is in
+    pub fn group(self, state: &str) -> Option<String> {
+        let abbrev = abbreviation(state)?;
+        match self {
+            Grouping::HhsRegion => HHS_REGIONS
+                .iter()
+                .find(|(_, states)| states.contains(&abbrev))
+                .map(|(region, _)| format!("HHS Region {}", region)),
+            Grouping::CensusRegion | Grouping::CensusDivision => CENSUS_DIVISIONS
+                .iter()
+                .find(|(_, _, states)| states.contains(&abbrev))
+                .map(|&(region, division, _)| match self {
+                    Grouping::CensusRegion => region.to_string(),
+                    _ => division.to_string(),
+                }),
+        }
+    }
+}
@@ -0,0 +1,296 @@
+//! Grouping entries by date as they're parsed, for `build --stream`: each date is handed on
+//! as soon as the inputs are past it, so only the dates in progress are ever in memory
+//!
+//! The inputs are parsed on one thread, grouped on another and the dates handed to the
+//! caller on a third, with bounded channels between them. A date is done once an entry
+//! reported after it, and after the longest `--lag` on top, comes in, so the inputs have to
+//! be in date order, like a file per day. An entry for a date already handed on fails the
+//! run
+
+use crate::dedup::Dedup;
+use crate::error::{CovidDataError, Result};
+use crate::filter::EntryFilter;
+use crate::group::{self, Grouping, UnmappedTypes};
+use crate::lag::Lags;
+use crate::source::{DataSource, RawEntry};
+use crate::timezone::Timezone;
+use crate::GroupedEntries;
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread;
+
+/// Entries sent from the parser to the grouping at once
+const BATCH: usize = 4096;
+
+/// Batches of entries the parser gets ahead of the grouping by
+const BATCHES_IN_FLIGHT: usize = 8;
+
+/// Finished dates the grouping gets ahead of the caller by
+const DATES_IN_FLIGHT: usize = 2;
+
+/// Group the entries of `sources` by date like `group_by_date`, calling `on_date` with each
+/// date's entries once they're all in, in date order. Stops at the first error, of the
+/// inputs or `on_date`
+pub fn by_date<F>(
+    sources: &[Box<dyn DataSource>],
+    filter: &EntryFilter,
+    dedup: Dedup,
+    timezone: Timezone,
+    lags: Option<&Lags>,
+    on_date: F,
+) -> Result<UnmappedTypes>
+where
+    F: FnMut(GroupedEntries) -> anyhow::Result<()> + Send,
+{
+    let (entries, batches) = mpsc::sync_channel(BATCHES_IN_FLIGHT);
+    let (dates, finished) = mpsc::sync_channel(DATES_IN_FLIGHT);
+    thread::scope(|scope| {
+        scope.spawn(move || parse(sources, entries));
+        let handed_on = scope.spawn(move || finished.into_iter().try_for_each(on_date));
+        let grouper = Grouper {
+            filter,
+            dedup,
+            timezone,
+            lags,
+            dedup_inputs: sources.len() > 1,
+        };
+        let grouped = grouper.group(batches, dates);
+        // The grouping stops when `on_date` does, so its error is the one to report
+        handed_on.join().expect("on_date panicked")?;
+        grouped
+    })
+}
+
+/// Send the entries of each source in turn, until they're done or nobody's listening
+fn parse(sources: &[Box<dyn DataSource>], batches: SyncSender<Vec<anyhow::Result<RawEntry>>>) {
+    let mut batch = Vec::with_capacity(BATCH);
+    for source in sources {
+        let entries = match source.entries() {
+            Ok(entries) => entries,
+            Err(e) => {
+                batch.push(Err(e));
+                break;
+            }
+        };
+        for entry in entries {
+            batch.push(entry);
+            if batch.len() == BATCH {
+                let full = std::mem::replace(&mut batch, Vec::with_capacity(BATCH));
+                if batches.send(full).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+    let _ = batches.send(batch);
+}
+
+struct Grouper<'a> {
+    filter: &'a EntryFilter,
+    dedup: Dedup,
+    timezone: Timezone,
+    lags: Option<&'a Lags>,
+    /// Drop records identical to one already seen, as `parse_entries` does across inputs
+    dedup_inputs: bool,
+}
+
+impl Grouper<'_> {
+    fn group(
+        &self,
+        batches: Receiver<Vec<anyhow::Result<RawEntry>>>,
+        dates: SyncSender<GroupedEntries>,
+    ) -> Result<UnmappedTypes> {
+        let max_lag = self.lags.map_or(Duration::zero(), Lags::max);
+        let mut grouping = Grouping::default();
+        let mut seen = HashMap::<DateTime<Utc>, HashSet<RawEntry>>::new();
+        // The latest date reported so far, and the latest date handed on
+        let mut reported = None;
+        let mut done = None;
+        let mut position = 0;
+        let hand_on = |grouping: &mut Grouping, date| {
+            dates
+                .send(grouping.take_date(date))
+                .map_err(|_| CovidDataError::from(anyhow!("Stopped handing on dates")))
+        };
+        for entry in batches.into_iter().flatten() {
+            let mut entry = entry?;
+            let raw = self.dedup_inputs.then(|| entry.clone());
+            let date = group::entry_date(&mut entry, self.timezone, self.lags)?;
+            let lag = self.lags.and_then(|lags| lags.get(&entry.state));
+            let report_date = date + lag.unwrap_or_else(Duration::zero);
+            if self
+                .filter
+                .keeps(date.date_naive(), &entry.state, &entry.county)
+            {
+                if done.is_some_and(|done| date <= done) {
+                    return Err(anyhow!(
+                        "An entry for {} came after its graph was written, --stream needs \
+                         the inputs in date order",
+                        date.date_naive()
+                    )
+                    .into());
+                }
+                let repeated = raw.is_some_and(|raw| !seen.entry(date).or_default().insert(raw));
+                if !repeated {
+                    grouping.add(self.filter, self.dedup, position, entry, date)?;
+                    position += 1;
+                }
+            }
+
+            if reported.is_some_and(|reported| report_date <= reported) {
+                continue;
+            }
+            reported = Some(report_date);
+            // Entries of the most lagged state can still land after this
+            let cutoff = report_date - max_lag;
+            let mut finished = grouping
+                .grouped
+                .keys()
+                .copied()
+                .filter(|&date| date < cutoff)
+                .collect::<Vec<_>>();
+            finished.sort();
+            for date in finished {
+                seen.remove(&date);
+                done = Some(date);
+                hand_on(&mut grouping, date)?;
+            }
+        }
+
+        let mut rest = grouping.grouped.keys().copied().collect::<Vec<_>>();
+        rest.sort();
+        for date in rest {
+            hand_on(&mut grouping, date)?;
+        }
+        Ok(grouping.unmapped)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::Unassigned;
+    use crate::source::Entries;
+    use chrono::{Datelike, NaiveDate};
+
+    struct Fixed(Vec<RawEntry>);
+
+    impl DataSource for Fixed {
+        fn entries(&self) -> anyhow::Result<Entries<'_>> {
+            Ok(Box::new(self.0.clone().into_iter().map(Ok)))
+        }
+    }
+
+    fn confirmed(day: u32, state: &str, county: &str, values: i64) -> RawEntry {
+        let date = NaiveDate::from_ymd_opt(2020, 4, day).unwrap();
+        RawEntry {
+            date: date
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+                .and_utc()
+                .timestamp_millis(),
+            county: county.to_string(),
+            state: state.to_string(),
+            values,
+            entry_type: "Confirmed".to_string(),
+            fips: None,
+            zcta: None,
+            date_only: true,
+        }
+    }
+
+    /// The dates handed on, in order, with each county's confirmed cases
+    fn stream(sources: Vec<Vec<RawEntry>>, lags: Option<&str>) -> Result<Vec<(u32, Vec<i64>)>> {
+        let sources = sources
+            .into_iter()
+            .map(|entries| Box::new(Fixed(entries)) as Box<dyn DataSource>)
+            .collect::<Vec<_>>();
+        let filter = EntryFilter::new(None, None, &[], &[], Unassigned::Keep, false)?;
+        let lags = lags.map(|lags| lags.parse::<Lags>().unwrap());
+        let mut dates = Vec::new();
+        by_date(
+            &sources,
+            &filter,
+            Dedup::Sum,
+            Timezone::UTC,
+            lags.as_ref(),
+            |grouped| {
+                for (date, entries) in grouped {
+                    let mut keys = entries.keys().collect::<Vec<_>>();
+                    keys.sort();
+                    let values = keys
+                        .into_iter()
+                        .map(|key| entries[key].metrics["confirmed"].as_i64())
+                        .collect();
+                    dates.push((date.day(), values));
+                }
+                Ok(())
+            },
+        )?;
+        Ok(dates)
+    }
+
+    #[test]
+    fn dates_are_handed_on_in_order() {
+        let entries = vec![
+            confirmed(1, "NY", "Kings", 1),
+            confirmed(1, "NY", "Queens", 2),
+            confirmed(2, "NY", "Kings", 3),
+            confirmed(3, "NY", "Kings", 4),
+        ];
+        let dates = stream(vec![entries], None).unwrap();
+        assert_eq!(dates, vec![(1, vec![1, 2]), (2, vec![3]), (3, vec![4])]);
+    }
+
+    #[test]
+    fn entries_after_their_date_fail() {
+        let entries = vec![
+            confirmed(1, "NY", "Kings", 1),
+            confirmed(2, "NY", "Kings", 2),
+            confirmed(1, "NY", "Queens", 3),
+        ];
+        let err = stream(vec![entries], None).unwrap_err();
+        assert!(err.to_string().contains("needs the inputs in date order"));
+    }
+
+    #[test]
+    fn lags_hold_dates_back() {
+        // Florida's entry reported on the 3rd is for the 1st, which waits for it
+        let entries = vec![
+            confirmed(1, "NY", "Kings", 1),
+            confirmed(2, "NY", "Kings", 2),
+            confirmed(3, "NY", "Kings", 3),
+            confirmed(3, "FL", "Dade", 4),
+            confirmed(4, "NY", "Kings", 5),
+        ];
+        let dates = stream(vec![entries.clone()], Some("FL=2")).unwrap();
+        assert_eq!(
+            dates,
+            vec![(1, vec![4, 1]), (2, vec![2]), (3, vec![3]), (4, vec![5])]
+        );
+        // Without it the entry stays on the day it was reported
+        assert_eq!(stream(vec![entries], None).unwrap()[2], (3, vec![4, 3]));
+    }
+
+    #[test]
+    fn records_repeated_across_inputs_count_once() {
+        let first = vec![
+            confirmed(1, "NY", "Kings", 5),
+            confirmed(2, "NY", "Kings", 6),
+        ];
+        let second = vec![
+            confirmed(2, "NY", "Kings", 6),
+            confirmed(3, "NY", "Kings", 7),
+        ];
+        let dates = stream(vec![first, second], None).unwrap();
+        assert_eq!(dates, vec![(1, vec![5]), (2, vec![6]), (3, vec![7])]);
+        // Within one input they're summed, as the dedup policy says
+        let repeated = vec![
+            confirmed(1, "NY", "Kings", 5),
+            confirmed(1, "NY", "Kings", 5),
+        ];
+        assert_eq!(stream(vec![repeated], None).unwrap(), vec![(1, vec![10])]);
+    }
+}
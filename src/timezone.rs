@@ -0,0 +1,226 @@
+//! `--timezone`: the zone an entry's timestamp is turned into a date in
+//!
+//! Zones are UTC, a fixed offset like `-05:00`, or one of the IANA zones of the US states
+//! and territories. Those follow the US daylight saving rules in force since 2007, which
+//! cover every date the feeds have.
+
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveTime, Utc, Weekday};
+use std::str::FromStr;
+
+/// IANA name, standard offset from UTC in hours and whether daylight saving is observed
+const ZONES: &[(&str, i64, bool)] = &[
+    ("America/New_York", -5, true),
+    ("America/Detroit", -5, true),
+    ("America/Indiana/Indianapolis", -5, true),
+    ("America/Kentucky/Louisville", -5, true),
+    ("America/Chicago", -6, true),
+    ("America/Denver", -7, true),
+    ("America/Boise", -7, true),
+    ("America/Phoenix", -7, false),
+    ("America/Los_Angeles", -8, true),
+    ("America/Anchorage", -9, true),
+    ("Pacific/Honolulu", -10, false),
+    ("America/Puerto_Rico", -4, false),
+    ("America/St_Thomas", -4, false),
+    ("Pacific/Guam", 10, false),
+    ("Pacific/Saipan", 10, false),
+    ("Pacific/Pago_Pago", -11, false),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timezone {
+    /// Offset from UTC outside daylight saving
+    standard: Duration,
+    dst: bool,
+}
+
+impl FromStr for Timezone {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("utc") {
+            return Ok(Timezone::UTC);
+        }
+        if let Some(&(_, hours, dst)) = ZONES.iter().find(|(name, _, _)| name == &s) {
+            return Ok(Timezone {
+                standard: Duration::hours(hours),
+                dst,
+            });
+        }
+        let offset = s
+            .strip_prefix('+')
+            .map(|rest| (1, rest))
+            .or_else(|| s.strip_prefix('-').map(|rest| (-1, rest)))
+            .and_then(|(sign, rest)| {
+                let (hours, minutes) = rest.split_once(':').unwrap_or((rest, "0"));
+                let (hours, minutes) = (hours.parse::<i64>().ok()?, minutes.parse::<i64>().ok()?);
+                (hours <= 14 && minutes < 60).then(|| sign * (hours * 60 + minutes))
+            });
+        match offset {
+            Some(minutes) => Ok(Timezone {
+                standard: Duration::minutes(minutes),
+                dst: false,
+            }),
+            None => Err(anyhow!(
+                "Unknown timezone {}, expected UTC, an offset like -05:00 or a US zone like \
+                 America/New_York",
+                s
+            )),
+        }
+    }
+}
+
+/// The `n`th `weekday` of a month, counting from 1
+fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: u8) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, n)
+        .expect("every month has a first and second Sunday")
+}
+
+impl Timezone {
+    pub const UTC: Timezone = Timezone {
+        standard: Duration::zero(),
+        dst: false,
+    };
+
+    /// Offset from UTC at an instant
+    fn offset(self, instant: DateTime<Utc>) -> Duration {
+        if !self.dst {
+            return self.standard;
+        }
+        // Daylight saving runs from 2am standard time on the second Sunday in March to
+        // 2am daylight time on the first Sunday in November
+        let year = instant.year();
+        let two_am = NaiveTime::from_hms_opt(2, 0, 0).expect("2am is a time");
+        let start = nth_weekday(year, 3, Weekday::Sun, 2)
+            .and_time(two_am)
+            .and_utc()
+            - self.standard;
+        let end = nth_weekday(year, 11, Weekday::Sun, 1)
+            .and_time(two_am)
+            .and_utc()
+            - self.standard
+            - Duration::hours(1);
+        if instant >= start && instant < end {
+            self.standard + Duration::hours(1)
+        } else {
+            self.standard
+        }
+    }
+
+    /// The date an instant falls on in this zone
+    pub fn date(self, instant: DateTime<Utc>) -> NaiveDate {
+        (instant + self.offset(instant)).date_naive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn offset(zone: &str, instant: &str) -> i64 {
+        zone.parse::<Timezone>()
+            .unwrap()
+            .offset(at(instant))
+            .num_hours()
+    }
+
+    fn date(zone: &str, instant: &str) -> String {
+        zone.parse::<Timezone>()
+            .unwrap()
+            .date(at(instant))
+            .to_string()
+    }
+
+    #[test]
+    fn new_york_march_transition() {
+        // 2am EST on 2020-03-08 is 07:00 UTC
+        assert_eq!(offset("America/New_York", "2020-03-08T06:59:59Z"), -5);
+        assert_eq!(offset("America/New_York", "2020-03-08T07:00:00Z"), -4);
+        assert_eq!(
+            date("America/New_York", "2020-03-08T04:30:00Z"),
+            "2020-03-07"
+        );
+        assert_eq!(
+            date("America/New_York", "2020-03-09T03:30:00Z"),
+            "2020-03-08"
+        );
+        assert_eq!(
+            date("America/New_York", "2020-03-09T04:30:00Z"),
+            "2020-03-09"
+        );
+    }
+
+    #[test]
+    fn new_york_november_transition() {
+        // 2am EDT on 2020-11-01 is 06:00 UTC
+        assert_eq!(offset("America/New_York", "2020-11-01T05:59:59Z"), -4);
+        assert_eq!(offset("America/New_York", "2020-11-01T06:00:00Z"), -5);
+        assert_eq!(
+            date("America/New_York", "2020-11-01T03:30:00Z"),
+            "2020-10-31"
+        );
+        assert_eq!(
+            date("America/New_York", "2020-11-01T04:30:00Z"),
+            "2020-11-01"
+        );
+        assert_eq!(
+            date("America/New_York", "2020-11-02T04:30:00Z"),
+            "2020-11-01"
+        );
+    }
+
+    #[test]
+    fn late_evening_reports_stay_on_their_day() {
+        // Midnight UTC is 8pm the day before in New York in summer
+        assert_eq!(
+            date("America/New_York", "2020-07-01T00:00:00Z"),
+            "2020-06-30"
+        );
+        assert_eq!(
+            date("America/Los_Angeles", "2020-12-01T07:59:00Z"),
+            "2020-11-30"
+        );
+    }
+
+    #[test]
+    fn phoenix_has_no_daylight_saving() {
+        for instant in [
+            "2020-01-15T12:00:00Z",
+            "2020-03-08T12:00:00Z",
+            "2020-07-01T12:00:00Z",
+            "2020-11-01T12:00:00Z",
+        ] {
+            assert_eq!(offset("America/Phoenix", instant), -7);
+        }
+        assert_eq!(
+            date("America/Phoenix", "2020-07-01T06:30:00Z"),
+            "2020-06-30"
+        );
+        assert_eq!(
+            date("America/Phoenix", "2020-07-01T07:00:00Z"),
+            "2020-07-01"
+        );
+    }
+
+    #[test]
+    fn fixed_offsets_and_utc() {
+        assert_eq!(date("UTC", "2020-07-01T00:00:00Z"), "2020-07-01");
+        assert_eq!(date("utc", "2020-07-01T23:59:00Z"), "2020-07-01");
+        assert_eq!(date("-05:00", "2020-07-01T04:59:00Z"), "2020-06-30");
+        assert_eq!(date("+05:30", "2020-06-30T18:30:00Z"), "2020-07-01");
+        assert_eq!(date("+10", "2020-06-30T13:59:00Z"), "2020-06-30");
+        assert_eq!(offset("-05:00", "2020-07-01T00:00:00Z"), -5);
+    }
+
+    #[test]
+    fn unknown_zones() {
+        for zone in ["Europe/Paris", "-15:00", "+05:60", "5", ""] {
+            assert!(zone.parse::<Timezone>().is_err(), "{}", zone);
+        }
+    }
+}
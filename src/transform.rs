@@ -0,0 +1,143 @@
+//! Stages run over the built graphs, chained in a `Pipeline`
+//!
+//! The corrections, derived metrics and pruning the CLI's flags add are the `Stage`s.
+//! Anything else implementing `Transform` can run among them.
+
+use crate::alert::{self, Alert};
+use crate::error::Result;
+use crate::expr::{self, MetricDef};
+use crate::graph::{self, Graph};
+use crate::join::excess::{self, Excess};
+use crate::log;
+use crate::metrics::{self, GenerationInterval, Monotonic, NegativeDeltas, Outliers};
+use tracing::subscriber::NoSubscriber;
+
+pub trait Transform: Send + Sync {
+    /// Logged as the stage's span
+    fn name(&self) -> &str;
+
+    /// Graphs in, graphs out: a stage can drop graphs and nodes as well as change them.
+    /// Counts worth knowing go in `tracing` events, which land in the stage's span
+    fn apply(&self, graphs: &mut Vec<Graph>) -> Result<()>;
+}
+
+/// The built-in stages, as the flag of the same name runs them
+#[derive(Debug, Clone)]
+pub enum Stage {
+    Monotonic(Monotonic),
+    Outliers {
+        policy: Outliers,
+        factor: f64,
+    },
+    Deltas(NegativeDeltas),
+    PerCapita,
+    Cfr {
+        min_cases: i64,
+    },
+    DoublingTime,
+    Rt {
+        window: i64,
+        interval: GenerationInterval,
+    },
+    Active {
+        recovery_days: i64,
+    },
+    ExcessDeaths(Excess),
+    PctChange(Vec<i64>),
+    Waves,
+    /// Computed in order, so a definition can use earlier ones
+    Custom(Vec<MetricDef>),
+    Alerts(Vec<Alert>),
+    /// Keep the `k` counties with the highest `metric`
+    KeepTop {
+        k: usize,
+        metric: String,
+    },
+}
+
+impl Transform for Stage {
+    fn name(&self) -> &str {
+        match self {
+            Stage::Monotonic(_) => "enforce monotonic",
+            Stage::Outliers { .. } => "smooth outliers",
+            Stage::Deltas(_) => "add deltas",
+            Stage::PerCapita => "add per capita",
+            Stage::Cfr { .. } => "add cfr",
+            Stage::DoublingTime => "add doubling time",
+            Stage::Rt { .. } => "add rt",
+            Stage::Active { .. } => "add active",
+            Stage::ExcessDeaths(_) => "add excess deaths",
+            Stage::PctChange(_) => "add pct change",
+            Stage::Waves => "detect waves",
+            Stage::Custom(_) => "add custom metrics",
+            Stage::Alerts(_) => "check alerts",
+            Stage::KeepTop { .. } => "keep top",
+        }
+    }
+
+    fn apply(&self, graphs: &mut Vec<Graph>) -> Result<()> {
+        match self {
+            Stage::Monotonic(policy) => metrics::enforce_monotonic(graphs, *policy)?,
+            Stage::Outliers { policy, factor } => {
+                metrics::smooth_outliers(graphs, *policy, *factor)?
+            }
+            Stage::Deltas(negative) => metrics::add_deltas(graphs, *negative),
+            Stage::PerCapita => metrics::add_per_capita(graphs),
+            Stage::Cfr { min_cases } => metrics::add_cfr(graphs, *min_cases),
+            Stage::DoublingTime => metrics::add_doubling_days(graphs)?,
+            Stage::Rt { window, interval } => metrics::add_rt(graphs, *window, *interval)?,
+            Stage::Active { recovery_days } => metrics::add_active(graphs, *recovery_days)?,
+            Stage::ExcessDeaths(deaths) => {
+                tracing::info!(states = deaths.len());
+                excess::add(graphs, deaths)?;
+            }
+            Stage::PctChange(windows) => metrics::add_pct_change(graphs, windows)?,
+            Stage::Waves => metrics::add_waves(graphs)?,
+            Stage::Custom(defs) => expr::add_metrics(graphs, defs),
+            // Out of the events, which skip their fields when nothing is listening
+            Stage::Alerts(alerts) => {
+                let nodes = alert::annotate(graphs, alerts);
+                tracing::info!(nodes);
+            }
+            Stage::KeepTop { k, metric } => {
+                let removed = graph::keep_top(graphs, *k, metric);
+                tracing::info!(removed);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Transforms run one after the other, each in a stage of its own
+#[derive(Default)]
+pub struct Pipeline {
+    transforms: Vec<Box<dyn Transform>>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, transform: impl Transform + 'static) -> &mut Self {
+        self.transforms.push(Box::new(transform));
+        self
+    }
+
+    pub fn run(&self, graphs: &mut Vec<Graph>) -> Result<()> {
+        for transform in &self.transforms {
+            log::lib_stage(transform.name(), || transform.apply(graphs))?;
+        }
+        Ok(())
+    }
+
+    /// Run the transforms without logging them, for `--stream` running them a date at a
+    /// time, where a stage per date would drown the log
+    pub fn run_quietly(&self, graphs: &mut Vec<Graph>) -> Result<()> {
+        tracing::subscriber::with_default(NoSubscriber::default(), || {
+            self.transforms
+                .iter()
+                .try_for_each(|transform| transform.apply(graphs))
+        })
+    }
+}
@@ -0,0 +1,132 @@
+//! `validate` subcommand: sanity checks on the raw input, without building graphs
+
+use crate::source::{self, Entity, RawEntry, SourceOpt};
+use crate::{log, states};
+use anyhow::Result;
+use chrono::Utc;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+pub struct ValidateOpt {
+    /// Input files to check, same as for building graphs
+    #[structopt(parse(from_os_str), required = true, min_values = 1)]
+    inputs: Vec<PathBuf>,
+
+    #[structopt(flatten)]
+    source: SourceOpt,
+}
+
+#[derive(Serialize)]
+struct Sample {
+    date: i64,
+    state: String,
+    county: String,
+    entry_type: String,
+    values: i64,
+}
+
+impl From<&RawEntry> for Sample {
+    fn from(entry: &RawEntry) -> Self {
+        Sample {
+            date: entry.date,
+            state: entry.state.clone(),
+            county: entry.county.clone(),
+            entry_type: entry.entry_type.clone(),
+            values: entry.values,
+        }
+    }
+}
+
+#[derive(Serialize, Default)]
+struct Issue {
+    count: usize,
+    samples: Vec<Sample>,
+}
+
+impl Issue {
+    const MAX_SAMPLES: usize = 10;
+
+    fn add(&mut self, entry: &RawEntry) {
+        self.count += 1;
+        if self.samples.len() < Self::MAX_SAMPLES {
+            self.samples.push(entry.into());
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    entries: usize,
+    valid: bool,
+    issues: BTreeMap<&'static str, Issue>,
+}
+
+impl Report {
+    /// No problems were found
+    pub fn is_valid(&self) -> bool {
+        self.valid
+    }
+}
+
+/// Print a JSON report of the problems found and return it
+pub fn run(opt: ValidateOpt) -> Result<Report> {
+    let ValidateOpt { inputs, source } = opt;
+    let sources = source::expand_inputs(inputs, &source)?
+        .into_iter()
+        .map(|input| source::create(input, &source))
+        .collect::<Result<Vec<_>>>()?;
+    let expect_county = matches!(source.entity, Entity::County);
+
+    let report = log::stage("validate", || {
+        let now = Utc::now().timestamp_millis();
+        let mut entries = 0;
+        let mut seen = HashSet::new();
+        let mut issues = BTreeMap::<&'static str, Issue>::new();
+
+        for source in &sources {
+            for entry in source.entries()? {
+                let entry = entry?;
+                entries += 1;
+
+                if entry.values < 0 {
+                    issues.entry("negative_values").or_default().add(&entry);
+                }
+                if entry.date > now {
+                    issues.entry("future_dates").or_default().add(&entry);
+                }
+                if entry.state.trim().is_empty() {
+                    issues.entry("missing_state").or_default().add(&entry);
+                }
+                if expect_county && entry.county.trim().is_empty() {
+                    issues.entry("missing_county").or_default().add(&entry);
+                }
+                // Copies spelling the state differently are the same record
+                let state = states::normalize(&entry.state).unwrap_or(entry.state.trim());
+                let key = (
+                    entry.date,
+                    state.to_string(),
+                    entry.county.clone(),
+                    entry.zcta.clone(),
+                    entry.entry_type.clone(),
+                );
+                if !seen.insert(key) {
+                    issues.entry("duplicates").or_default().add(&entry);
+                }
+            }
+        }
+
+        tracing::info!(entries = entries);
+        tracing::info!(issues = issues.values().map(|i| i.count).sum::<usize>());
+        Ok(Report {
+            entries,
+            valid: issues.is_empty(),
+            issues,
+        })
+    })?;
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(report)
+}
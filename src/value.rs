@@ -0,0 +1,89 @@
+//! `MetricValue`: a metric's value, a count or a rate, and `MetricName`
+
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::Add;
+
+/// The built-in metrics' names are borrowed, so the nodes' maps don't allocate a key per
+/// metric. Names made at runtime, like `--metric` definitions and the `--pct-change`
+/// windows, are owned
+pub type MetricName = Cow<'static, str>;
+
+/// Counts stay integers, so they print as they always have. Rates like the per capita
+/// metrics are floats rather than scaled integers. Serialized as a plain JSON number
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(untagged)]
+pub enum MetricValue {
+    Int(i64),
+    Float(f64),
+}
+
+impl MetricValue {
+    /// Floats rounded to the nearest integer, for the metrics derived from counts
+    pub fn as_i64(self) -> i64 {
+        match self {
+            MetricValue::Int(n) => n,
+            MetricValue::Float(x) => x.round() as i64,
+        }
+    }
+
+    pub fn as_f64(self) -> f64 {
+        match self {
+            MetricValue::Int(n) => n as f64,
+            MetricValue::Float(x) => x,
+        }
+    }
+
+    pub fn is_float(self) -> bool {
+        matches!(self, MetricValue::Float(_))
+    }
+
+    /// Integers compare exactly, anything with a float by value with NaN last
+    pub fn total_cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (MetricValue::Int(a), MetricValue::Int(b)) => a.cmp(b),
+            (a, b) => a.as_f64().total_cmp(&b.as_f64()),
+        }
+    }
+}
+
+impl Default for MetricValue {
+    fn default() -> Self {
+        MetricValue::Int(0)
+    }
+}
+
+impl From<i64> for MetricValue {
+    fn from(n: i64) -> Self {
+        MetricValue::Int(n)
+    }
+}
+
+impl From<f64> for MetricValue {
+    fn from(x: f64) -> Self {
+        MetricValue::Float(x)
+    }
+}
+
+/// Summing a float into an integer gives a float
+impl Add for MetricValue {
+    type Output = MetricValue;
+
+    fn add(self, other: Self) -> Self {
+        match (self, other) {
+            (MetricValue::Int(a), MetricValue::Int(b)) => MetricValue::Int(a + b),
+            (a, b) => MetricValue::Float(a.as_f64() + b.as_f64()),
+        }
+    }
+}
+
+impl fmt::Display for MetricValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MetricValue::Int(n) => write!(f, "{}", n),
+            MetricValue::Float(x) => write!(f, "{}", x),
+        }
+    }
+}